@@ -0,0 +1,240 @@
+//! Compara las políticas de planificación del OrderCoordinator atendiendo los pedidos de
+//! `pedidos/*.jsonl`.
+//!
+//! Es una simulación de eventos discretos en tiempo simulado: cada archivo es una pantalla
+//! que carga un pedido cada `ARRIVAL_GAP_MS` y repite su archivo `ROUNDS` veces. Los robots
+//! tienen distintas velocidades, y un pedido espera a que los tokens de sus gustos den la
+//! vuelta hasta el robot antes de servirse.
+//!
+//! ```bash
+//! cargo bench --bench scheduling
+//! ```
+
+use helados_grido::robot_lib::{
+    icecream::{Container, IceCream},
+    robot::MAX_QUEUED_ORDERS,
+};
+use helados_grido::screen_lib::{
+    actors::OrderActorMessage,
+    scheduling::{policy_from_name, RingView, RobotCandidate, SchedulingPolicy},
+};
+use helados_grido::utils::messages::OrderJson;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+};
+
+const PEDIDOS: [&str; 3] = [
+    "pedidos/pedidos0.jsonl",
+    "pedidos/pedidos1.jsonl",
+    "pedidos/pedidos2.jsonl",
+];
+const ROUNDS: usize = 20;
+// Cada cuánto carga un pedido cada pantalla
+const ARRIVAL_GAP_MS: u64 = 3_000;
+// Cuánto tarda un token en pasar al siguiente robot
+const HOP_MS: u64 = 200;
+// Velocidad de cada robot del anillo, en porcentaje
+const ROBOT_SPEEDS: [u16; 3] = [100, 150, 75];
+
+struct Arrival {
+    at: u64,
+    order: OrderActorMessage,
+}
+
+fn load_arrivals() -> Vec<Arrival> {
+    let mut arrivals = Vec::new();
+    for (screen, path) in PEDIDOS.iter().enumerate() {
+        let content = fs::read_to_string(path).expect("no se pudo leer el archivo de pedidos");
+        let orders: Vec<OrderJson> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("pedido inválido"))
+            .collect();
+        for round in 0..ROUNDS {
+            for (i, order) in orders.iter().enumerate() {
+                let n = round * orders.len() + i;
+                arrivals.push(Arrival {
+                    at: n as u64 * ARRIVAL_GAP_MS,
+                    order: OrderActorMessage {
+                        screen_owner: screen as u8,
+                        order_id: (n % 256) as u8,
+                        items: order.items.clone(),
+                        container: order.container,
+                        priority: order.priority,
+                        // la simulación no tiene en cuenta los vencimientos
+                        deadline: u64::MAX,
+                    },
+                });
+            }
+        }
+    }
+    arrivals.sort_by_key(|arrival| arrival.at);
+    arrivals
+}
+
+struct SimRobot {
+    id: u8,
+    speed_percent: u16,
+    busy_until: u64,
+}
+
+#[derive(Default)]
+struct Stats {
+    turnaround: Vec<u64>,
+    cones: Vec<u64>,
+    makespan: u64,
+}
+
+// Sirve el pedido en el robot a partir de `start` y devuelve cuándo termina. Los tokens de
+// sus gustos tienen que llegar hasta el robot y después quedan ahí.
+fn serve(
+    order: &OrderActorMessage,
+    robot: &SimRobot,
+    start: u64,
+    tokens: &mut HashMap<IceCream, u8>,
+) -> u64 {
+    let nrobots = ROBOT_SPEEDS.len() as u64;
+    let wait = order
+        .items
+        .keys()
+        .map(|ice_cream| {
+            let at = *tokens.get(ice_cream).unwrap_or(&0) as u64;
+            (robot.id as u64 + nrobots - at) % nrobots * HOP_MS
+        })
+        .max()
+        .unwrap_or(0);
+    for ice_cream in order.items.keys() {
+        tokens.insert(*ice_cream, robot.id);
+    }
+    let scoop = order.total_grams() as u64 * 100 / robot.speed_percent as u64;
+    start + wait + scoop
+}
+
+fn simulate(policy: &dyn SchedulingPolicy, arrivals: &[Arrival]) -> Stats {
+    let mut robots: Vec<SimRobot> = ROBOT_SPEEDS
+        .iter()
+        .enumerate()
+        .map(|(id, speed_percent)| SimRobot {
+            id: id as u8,
+            speed_percent: *speed_percent,
+            busy_until: 0,
+        })
+        .collect();
+    let mut tokens: HashMap<IceCream, u8> = HashMap::new();
+    let mut pending: VecDeque<&Arrival> = VecDeque::new();
+    let mut stats = Stats::default();
+    let mut next_arrival = 0;
+    let mut now = 0;
+
+    while next_arrival < arrivals.len() || !pending.is_empty() {
+        while next_arrival < arrivals.len() && arrivals[next_arrival].at <= now {
+            pending.push_back(&arrivals[next_arrival]);
+            next_arrival += 1;
+        }
+
+        // como el coordinador: la política elige el pedido y el robot libre que lo prepara,
+        // y el robot completa su cola con los siguientes pedidos que elige la política
+        loop {
+            let free: Vec<RobotCandidate> = robots
+                .iter()
+                .filter(|robot| robot.busy_until <= now)
+                .map(|robot| RobotCandidate {
+                    id: robot.id,
+                    speed_percent: robot.speed_percent,
+                })
+                .collect();
+            if free.is_empty() || pending.is_empty() {
+                break;
+            }
+            let ring = RingView {
+                ring: robots.iter().map(|robot| robot.id).collect(),
+                sightings: tokens.clone(),
+            };
+            let orders: Vec<&OrderActorMessage> =
+                pending.iter().map(|arrival| &arrival.order).collect();
+            let first = policy.select_order(&orders);
+            let robot_id = match policy.select_robot(&pending[first].order, &free, &ring) {
+                Some(id) => id,
+                None => break,
+            };
+            let robot = &mut robots[robot_id as usize];
+
+            let mut batch = vec![pending.remove(first).expect("índice válido")];
+            while batch.len() < MAX_QUEUED_ORDERS && !pending.is_empty() {
+                let orders: Vec<&OrderActorMessage> =
+                    pending.iter().map(|arrival| &arrival.order).collect();
+                let next = policy.select_order(&orders);
+                batch.push(pending.remove(next).expect("índice válido"));
+            }
+
+            let mut t = now;
+            for arrival in batch {
+                t = serve(&arrival.order, robot, t, &mut tokens);
+                let turnaround = t - arrival.at;
+                stats.turnaround.push(turnaround);
+                if arrival.order.container == Container::Cone {
+                    stats.cones.push(turnaround);
+                }
+            }
+            robot.busy_until = t;
+            stats.makespan = stats.makespan.max(t);
+        }
+
+        // avanza hasta el próximo evento: un pedido nuevo o un robot que se libera
+        let next_free = robots
+            .iter()
+            .map(|robot| robot.busy_until)
+            .filter(|t| *t > now)
+            .min();
+        let next_at = arrivals.get(next_arrival).map(|arrival| arrival.at);
+        now = match (next_free, next_at) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) => a,
+            (None, Some(b)) => b,
+            (None, None) => break,
+        };
+    }
+    stats
+}
+
+fn mean(values: &[u64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().sum::<u64>() as f64 / values.len() as f64
+}
+
+fn p95(values: &[u64]) -> u64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    sorted
+        .get((sorted.len() * 95 / 100).min(sorted.len().saturating_sub(1)))
+        .copied()
+        .unwrap_or(0)
+}
+
+fn main() {
+    let arrivals = load_arrivals();
+    println!(
+        "{} pedidos, {} robots con velocidades {:?}",
+        arrivals.len(),
+        ROBOT_SPEEDS.len(),
+        ROBOT_SPEEDS
+    );
+    println!(
+        "{:<10} {:>14} {:>14} {:>16} {:>14}",
+        "politica", "espera media", "espera p95", "cucuruchos media", "total"
+    );
+    for name in ["fifo", "shortest", "priority", "ring"] {
+        let policy = policy_from_name(name).expect("política conocida");
+        let stats = simulate(policy.as_ref(), &arrivals);
+        println!(
+            "{:<10} {:>12.1}s {:>13.1}s {:>15.1}s {:>13.1}s",
+            policy.name(),
+            mean(&stats.turnaround) / 1000.0,
+            p95(&stats.turnaround) as f64 / 1000.0,
+            mean(&stats.cones) / 1000.0,
+            stats.makespan as f64 / 1000.0
+        );
+    }
+}
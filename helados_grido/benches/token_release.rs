@@ -0,0 +1,191 @@
+//! Compara el throughput del anillo de robots reteniendo el token mientras se sirve el
+//! helado contra liberarlo apenas se descuenta el stock.
+//!
+//! Es un modelo, no ejecuta el `OrderHandler` ni el `Scooper` del robot: cada robot simulado
+//! atiende un pedido a la vez y descuenta el stock como ellos, pero en un único hilo por
+//! robot y con canales en lugar de sockets UDP. El modo `ReleaseThenScoop` reproduce el
+//! reparto de trabajo entre el `OrderHandler` (descuenta y pasa el token) y el `Scooper`
+//! (sirve después), y `HoldWhileScooping` el comportamiento anterior, que ya no existe en el
+//! código. Los números sirven para comparar los dos enfoques, no para medir el robot real.
+//! Los tiempos de servido se escalan para que corra en pocos segundos.
+//!
+//! ```bash
+//! cargo bench --bench token_release
+//! ```
+
+use helados_grido::robot_lib::icecream::{Grams, IceCream};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const FLAVORS: [IceCream; 5] = [
+    IceCream::Chocolate,
+    IceCream::Vanilla,
+    IceCream::Strawberry,
+    IceCream::Lemon,
+    IceCream::DulceDeLeche,
+];
+const ORDERS: usize = 60;
+// Cuánto tarda servir un gramo de helado en la simulación
+const SCOOP_TIME_PER_GRAM: Duration = Duration::from_micros(20);
+// Latencia de pasar un token al siguiente robot
+const HOP_TIME: Duration = Duration::from_micros(200);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Mode {
+    HoldWhileScooping,
+    ReleaseThenScoop,
+}
+
+type SimOrder = HashMap<IceCream, Grams>;
+
+fn generate_orders(seed: u64) -> Vec<SimOrder> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..ORDERS)
+        .map(|_| {
+            let nflavors = rng.gen_range(1..=3);
+            let mut order = HashMap::new();
+            while order.len() < nflavors {
+                let flavor = FLAVORS[rng.gen_range(0..FLAVORS.len())];
+                // Vainilla y chocolate son los gustos populares
+                let flavor = if rng.gen_bool(0.5) {
+                    FLAVORS[rng.gen_range(0..2)]
+                } else {
+                    flavor
+                };
+                order.insert(flavor, rng.gen_range(1..=6) * 500);
+            }
+            order
+        })
+        .collect()
+}
+
+fn scoop_time(amount: Grams) -> Duration {
+    SCOOP_TIME_PER_GRAM * amount
+}
+
+fn run_robot(
+    mode: Mode,
+    inbox: Receiver<(IceCream, Grams)>,
+    next: Sender<(IceCream, Grams)>,
+    queue: Arc<Mutex<VecDeque<SimOrder>>>,
+    completed: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+) {
+    let (scoop_tx, scoop_rx) = mpsc::channel::<(Grams, bool)>();
+    let scooper_completed = completed.clone();
+    let scooper = thread::spawn(move || {
+        for (amount, last) in scoop_rx {
+            thread::sleep(scoop_time(amount));
+            if last {
+                scooper_completed.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    });
+
+    let mut current: Option<SimOrder> = None;
+    while !stop.load(Ordering::SeqCst) {
+        if current.is_none() {
+            current = queue.lock().expect("lock de la cola").pop_front();
+        }
+        let (flavor, mut stock) = match inbox.recv_timeout(Duration::from_millis(50)) {
+            Ok(token) => token,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if let Some(order) = current.as_mut() {
+            if let Some(amount) = order.remove(&flavor) {
+                let last = order.is_empty();
+                stock -= amount;
+                match mode {
+                    Mode::HoldWhileScooping => {
+                        thread::sleep(scoop_time(amount));
+                        if last {
+                            completed.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                    Mode::ReleaseThenScoop => {
+                        let _ = scoop_tx.send((amount, last));
+                    }
+                }
+                if last {
+                    current = None;
+                }
+            }
+        }
+        thread::sleep(HOP_TIME);
+        if next.send((flavor, stock)).is_err() {
+            break;
+        }
+    }
+    drop(scoop_tx);
+    let _ = scooper.join();
+}
+
+fn simulate(mode: Mode, nrobots: usize, orders: &[SimOrder]) -> Duration {
+    let queue = Arc::new(Mutex::new(orders.iter().cloned().collect::<VecDeque<_>>()));
+    let completed = Arc::new(AtomicUsize::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..nrobots)
+        .map(|_| mpsc::channel::<(IceCream, Grams)>())
+        .unzip();
+
+    let start = Instant::now();
+    let handles: Vec<_> = receivers
+        .into_iter()
+        .enumerate()
+        .map(|(i, inbox)| {
+            let next = senders[(i + 1) % nrobots].clone();
+            let queue = queue.clone();
+            let completed = completed.clone();
+            let stop = stop.clone();
+            thread::spawn(move || run_robot(mode, inbox, next, queue, completed, stop))
+        })
+        .collect();
+
+    for flavor in FLAVORS {
+        senders[0]
+            .send((flavor, Grams::MAX))
+            .expect("el robot 0 debe estar escuchando");
+    }
+    drop(senders);
+
+    while completed.load(Ordering::SeqCst) < orders.len() {
+        thread::sleep(Duration::from_millis(5));
+    }
+    let elapsed = start.elapsed();
+    stop.store(true, Ordering::SeqCst);
+    for handle in handles {
+        let _ = handle.join();
+    }
+    elapsed
+}
+
+fn main() {
+    let orders = generate_orders(42);
+    println!(
+        "{:<8} {:<20} {:>12} {:>14}",
+        "robots", "modo", "tiempo", "pedidos/seg"
+    );
+    for nrobots in [4, 6, 8] {
+        for mode in [Mode::HoldWhileScooping, Mode::ReleaseThenScoop] {
+            let elapsed = simulate(mode, nrobots, &orders);
+            println!(
+                "{:<8} {:<20} {:>12.2?} {:>14.2}",
+                nrobots,
+                format!("{:?}", mode),
+                elapsed,
+                orders.len() as f64 / elapsed.as_secs_f64()
+            );
+        }
+    }
+}
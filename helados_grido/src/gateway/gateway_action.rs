@@ -1,14 +1,18 @@
 use std::convert::{TryFrom, TryInto};
 
-use crate::utils::errors::ParseError;
+use crate::utils::{errors::ParseError, messages::Priority};
+
+/// Montos de dinero en centavos. Nunca se representan con punto flotante.
+pub type Cents = u64;
 
 #[derive(Debug, Clone)]
 pub enum GatewayAction {
     Capture {
         order_id: u8,
         card_number: u32,
-        amount: f64,
+        amount: Cents,
         owner_id: u8,
+        priority: Priority,
     },
     Commit {
         order_id: u8,
@@ -20,6 +24,16 @@ pub enum GatewayAction {
     },
 }
 
+impl GatewayAction {
+    pub fn order_id(&self) -> u8 {
+        match self {
+            GatewayAction::Capture { order_id, .. }
+            | GatewayAction::Commit { order_id, .. }
+            | GatewayAction::Abort { order_id, .. } => *order_id,
+        }
+    }
+}
+
 impl TryFrom<Vec<u8>> for GatewayAction {
     type Error = ParseError;
 
@@ -38,7 +52,7 @@ impl TryFrom<&[u8]> for GatewayAction {
 
         match bytes[0] {
             0 => {
-                if bytes.len() < 15 {
+                if bytes.len() < 16 {
                     return Err(ParseError::IncompleteCapturePacket);
                 }
 
@@ -47,7 +61,7 @@ impl TryFrom<&[u8]> for GatewayAction {
                         .try_into()
                         .map_err(|_| ParseError::ConversionError)?,
                 );
-                let amount = f64::from_be_bytes(
+                let amount = Cents::from_be_bytes(
                     bytes[6..14]
                         .try_into()
                         .map_err(|_| ParseError::ConversionError)?,
@@ -58,6 +72,7 @@ impl TryFrom<&[u8]> for GatewayAction {
                     card_number,
                     amount,
                     owner_id: bytes[14],
+                    priority: Priority::try_from(bytes[15])?,
                 })
             }
             1 => {
@@ -93,6 +108,7 @@ impl From<GatewayAction> for Vec<u8> {
                 card_number,
                 amount,
                 owner_id,
+                priority,
             } => {
                 let mut bytes = Vec::new();
                 bytes.push(0);
@@ -100,6 +116,7 @@ impl From<GatewayAction> for Vec<u8> {
                 bytes.extend_from_slice(&card_number.to_be_bytes());
                 bytes.extend_from_slice(&amount.to_be_bytes());
                 bytes.push(owner_id);
+                bytes.push(priority as u8);
                 bytes
             }
             GatewayAction::Commit { order_id, owner_id } => {
@@ -8,8 +8,8 @@ use std::{
 use rand::Rng;
 use tokio::task;
 
-use crate::gateway::gateway_action::GatewayAction;
-use crate::utils::messages::GatewayResponse;
+use crate::gateway::gateway_action::{Cents, GatewayAction};
+use crate::utils::messages::{GatewayReply, GatewayResponse, Priority};
 
 pub struct PaymentGateway {
     socket: UdpSocket,
@@ -40,18 +40,21 @@ impl PaymentGateway {
             task::spawn(async move {
                 match GatewayAction::try_from(msg.clone()) {
                     Ok(action) => {
+                        let order_id = action.order_id();
                         let result = match action {
                             GatewayAction::Capture {
                                 order_id,
                                 card_number,
                                 amount,
                                 owner_id,
+                                priority,
                             } => clone
                                 .capture(PaymentInformation {
                                     order_id,
                                     card_number,
                                     amount,
                                     owner_id,
+                                    priority,
                                 })
                                 .await
                                 .map(|_| PaymentOk::Capture(CapturePaymentOk::Ok))
@@ -70,7 +73,7 @@ impl PaymentGateway {
 
                         match result {
                             Ok(PaymentOk::Capture(CapturePaymentOk::Ok)) => {
-                                let res = GatewayResponse::Acknowledge;
+                                let res = GatewayReply::new(order_id, GatewayResponse::Acknowledge);
                                 let res: Vec<u8> = res.into();
 
                                 if let Err(e) = clone.socket.send_to(&res, src_clone) {
@@ -84,7 +87,8 @@ impl PaymentGateway {
                                 // no envío nada
                             }
                             Err(PaymentError::Capture(CapturePaymentError::RejectedCard)) => {
-                                let res = GatewayResponse::RejectedCard;
+                                let res =
+                                    GatewayReply::new(order_id, GatewayResponse::RejectedCard);
                                 let res: Vec<u8> = res.into();
                                 if let Err(e) = clone.socket.send_to(&res, src_clone) {
                                     error!("No se pudo enviar el rechazo de tarjeta: {}", e);
@@ -93,7 +97,8 @@ impl PaymentGateway {
                             Err(PaymentError::Capture(
                                 CapturePaymentError::DuplicatedPendingOrder,
                             )) => {
-                                let res = GatewayResponse::DuplicatedOrder;
+                                let res =
+                                    GatewayReply::new(order_id, GatewayResponse::DuplicatedOrder);
                                 let res: Vec<u8> = res.into();
 
                                 if let Err(e) = clone.socket.send_to(&res, src_clone) {
@@ -102,7 +107,10 @@ impl PaymentGateway {
                             }
                             Err(PaymentError::Abort(AbortPaymentError::NoSuchPendingPayment)) => {}
                             Err(PaymentError::Commit(CommitPaymentError::NoSuchPendingPayment)) => {
-                                let res = GatewayResponse::NoSuchPendingPayment;
+                                let res = GatewayReply::new(
+                                    order_id,
+                                    GatewayResponse::NoSuchPendingPayment,
+                                );
                                 let res: Vec<u8> = res.into();
 
                                 if let Err(e) = clone.socket.send_to(&res, src_clone) {
@@ -150,13 +158,13 @@ impl PaymentGateway {
         let info_clone = info.clone();
 
         if fail {
-            info!("[GATEWAY] Error al capturar pago de la orden: (SCREEN {} - ID {}), de monto $ {}. Razón: tarjeta {} rechazada.", info_clone.owner_id, info_clone.order_id, info_clone.amount, info_clone.card_number);
+            info!("[GATEWAY] Error al capturar pago de la orden: (SCREEN {} - ID {}), de monto $ {}, prioridad {:?}. Razón: tarjeta {} rechazada.", info_clone.owner_id, info_clone.order_id, format_cents(info_clone.amount), info_clone.priority, info_clone.card_number);
             return Err(CapturePaymentError::RejectedCard);
         }
 
         pending_payments.push(info);
 
-        info!("[GATEWAY] Pago capturado correctamente (SCREEN {} - ID {}), de monto $ {}, a la tarjeta {}.", info_clone.owner_id, info_clone.order_id, info_clone.amount, info_clone.card_number);
+        info!("[GATEWAY] Pago capturado correctamente (SCREEN {} - ID {}), de monto $ {}, prioridad {:?}, a la tarjeta {}.", info_clone.owner_id, info_clone.order_id, format_cents(info_clone.amount), info_clone.priority, info_clone.card_number);
 
         Ok(CapturePaymentOk::Ok)
     }
@@ -171,22 +179,28 @@ impl PaymentGateway {
             .lock()
             .map_err(|_| CommitPaymentError::MutexLockFailed)?;
 
-        if !pending_payments
+        let payment = match pending_payments
             .iter()
-            .any(|p| p.order_id == order_id && p.owner_id == owner_id)
+            .find(|p| p.order_id == order_id && p.owner_id == owner_id)
         {
-            info!(
-                "[GATEWAY] Error: no se pudo confirmar el pago (SCREEN {} - ID {}). Razón: no hay un pago pendiente asociado a la orden.",
-                owner_id, order_id
-            );
-            return Err(CommitPaymentError::NoSuchPendingPayment);
-        }
+            Some(payment) => payment.clone(),
+            None => {
+                info!(
+                    "[GATEWAY] Error: no se pudo confirmar el pago (SCREEN {} - ID {}). Razón: no hay un pago pendiente asociado a la orden.",
+                    owner_id, order_id
+                );
+                return Err(CommitPaymentError::NoSuchPendingPayment);
+            }
+        };
 
         pending_payments.retain(|p| p.order_id != order_id || p.owner_id != owner_id);
 
         info!(
-            "[GATEWAY] Pago confirmado exitosamente (SCREEN {} - ID {}).",
-            owner_id, order_id
+            "[GATEWAY] Pago confirmado exitosamente (SCREEN {} - ID {}), de monto $ {}, prioridad {:?}.",
+            owner_id,
+            order_id,
+            format_cents(payment.amount),
+            payment.priority
         );
 
         Ok(CommitPaymentOk::Ok)
@@ -277,8 +291,14 @@ enum AbortPaymentError {
 struct PaymentInformation {
     order_id: u8,
     card_number: u32,
-    amount: f64,
+    amount: Cents,
     owner_id: u8,
+    priority: Priority,
+}
+
+// Formatea un monto en centavos como pesos, ej: 100050 -> "1000.50"
+fn format_cents(amount: Cents) -> String {
+    format!("{}.{:02}", amount / 100, amount % 100)
 }
 
 // #[cfg(test)]
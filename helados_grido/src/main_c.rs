@@ -0,0 +1,124 @@
+use helados_grido::utils::{
+    addresses::id_to_addr_robot,
+    messages::{DrainReply, Messages, StatusReply},
+};
+use std::{
+    env,
+    net::{Ipv4Addr, UdpSocket},
+    time::Duration,
+};
+
+fn print_status(status: &StatusReply) {
+    println!("Robot {}", status.owner);
+    println!(
+        "  En funcionamiento hace {}s, {} pedido(s) completado(s)",
+        status.uptime_secs, status.orders_completed
+    );
+    println!(
+        "  Anillo: {:?} (anterior: {}, siguiente: {})",
+        status.ring_path, status.prev, status.next
+    );
+    println!("  Stock conocido:");
+    for (ice_cream, amount, grade) in status.stock.iter() {
+        println!(
+            "    {:<14} {:>6} g  (grado {:?})",
+            format!("{:?}", ice_cream),
+            amount,
+            grade
+        );
+    }
+    match status.orders.split_first() {
+        Some((current, queue)) => {
+            println!(
+                "  Pedido actual: ({}, {}) {:?} en {:?}, prioridad {:?}{}",
+                current.screen_id,
+                current.order_id,
+                current.items,
+                current.container,
+                current.priority,
+                if current.is_expired() {
+                    ", vencido"
+                } else {
+                    ""
+                }
+            );
+            println!("  En cola: {}", queue.len());
+            for order in queue {
+                println!(
+                    "    ({}, {}) {:?} en {:?}, prioridad {:?}",
+                    order.screen_id, order.order_id, order.items, order.container, order.priority
+                );
+            }
+        }
+        None => println!("  Sin pedidos"),
+    }
+}
+
+fn query_status(socket: &UdpSocket, id: u8) {
+    if let Err(error) = socket.send_to(
+        &[Messages::StatusRequest as u8],
+        id_to_addr_robot(id as u16),
+    ) {
+        eprintln!("ERROR: {}", error);
+        return;
+    }
+
+    let mut buf = [0; 1024];
+    match socket.recv_from(&mut buf) {
+        Ok((size, _)) if size > 0 && buf[0] == Messages::StatusReply as u8 => {
+            match StatusReply::from_bytes(&buf[1..size]) {
+                Ok(status) => print_status(&status),
+                Err(error) => eprintln!("ERROR: respuesta inválida: {:?}", error),
+            }
+        }
+        Ok(_) => eprintln!("ERROR: el robot {} respondió un mensaje inesperado", id),
+        Err(_) => eprintln!("ERROR: el robot {} no respondió", id),
+    }
+}
+
+fn drain(socket: &UdpSocket, id: u8) {
+    if let Err(error) = socket.send_to(&[Messages::Drain as u8], id_to_addr_robot(id as u16)) {
+        eprintln!("ERROR: {}", error);
+        return;
+    }
+
+    let mut buf = [0; 1024];
+    match socket.recv_from(&mut buf) {
+        Ok((size, _)) if size > 0 && buf[0] == Messages::DrainReply as u8 => {
+            match DrainReply::from_bytes(&buf[1..size]) {
+                Ok(reply) if reply.accepted => println!(
+                    "El robot {} va a terminar sus pedidos y salir del anillo",
+                    id
+                ),
+                Ok(_) => eprintln!(
+                    "ERROR: el robot {} no conoce a la pantalla líder todavía, no puede drenarse",
+                    id
+                ),
+                Err(error) => eprintln!("ERROR: respuesta inválida: {:?}", error),
+            }
+        }
+        Ok(_) => eprintln!("ERROR: el robot {} respondió un mensaje inesperado", id),
+        Err(_) => eprintln!("ERROR: el robot {} no respondió", id),
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 || (args[2] != "status" && args[2] != "drain") {
+        println!("Uso: {} <id_robot> <status|drain>", args[0]);
+        std::process::exit(1);
+    }
+    let id: u8 = args[1].parse().expect("El ID no es un número válido");
+
+    let socket =
+        UdpSocket::bind((Ipv4Addr::new(127, 0, 0, 1), 0)).expect("No se pudo abrir el socket");
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("No se pudo setear el timeout");
+
+    match args[2].as_str() {
+        "drain" => drain(&socket, id),
+        _ => query_status(&socket, id),
+    }
+}
@@ -1,17 +1,59 @@
+use helados_grido::robot_lib::{capabilities::Capabilities, icecream::Container};
 use helados_grido::utils::addresses::id_to_addr_robot;
 use std::{env, net::Ipv4Addr};
 
 use helados_grido::robot_lib::robot::Robot;
 
+fn usage(program: &str) -> ! {
+    println!(
+        "Uso: {} <id> <path separado por ':' iniciando por <id>> [--containers cup,cone,kilo_pot] [--max-grams <gramos>] [--speed <factor>]",
+        program
+    );
+    std::process::exit(1);
+}
+
+fn parse_container(name: &str) -> Option<Container> {
+    match name {
+        "cup" => Some(Container::Cup),
+        "cone" => Some(Container::Cone),
+        "kilo_pot" => Some(Container::KiloPot),
+        _ => None,
+    }
+}
+
+// Las capacidades que no se indican quedan como las de un robot estándar
+fn parse_capabilities(program: &str, args: &[String]) -> Capabilities {
+    let mut capabilities = Capabilities::default();
+    for flag in args.chunks(2) {
+        let value = match flag.get(1) {
+            Some(value) => value,
+            None => usage(program),
+        };
+        match flag[0].as_str() {
+            "--containers" => {
+                capabilities.containers = value
+                    .split(',')
+                    .map(|name| parse_container(name).unwrap_or_else(|| usage(program)))
+                    .collect();
+            }
+            "--max-grams" => {
+                capabilities.max_grams = value.parse().unwrap_or_else(|_| usage(program));
+            }
+            "--speed" => {
+                let factor: f32 = value.parse().unwrap_or_else(|_| usage(program));
+                capabilities.speed_percent = (factor * 100.0).round() as u16;
+            }
+            _ => usage(program),
+        }
+    }
+    capabilities
+}
+
 fn main() {
     env_logger::init();
     let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        println!(
-            "Uso: {} <id> <path separado por ':' iniciando por <id>>",
-            args[0]
-        );
-        std::process::exit(1);
+    if args.len() < 3 {
+        usage(&args[0]);
     }
     let id: u8 = args[1].parse::<u8>().unwrap();
     let my_addr: (Ipv4Addr, u16) = id_to_addr_robot(id as u16);
@@ -19,8 +61,9 @@ fn main() {
         .split(':')
         .map(|s| s.parse().expect("No es un número válido"))
         .collect();
+    let capabilities = parse_capabilities(&args[0], &args[3..]);
 
-    let mut robot = match Robot::new(id, peers, my_addr) {
+    let mut robot = match Robot::new(id, peers, my_addr, capabilities) {
         Ok(robot) => robot,
         Err(error) => {
             eprintln!("ERROR: {}", error);
@@ -1,6 +1,43 @@
-use helados_grido::screen_lib::screen::Screen;
+use helados_grido::screen_lib::{
+    order_source,
+    screen::Screen,
+    validation::{validate_file, RejectionReport},
+};
 use std::env;
 
+// Revisa un archivo de pedidos sin levantar una pantalla. Termina con error si hay pedidos
+// rechazados, para poder usarlo antes de cargar un archivo nuevo.
+fn validate(path: &str, report_path: Option<&String>) {
+    let summary = match validate_file(path) {
+        Ok(summary) => summary,
+        Err(error) => {
+            eprintln!("ERROR: No pude leer {}: {}", path, error);
+            std::process::exit(2);
+        }
+    };
+    let mut report = report_path.map(RejectionReport::new);
+    for rejected in &summary.rejected {
+        println!(
+            "{}:{}: {} ({})",
+            path, rejected.line, rejected.reason, rejected.content
+        );
+        if let Some(report) = report.as_mut() {
+            if let Err(error) = report.record(path, rejected) {
+                eprintln!("ERROR: No pude escribir el reporte: {}", error);
+            }
+        }
+    }
+    println!(
+        "{}: {} pedidos validos, {} rechazados",
+        path,
+        summary.accepted,
+        summary.rejected.len()
+    );
+    if !summary.rejected.is_empty() {
+        std::process::exit(1);
+    }
+}
+
 fn main() {
     env_logger::init();
     log::info!("Starting Screen...");
@@ -8,10 +45,20 @@ fn main() {
     // Obtener los argumentos de la línea de comandos
     let args: Vec<String> = env::args().collect();
 
+    if args.len() >= 3 && args.len() <= 4 && args[1] == "validate" {
+        validate(&args[2], args.get(3));
+        return;
+    }
+
     // Verificar que se hayan pasado suficientes argumentos
     if args.len() != 5 {
         println!(
-            "Uso: {} <id> <numeros_separados_por_dos_puntos> <ruta_al_archivo> <cant_robots>",
+            "Uso: {} <id> <numeros_separados_por_dos_puntos> <fuente_de_pedidos> <cant_robots>",
+            args[0]
+        );
+        println!("La fuente de pedidos es un archivo, follow:<archivo>, stdin o http[:<puerto>]");
+        println!(
+            "Para revisar un archivo de pedidos: {} validate <archivo> [<reporte>]",
             args[0]
         );
         std::process::exit(1);
@@ -26,15 +73,21 @@ fn main() {
         .map(|s| s.parse().expect("No es un número válido"))
         .collect();
 
-    // La ruta del archivo es el tercer argumento
-    let path = args[3].clone();
+    // La fuente de pedidos es el tercer argumento
+    let source = match order_source::from_spec(&args[3], id) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("ERROR: {}", error);
+            return;
+        }
+    };
 
     // La cantidad de robots es el cuarto argumento
     let nrobots: u8 = args[4]
         .parse()
         .expect("La cantidad de robots no es un número válido");
 
-    let mut screen = match Screen::new(id, peers, path, nrobots) {
+    let mut screen = match Screen::new(id, peers, source, nrobots) {
         Ok(screen) => screen,
         Err(error) => {
             eprintln!("ERROR: {}", error);
@@ -0,0 +1,60 @@
+use crate::{
+    robot_lib::icecream::{Container, Grams},
+    utils::errors::ParseError,
+};
+
+/// Velocidad de un robot estándar, en porcentaje.
+pub const DEFAULT_SPEED_PERCENT: u16 = 100;
+
+/// Lo que puede preparar un robot: los envases que maneja, el tamaño máximo de pedido y
+/// qué tan rápido sirve respecto de un robot estándar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub containers: Vec<Container>,
+    pub max_grams: Grams,
+    pub speed_percent: u16,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities {
+            containers: vec![Container::Cup, Container::Cone, Container::KiloPot],
+            max_grams: Grams::MAX,
+            speed_percent: DEFAULT_SPEED_PERCENT,
+        }
+    }
+}
+
+impl Capabilities {
+    pub fn can_handle(&self, container: Container, grams: Grams) -> bool {
+        self.containers.contains(&container) && grams <= self.max_grams
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf_msg = vec![self.containers.len() as u8];
+        buf_msg.extend(self.containers.iter().map(|container| *container as u8));
+        buf_msg.extend_from_slice(&self.max_grams.to_be_bytes());
+        buf_msg.extend_from_slice(&self.speed_percent.to_be_bytes());
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<Capabilities, ParseError> {
+        let len = buffer[0] as usize;
+        let containers = buffer[1..1 + len]
+            .iter()
+            .map(|container| Container::try_from(*container))
+            .collect::<Result<Vec<_>, _>>()?;
+        let max_grams = Grams::from_be_bytes(buffer[1 + len..5 + len].try_into()?);
+        let speed_percent = u16::from_be_bytes(buffer[5 + len..7 + len].try_into()?);
+        Ok(Capabilities {
+            containers,
+            max_grams,
+            speed_percent,
+        })
+    }
+
+    // Largo en bytes de las capacidades serializadas
+    pub fn encoded_len(&self) -> usize {
+        7 + self.containers.len()
+    }
+}
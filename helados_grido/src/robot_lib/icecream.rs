@@ -25,14 +25,43 @@ impl TryFrom<u8> for IceCream {
     }
 }
 
+/// Envase en el que se sirve un pedido.
+#[derive(Eq, Hash, PartialEq, Clone, Debug, Copy, Serialize, Deserialize, Default)]
+pub enum Container {
+    #[default]
+    Cup = 0,
+    Cone = 1,
+    KiloPot = 2,
+}
+
+impl TryFrom<u8> for Container {
+    type Error = crate::utils::errors::ParseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Container::Cup),
+            1 => Ok(Container::Cone),
+            2 => Ok(Container::KiloPot),
+            _ => Err(crate::utils::errors::ParseError::ConversionError),
+        }
+    }
+}
+
+/// Cantidad de helado en gramos. Se usan enteros para que el stock no acumule errores de
+/// redondeo después de muchos descuentos.
+pub type Grams = u32;
+
+/// Cantidad de helado con la que arranca cada contenedor.
+pub const BUCKET_GRAMS: Grams = 20_000;
+
 #[derive(Clone, Debug)]
 pub struct Bucket {
     pub ice_cream: IceCream,
-    pub amount: f32,
+    pub amount: Grams,
 }
 
 impl Bucket {
-    pub fn new(ice_cream: IceCream, amount: f32) -> Self {
+    pub fn new(ice_cream: IceCream, amount: Grams) -> Self {
         Bucket { ice_cream, amount }
     }
 
@@ -49,7 +78,7 @@ impl Bucket {
 
     pub fn from_bytes(buffer: &[u8]) -> Result<Bucket, ParseError> {
         let ice_cream = IceCream::try_from(buffer[0])?;
-        let amount = f32::from_be_bytes(buffer[1..5].try_into()?);
+        let amount = Grams::from_be_bytes(buffer[1..5].try_into()?);
         Ok(Bucket::new(ice_cream, amount))
     }
 }
@@ -1,2 +1,4 @@
+pub mod capabilities;
 pub mod icecream;
 pub mod robot;
+pub mod snapshot;
@@ -1,33 +1,65 @@
-use log::{debug, info};
+use log::{debug, error, info};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     io::ErrorKind,
     net::{Ipv4Addr, SocketAddr, UdpSocket},
     sync::{
         mpsc::{self, Receiver, Sender},
         Arc, Condvar, Mutex, RwLock,
     },
-    thread,
-    time::Duration,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use crate::{
-    robot_lib::icecream::{Bucket, IceCream},
+    robot_lib::{
+        capabilities::Capabilities,
+        icecream::{Bucket, Grams, IceCream, BUCKET_GRAMS},
+        snapshot::{Snapshotter, TokenSnapshot},
+    },
     utils::{
-        addresses::{id_to_addr_resolver, id_to_addr_robot},
+        addresses::{addr_to_id_robot, id_to_addr_leader, id_to_addr_resolver, id_to_addr_robot},
         errors::RobotError,
+        failure_detector::FailureDetector,
         messages::{
-            Alive, Grade, Handshake, KeepAlive, Messages, Next, Order, OrderResult, OrderStatus,
-            RobotDead, RobotOrder, RobotWithOrder, Token,
+            AbortReason, Alive, CancelOrder, DrainReply, Grade, Handshake, KeepAlive, Leave,
+            Messages, NewLeader, Next, Order, OrderProgress, OrderResult, OrderStatus,
+            RobotAvailable, RobotDead, RobotDraining, RobotOrder, RobotQueueStatus, RobotWithOrder,
+            SnapshotCollect, StaleTerm, StatusReply, Term, Token,
         },
     },
 };
 
+/// Cantidad máxima de pedidos que un robot acepta tener encolados a la vez.
+pub const MAX_QUEUED_ORDERS: usize = 3;
+
+/// Cantidad de resultados de pedidos ya preparados que recuerda el robot, para responder
+/// sin volver a prepararlos si se los reenvían.
+pub const EXECUTED_ORDERS_CAPACITY: usize = 64;
+
+/// Tiempo sin recibir mensajes que espera un robot que salió del anillo antes de terminar,
+/// reenviando los tokens que todavía le lleguen.
+const LEAVE_GRACE_PERIOD: Duration = Duration::from_secs(3);
+
+/// Cada cuánto un robot que sale del anillo le reenvía el LEAVE a su anterior mientras no se
+/// lo confirme, y cuántas veces lo intenta antes de dejar que el anillo lo dé por caído.
+const LEAVE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const LEAVE_ATTEMPTS: u32 = 10;
+
+/// Cada cuánto se reenvía el INIT mientras se arma el anillo.
+const INIT_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Tiempo sin mensajes tras el cual el robot le pregunta a su anterior si sigue vivo, hasta
+/// que su detector de fallas aprende cada cuánto le llegan.
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Veces que el robot 0 reintenta juntar los snapshots del anillo antes de arrancar solo con
+/// el suyo.
+const SNAPSHOT_COLLECT_RETRIES: u8 = 3;
+
 fn init_protocol(robot: &Robot) -> Result<(), RobotError> {
     let next_lock = robot.next.read()?;
-    robot
-        .socket
-        .set_read_timeout(Some(Duration::from_secs(10)))?;
+    robot.socket.set_read_timeout(Some(INIT_RETRY_INTERVAL))?;
     let mut ack_recv = false;
     let mut init_recv = false;
     loop {
@@ -64,9 +96,7 @@ fn init_protocol(robot: &Robot) -> Result<(), RobotError> {
             Err(error) => return Err(error.into()),
         }
         if ack_recv && init_recv {
-            robot
-                .socket
-                .set_read_timeout(Some(Duration::from_secs(10)))?;
+            robot.socket.set_read_timeout(Some(INIT_RETRY_INTERVAL))?;
             break;
         }
     }
@@ -94,9 +124,7 @@ fn init_comunication(robot: &Robot) -> Result<(), RobotError> {
                     );
                     robot.socket.send_to(&[Messages::Ack as u8], robot.prev)?;
                     robot.socket.send_to(&[Messages::Init as u8], *next_lock)?;
-                    robot
-                        .socket
-                        .set_read_timeout(Some(Duration::from_secs(10)))?;
+                    robot.socket.set_read_timeout(Some(INIT_RETRY_INTERVAL))?;
                 }
             }
             Err(ref error) if error.kind() == ErrorKind::WouldBlock => {
@@ -112,9 +140,54 @@ fn init_comunication(robot: &Robot) -> Result<(), RobotError> {
     Ok(())
 }
 
+// Hace circular un SNAPSHOTCOLLECT por el anillo para que todos aporten el último estado
+// que guardaron de los tokens, y se queda con el más fresco de cada gusto.
+fn collect_snapshots(robot: &Robot) -> Result<TokenSnapshot, RobotError> {
+    let next = *robot.next.read()?;
+    let mut snapshot = robot.snapshot.lock()?.clone();
+    if next == robot.addr {
+        return Ok(snapshot);
+    }
+    let collect = SnapshotCollect::new(robot.id, snapshot.entries());
+    for _ in 0..SNAPSHOT_COLLECT_RETRIES {
+        info!(
+            "[RobotReceiver {}] Junto los snapshots del anillo antes de enviar los tokens",
+            robot.id
+        );
+        robot.socket.send_to(&collect.as_bytes(), next)?;
+        loop {
+            let mut buf = [0; 100];
+            let (_, from) = match robot.socket.recv_from(&mut buf) {
+                Ok(result) => result,
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error.into()),
+            };
+            match Messages::try_from(buf[0])? {
+                Messages::SnapshotCollect => {
+                    let collected = SnapshotCollect::from_bytes(&buf[1..])?;
+                    snapshot.merge(&collected.stock);
+                    return Ok(snapshot);
+                }
+                Messages::KeepAlive => {
+                    robot
+                        .socket
+                        .send_to(&Alive::new(robot.id).as_bytes(), from)?;
+                }
+                _ => {}
+            }
+        }
+    }
+    info!(
+        "[RobotReceiver {}] No volvieron los snapshots, uso solo el mio",
+        robot.id
+    );
+    Ok(snapshot)
+}
+
 fn start_protocol(robot: &Robot) -> Result<(), RobotError> {
     if robot.id == 0 {
         init_protocol(robot)?;
+        let snapshot = collect_snapshots(robot)?;
         info!(
             "[RobotReceiver {}] Soy el minimo, envio los tokens",
             robot.id
@@ -122,34 +195,37 @@ fn start_protocol(robot: &Robot) -> Result<(), RobotError> {
         let next_lock = robot.next.read()?;
 
         // ========= SE HACE EL ENVIO DE TOKENS INICIAL ========= //
-        let chlte_token = Token {
-            owner: robot.id,
-            bucket: Bucket::new(IceCream::Chocolate, 20.0),
-        };
-        let vnla_token = Token {
+        // Cada contenedor arranca con el stock del snapshot más fresco, o lleno si nadie
+        // guardó ese gusto.
+        let flavors = [
+            IceCream::Chocolate,
+            IceCream::Vanilla,
+            IceCream::DulceDeLeche,
+            IceCream::Strawberry,
+            IceCream::Lemon,
+        ];
+        let tokens = flavors.map(|ice_cream| Token {
             owner: robot.id,
-            bucket: Bucket::new(IceCream::Vanilla, 20.0),
-        };
-        let ddl_token = Token {
-            owner: robot.id,
-            bucket: Bucket::new(IceCream::DulceDeLeche, 20.0),
-        };
-        let sby_token = Token {
-            owner: robot.id,
-            bucket: Bucket::new(IceCream::Strawberry, 20.0),
-        };
-        let lem_token = Token {
-            owner: robot.id,
-            bucket: Bucket::new(IceCream::Lemon, 20.0),
-        };
+            bucket: Bucket::new(
+                ice_cream,
+                snapshot
+                    .flavors
+                    .get(&ice_cream)
+                    .map_or(BUCKET_GRAMS, |flavor| flavor.amount),
+            ),
+        });
         // ===================================================== //
 
-        let tokens = [chlte_token, vnla_token, ddl_token, sby_token, lem_token];
         let mut token_status_lock = robot.token_status.lock()?;
         for token in tokens {
-            if let Some((_, grade)) = token_status_lock.get_mut(&token.bucket.ice_cream) {
+            if let Some((amount, grade)) = token_status_lock.get_mut(&token.bucket.ice_cream) {
+                *amount = token.bucket.amount;
                 *grade = grade.next();
             }
+            info!(
+                "[RobotReceiver {}] Envio el token de {:?} con {} g",
+                robot.id, token.bucket.ice_cream, token.bucket.amount
+            );
             robot.socket.send_to(&token.as_bytes(), *next_lock)?;
             thread::sleep(Duration::from_secs(1))
         }
@@ -159,116 +235,159 @@ fn start_protocol(robot: &Robot) -> Result<(), RobotError> {
     Ok(())
 }
 
+/// Tarea que el OrderHandler le encarga al Scooper. Las tareas se resuelven en orden,
+/// por lo que la entrega de un pedido siempre ocurre después de servir todos sus gustos.
+#[derive(Debug)]
+pub enum ScoopTask {
+    Scoop { ice_cream: IceCream, amount: Grams },
+    Deliver { result: OrderResult, deadline: u64 },
+}
+
+/// Gustos que el OrderHandler ya descontó de cada pedido que empezó a servir.
+pub type ServedFlavors = HashMap<(u8, u8), Vec<(IceCream, Grams)>>;
+
+/// Resultado de un pedido que el robot ya preparó. Los ids de pedido se reutilizan, así que
+/// se guarda también el deadline para no confundirlo con un pedido nuevo del mismo id.
+#[derive(Debug, Clone, Copy)]
+pub struct ExecutedOrder {
+    pub result: OrderResult,
+    pub deadline: u64,
+}
+
+impl ExecutedOrder {
+    fn is_for(&self, order: &Order) -> bool {
+        (self.result.owner_id, self.result.order_id) == (order.screen_id, order.order_id)
+            && self.deadline == order.deadline
+    }
+}
+
 pub struct OrderHandler {
     pub id: u8,
     pub socket: UdpSocket,
     pub next: Arc<RwLock<(Ipv4Addr, u16)>>,
-    pub order: Arc<Mutex<Option<Order>>>,
+    pub orders: Arc<Mutex<VecDeque<Order>>>,
     pub bucket_rx: Receiver<(Bucket, SocketAddr)>,
-    pub token_status: Arc<Mutex<HashMap<IceCream, (f32, Grade)>>>,
-    pub result_sent_pair: Arc<(Mutex<u8>, Condvar)>,
+    pub token_status: Arc<Mutex<HashMap<IceCream, (Grams, Grade)>>>,
+    pub scoop_tx: Sender<ScoopTask>,
+    pub cancelled: Arc<Mutex<HashSet<(u8, u8)>>>,
+    pub served: Arc<Mutex<ServedFlavors>>,
 }
 
 impl OrderHandler {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: u8,
         socket: UdpSocket,
         next: Arc<RwLock<(Ipv4Addr, u16)>>,
-        order: Arc<Mutex<Option<Order>>>,
+        orders: Arc<Mutex<VecDeque<Order>>>,
         bucket_rx: Receiver<(Bucket, SocketAddr)>,
-        token_status: Arc<Mutex<HashMap<IceCream, (f32, Grade)>>>,
-        result_sent_pair: Arc<(Mutex<u8>, Condvar)>,
+        token_status: Arc<Mutex<HashMap<IceCream, (Grams, Grade)>>>,
+        scoop_tx: Sender<ScoopTask>,
+        cancelled: Arc<Mutex<HashSet<(u8, u8)>>>,
+        served: Arc<Mutex<ServedFlavors>>,
     ) -> Self {
         OrderHandler {
             id,
             socket,
             next,
-            order,
+            orders,
             bucket_rx,
             token_status,
-            result_sent_pair,
+            scoop_tx,
+            cancelled,
+            served,
         }
     }
 
     pub fn prepare(self) -> Result<(), RobotError> {
-        loop {
-            // Espera a que le llegue un token
-            let (mut bucket, _from) = self.bucket_rx.recv()?;
-
-            let mut order_lock = self.order.lock()?;
-            let mut order_completed = false;
-            let mut abort_order = false;
-            let mut order_id: Option<u8> = None;
-            let mut screen_id: Option<u8> = None;
-
-            if let Some(ref mut order) = *order_lock {
-                order_id = Some(order.order_id);
-                screen_id = Some(order.screen_id);
-                info!(
-                    "[OrderHandler {}] Ya con el helado {:?} resuelvo el pedido {:?}",
-                    self.id, bucket.ice_cream, order
-                );
-                if let Some(amount) = order.items.remove(&bucket.ice_cream) {
-                    // Se fija si hay suficiente cantidad, sino cancela el pedido
-                    abort_order = bucket.amount < amount;
-                    if !abort_order {
-                        let time = Duration::from_millis((amount * 1000.0) as u64);
+        // Cuando el RobotReceiver termina se cierra el channel y el OrderHandler termina
+        while let Ok((mut bucket, _from)) = self.bucket_rx.recv() {
+            // Con el mismo token descuenta el helado de todos los pedidos encolados que usan
+            // ese gusto. Servirlo lleva tiempo, pero eso lo hace el Scooper sin retener el token.
+            let mut orders_lock = self.orders.lock()?;
+            let mut cancelled_lock = self.cancelled.lock()?;
+            // Gustos ya descontados de cada pedido, para informarle el avance al OrderResolver
+            let mut served = self.served.lock()?;
+            for order in orders_lock.iter_mut() {
+                let key = (order.screen_id, order.order_id);
+                // si lo cancelaron o venció antes de que llegara algún token no se empieza a
+                // servir. Una vez empezado se termina.
+                if order.items.contains_key(&bucket.ice_cream) && !served.contains_key(&key) {
+                    let reason = if cancelled_lock.remove(&key) {
+                        Some(AbortReason::Cancelled)
+                    } else if order.is_expired() {
+                        Some(AbortReason::Timeout)
+                    } else {
+                        None
+                    };
+                    if let Some(reason) = reason {
                         info!(
-                            "[OrderHandler {}] Voy a usar el helado, tardo {:?}",
-                            self.id, time
+                            "[OrderHandler {}] Descarto el pedido ({}, {}) antes de servirlo por {:?}",
+                            self.id, order.screen_id, order.order_id, reason
                         );
-                        bucket.amount -= amount;
-                        thread::sleep(time);
-                        order_completed = order.items.is_empty();
+                        order.items.clear();
+                        self.scoop_tx.send(ScoopTask::Deliver {
+                            result: OrderResult::abort(order.order_id, order.screen_id, reason),
+                            deadline: order.deadline,
+                        })?;
+                        continue;
                     }
                 }
-            }
-
-            drop(order_lock);
-
-            if order_completed || abort_order {
-                if let (Some(screen_id), Some(order_id)) = (screen_id, order_id) {
-                    let order_result = if order_completed {
-                        // ya cubrio todos los gustos del pedido
-                        OrderResult::new(OrderStatus::Ready, order_id, screen_id)
-                    } else {
+                if let Some(amount) = order.items.remove(&bucket.ice_cream) {
+                    info!(
+                        "[OrderHandler {}] Ya con el helado {:?} resuelvo el pedido {:?}",
+                        self.id, bucket.ice_cream, order
+                    );
+                    // Se fija si hay suficiente cantidad, sino cancela el pedido
+                    if bucket.amount < amount {
                         info!(
                             "[OrderHandler {}] No tengo helado suficiente, aborto el pedido",
                             self.id
                         );
-                        OrderResult::new(OrderStatus::Abort, order_id, screen_id)
-                    };
-
-                    let mut result_sent_lock = self.result_sent_pair.0.lock()?;
-                    *result_sent_lock = 0;
-                    drop(result_sent_lock);
-
-                    loop {
-                        info!(
-                            "[OrderHandler {}] Envio el {:?} al OrderResolver {}",
-                            self.id, order_result.status, self.id
-                        );
-                        self.socket.send_to(
-                            &order_result.as_bytes(),
-                            id_to_addr_resolver(self.id as u16),
-                        )?;
-                        let (result_sent, cvar) = &*self.result_sent_pair;
-                        let mut result_sent_lock =
-                            cvar.wait_while(result_sent.lock()?, |result_sent| *result_sent == 0)?;
-                        if *result_sent_lock == 1 {
-                            info!("[OrderHandler {}] Ya me confirmaron que el resultado se entrego exitosamente, me preparo para el proximo pedido", self.id);
-                            *result_sent_lock = 0;
-                            cvar.notify_one();
-                            break;
-                        }
-                        *result_sent_lock = 0;
-                        cvar.notify_one();
+                        // sin gustos pendientes no vuelve a pedir tokens hasta que se entregue
+                        order.items.clear();
+                        served.remove(&key);
+                        cancelled_lock.remove(&key);
+                        self.scoop_tx.send(ScoopTask::Deliver {
+                            result: OrderResult::abort(
+                                order.order_id,
+                                order.screen_id,
+                                AbortReason::OutOfStock,
+                            ),
+                            deadline: order.deadline,
+                        })?;
+                        continue;
+                    }
+                    bucket.amount -= amount;
+                    self.scoop_tx.send(ScoopTask::Scoop {
+                        ice_cream: bucket.ice_cream,
+                        amount,
+                    })?;
+                    // ya empezó a servirse, una cancelación tardía no lo frena
+                    cancelled_lock.remove(&key);
+                    let order_served = served.entry(key).or_default();
+                    order_served.push((bucket.ice_cream, amount));
+                    self.socket.send_to(
+                        &OrderProgress::new(self.id, key.0, key.1, order_served.clone()).as_bytes(),
+                        id_to_addr_resolver(self.id as u16),
+                    )?;
+                    if order.items.is_empty() {
+                        served.remove(&key);
+                        // ya cubrio todos los gustos del pedido
+                        self.scoop_tx.send(ScoopTask::Deliver {
+                            result: OrderResult::new(
+                                OrderStatus::Ready,
+                                order.order_id,
+                                order.screen_id,
+                            ),
+                            deadline: order.deadline,
+                        })?;
                     }
                 }
-                let mut order_lock = self.order.lock()?;
-                *order_lock = None;
-                drop(order_lock);
             }
+            drop(served);
+            drop(cancelled_lock);
+            drop(orders_lock);
 
             // Actualizo el token status
             let mut token_status_lock = self.token_status.lock()?;
@@ -276,6 +395,7 @@ impl OrderHandler {
                 *amount = bucket.amount;
                 *grade = grade.next();
             }
+            drop(token_status_lock);
 
             let token = Token {
                 owner: self.id,
@@ -285,6 +405,120 @@ impl OrderHandler {
             self.socket
                 .send_to(&token.clone().as_bytes(), *self.next.read()?)?;
         }
+        Ok(())
+    }
+}
+
+/// Simula el armado físico de los pedidos: sirve el helado ya descontado del token y
+/// entrega los resultados al OrderResolver.
+pub struct Scooper {
+    pub id: u8,
+    pub socket: UdpSocket,
+    pub orders: Arc<Mutex<VecDeque<Order>>>,
+    pub scoop_rx: Receiver<ScoopTask>,
+    pub result_sent_pair: Arc<(Mutex<u8>, Condvar)>,
+    pub orders_completed: Arc<Mutex<u32>>,
+    pub executed: Arc<Mutex<VecDeque<ExecutedOrder>>>,
+    pub speed_percent: u16,
+}
+
+impl Scooper {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: u8,
+        socket: UdpSocket,
+        orders: Arc<Mutex<VecDeque<Order>>>,
+        scoop_rx: Receiver<ScoopTask>,
+        result_sent_pair: Arc<(Mutex<u8>, Condvar)>,
+        orders_completed: Arc<Mutex<u32>>,
+        executed: Arc<Mutex<VecDeque<ExecutedOrder>>>,
+        speed_percent: u16,
+    ) -> Self {
+        Scooper {
+            id,
+            socket,
+            orders,
+            scoop_rx,
+            result_sent_pair,
+            orders_completed,
+            executed,
+            speed_percent,
+        }
+    }
+
+    pub fn scoop(self) -> Result<(), RobotError> {
+        while let Ok(task) = self.scoop_rx.recv() {
+            match task {
+                ScoopTask::Scoop { ice_cream, amount } => {
+                    // servir cada gramo lleva un milisegundo en un robot de velocidad estándar
+                    let time = Duration::from_millis(
+                        amount as u64 * 100 / self.speed_percent.max(1) as u64,
+                    );
+                    info!(
+                        "[Scooper {}] Sirvo el helado {:?}, tardo {:?}",
+                        self.id, ice_cream, time
+                    );
+                    thread::sleep(time);
+                }
+                ScoopTask::Deliver {
+                    result: order_result,
+                    deadline,
+                } => {
+                    // se registra antes de entregarlo, por si el lider cae mientras tanto
+                    let mut executed_lock = self.executed.lock()?;
+                    if executed_lock.len() == EXECUTED_ORDERS_CAPACITY {
+                        executed_lock.pop_front();
+                    }
+                    executed_lock.push_back(ExecutedOrder {
+                        result: order_result,
+                        deadline,
+                    });
+                    drop(executed_lock);
+                    self.send_result(order_result)?;
+                    // recien con el resultado entregado deja de ocupar lugar en la cola
+                    self.orders.lock()?.retain(|order| {
+                        order.order_id != order_result.order_id
+                            || order.screen_id != order_result.owner_id
+                    });
+                    if order_result.status == OrderStatus::Ready {
+                        *self.orders_completed.lock()? += 1;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Envia el resultado al OrderResolver hasta que confirme que lo entregó
+    fn send_result(&self, order_result: OrderResult) -> Result<(), RobotError> {
+        let mut result_sent_lock = self.result_sent_pair.0.lock()?;
+        *result_sent_lock = 0;
+        drop(result_sent_lock);
+
+        loop {
+            info!(
+                "[Scooper {}] Envio el {:?} del pedido {} al OrderResolver {}",
+                self.id, order_result.status, order_result.order_id, self.id
+            );
+            self.socket.send_to(
+                &order_result.as_bytes(),
+                id_to_addr_resolver(self.id as u16),
+            )?;
+            let (result_sent, cvar) = &*self.result_sent_pair;
+            let mut result_sent_lock =
+                cvar.wait_while(result_sent.lock()?, |result_sent| *result_sent == 0)?;
+            if *result_sent_lock == 1 {
+                info!(
+                    "[Scooper {}] Ya me confirmaron que el resultado se entrego exitosamente",
+                    self.id
+                );
+                *result_sent_lock = 0;
+                cvar.notify_one();
+                return Ok(());
+            }
+            *result_sent_lock = 0;
+            cvar.notify_one();
+        }
     }
 }
 
@@ -293,6 +527,8 @@ pub struct RobotInspector {
     pub ring_path: Vec<u8>,
     pub prev: (Ipv4Addr, u16),
     pub token_status: Vec<(IceCream, Grade)>,
+    // cuánto espera la respuesta del anterior, el plazo actual del detector de fallas
+    pub timeout: Duration,
 }
 
 impl RobotInspector {
@@ -301,12 +537,14 @@ impl RobotInspector {
         ring_path: Vec<u8>,
         prev: (Ipv4Addr, u16),
         token_status: Vec<(IceCream, Grade)>,
+        timeout: Duration,
     ) -> Self {
         RobotInspector {
             id,
             ring_path,
             prev,
             token_status,
+            timeout,
         }
     }
 
@@ -332,7 +570,7 @@ impl RobotInspector {
         );
         let inspect_socket =
             UdpSocket::bind((Ipv4Addr::new(127, 0, 0, 1), 2100 + (self.id as u16)))?;
-        inspect_socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+        inspect_socket.set_read_timeout(Some(self.timeout))?;
         inspect_socket.send_to(&KeepAlive::new(self.id).as_bytes(), self.prev)?;
 
         let mut response = [0; 100];
@@ -365,32 +603,62 @@ pub struct Robot {
     pub socket: UdpSocket,
     pub prev: (Ipv4Addr, u16),
     pub next: Arc<RwLock<(Ipv4Addr, u16)>>,
-    pub order: Arc<Mutex<Option<Order>>>,
-    pub token_status: Arc<Mutex<HashMap<IceCream, (f32, Grade)>>>,
+    pub orders: Arc<Mutex<VecDeque<Order>>>,
+    pub token_status: Arc<Mutex<HashMap<IceCream, (Grams, Grade)>>>,
     pub result_sent_pair: Arc<(Mutex<u8>, Condvar)>,
+    pub started_at: Instant,
+    pub orders_completed: Arc<Mutex<u32>>,
+    pub leader: Option<u8>,
+    // mandato más nuevo que conoce, los pedidos de una líder anterior se rechazan
+    pub term: Term,
+    pub draining: bool,
+    pub snapshot: Arc<Mutex<TokenSnapshot>>,
+    pub executed: Arc<Mutex<VecDeque<ExecutedOrder>>>,
+    pub capabilities: Capabilities,
+    // pedidos encolados que el cliente canceló y todavía no se empezaron a servir
+    pub cancelled: Arc<Mutex<HashSet<(u8, u8)>>>,
+    // gustos servidos de los pedidos que ya se empezaron, compartidos con el OrderHandler
+    pub served: Arc<Mutex<ServedFlavors>>,
+    // aprende cada cuánto le llegan mensajes, para notar a tiempo que se cayó el anterior
+    pub detector: FailureDetector,
+    // el RobotInspector que está esperando la respuesta del anterior, si hay uno
+    pub inspector: Option<JoinHandle<Result<(), RobotError>>>,
 }
 
 impl Robot {
-    pub fn new(id: u8, ring_path: Vec<u8>, addr: (Ipv4Addr, u16)) -> Result<Self, RobotError> {
+    pub fn new(
+        id: u8,
+        ring_path: Vec<u8>,
+        addr: (Ipv4Addr, u16),
+        capabilities: Capabilities,
+    ) -> Result<Self, RobotError> {
         let socket = UdpSocket::bind(addr)?;
-        socket.set_read_timeout(Some(Duration::from_secs(10)))?;
+        socket.set_read_timeout(Some(INIT_RETRY_INTERVAL))?;
         let prev = id_to_addr_robot(((id as usize + ring_path.len() - 1) % ring_path.len()) as u16); // agarra el anterior en el ring path
         let next = Arc::new(RwLock::new(id_to_addr_robot(
             ((id as usize + ring_path.len() + 1) % ring_path.len()) as u16,
         ))); // agarra el siguiente en el ring path
 
-        let order = Arc::new(Mutex::new(None));
+        let orders = Arc::new(Mutex::new(VecDeque::new()));
         let result_sent_pair = Arc::new((Mutex::new(0), Condvar::new()));
+        // Arranca con el último stock que vio antes de apagarse, si lo guardó
+        let snapshot = TokenSnapshot::load(id);
         let token_status = Arc::new(Mutex::new(
             [
-                (IceCream::Vanilla, (20.0, Grade::A)),
-                (IceCream::DulceDeLeche, (20.0, Grade::A)),
-                (IceCream::Chocolate, (20.0, Grade::A)),
-                (IceCream::Lemon, (20.0, Grade::A)),
-                (IceCream::Strawberry, (20.0, Grade::A)),
+                IceCream::Vanilla,
+                IceCream::DulceDeLeche,
+                IceCream::Chocolate,
+                IceCream::Lemon,
+                IceCream::Strawberry,
             ]
             .iter()
-            .cloned()
+            .map(|ice_cream| {
+                let amount = snapshot
+                    .flavors
+                    .get(ice_cream)
+                    .map_or(BUCKET_GRAMS, |flavor| flavor.amount);
+                (*ice_cream, (amount, Grade::A))
+            })
             .collect(),
         ));
 
@@ -398,6 +666,7 @@ impl Robot {
             "[Robot {}] Escucho en {:?}, con el path {:?}, mi anterior {:?} y mi siguiente {:?}",
             id, addr, ring_path, prev, next
         );
+        info!("[Robot {}] Mis capacidades son {:?}", id, capabilities);
 
         Ok(Robot {
             id,
@@ -406,9 +675,21 @@ impl Robot {
             socket,
             prev,
             next,
-            order,
+            orders,
             token_status,
             result_sent_pair,
+            started_at: Instant::now(),
+            orders_completed: Arc::new(Mutex::new(0)),
+            leader: None,
+            term: 0,
+            draining: false,
+            snapshot: Arc::new(Mutex::new(snapshot)),
+            executed: Arc::new(Mutex::new(VecDeque::new())),
+            capabilities,
+            cancelled: Arc::new(Mutex::new(HashSet::new())),
+            served: Arc::new(Mutex::new(HashMap::new())),
+            detector: FailureDetector::new(RECEIVE_TIMEOUT),
+            inspector: None,
         })
     }
 
@@ -416,25 +697,45 @@ impl Robot {
         info!("[Robot {}] Arranque a funcionar", self.id);
 
         let (bucket_tx, bucket_rx) = mpsc::channel::<(Bucket, SocketAddr)>();
+        let (scoop_tx, scoop_rx) = mpsc::channel::<ScoopTask>();
         let order_handler = OrderHandler::new(
             self.id,
             self.socket.try_clone()?,
             self.next.clone(),
-            self.order.clone(),
+            self.orders.clone(),
             bucket_rx,
             self.token_status.clone(),
+            scoop_tx,
+            self.cancelled.clone(),
+            self.served.clone(),
+        );
+        let scooper = Scooper::new(
+            self.id,
+            self.socket.try_clone()?,
+            self.orders.clone(),
+            scoop_rx,
             self.result_sent_pair.clone(),
+            self.orders_completed.clone(),
+            self.executed.clone(),
+            self.capabilities.speed_percent,
         );
 
+        let snapshotter =
+            Snapshotter::new(self.id, self.token_status.clone(), self.snapshot.clone());
+
         start_protocol(self)?;
 
-        // Arranca el OrderHandler
+        // Arranca el OrderHandler y el Scooper
         let oh_handler = thread::spawn(move || order_handler.prepare());
+        let scooper_handler = thread::spawn(move || scooper.scoop());
+        // El Snapshotter queda corriendo hasta que termine el robot
+        thread::spawn(move || snapshotter.run());
 
         // Arranca el RobotReceiver
         self.receiver(bucket_tx)?;
 
         oh_handler.join()??;
+        scooper_handler.join()??;
 
         Ok(())
     }
@@ -443,24 +744,23 @@ impl Robot {
 
     fn receiver(&mut self, mut bucket_tx: Sender<(Bucket, SocketAddr)>) -> Result<(), RobotError> {
         info!("[RobotReceiver {}] Empece a escuchar", self.id);
+        self.detector.restart();
         loop {
+            // también después de un timeout, por si el anillo está quieto mientras se drena
+            if self.draining && self.orders.lock()?.is_empty() {
+                self.leave_ring()?;
+                return Ok(());
+            }
             let mut buf = [0; 100];
+            self.socket
+                .set_read_timeout(Some(self.detector.timeout()))?;
             let (_, from) = match self.socket.recv_from(&mut buf) {
-                Ok(result) => result,
+                Ok(result) => {
+                    self.detector.heartbeat();
+                    result
+                }
                 Err(ref error) if error.kind() == ErrorKind::WouldBlock => {
-                    let token_status = self
-                        .token_status
-                        .lock()?
-                        .iter()
-                        .map(|(ice_cream, (_, grade))| (*ice_cream, grade.clone()))
-                        .collect();
-                    let robot_inspector = RobotInspector::new(
-                        self.id,
-                        self.ring_path.clone(),
-                        self.prev,
-                        token_status,
-                    );
-                    thread::spawn(move || robot_inspector.inspect_prev());
+                    self.inspect_prev()?;
                     continue;
                 }
                 Err(error) => return Err(error.into()),
@@ -469,7 +769,7 @@ impl Robot {
             match msg {
                 Messages::Handshake => self.handle_handshake(&buf)?,
                 Messages::Token => self.handle_token(&buf, from, &mut bucket_tx)?,
-                Messages::RobotOrder => self.handle_order(&buf)?,
+                Messages::RobotOrder => self.handle_order(&buf, from)?,
                 Messages::RobotDead => self.handle_robotdead(&buf)?,
                 Messages::KeepAlive => self.handle_keepalive(&buf, from)?,
                 Messages::KeepAliveFromResolver => {
@@ -477,11 +777,50 @@ impl Robot {
                 }
                 Messages::NewLeader => self.handle_newleader(&buf, from)?,
                 Messages::Ack => self.handle_ack()?,
+                Messages::StatusRequest => self.handle_status_request(from)?,
+                Messages::Drain => self.handle_drain(from)?,
+                Messages::Leave => self.handle_leave(&buf, from)?,
+                Messages::SnapshotCollect => self.handle_snapshot_collect(&buf)?,
+                Messages::CancelOrder => self.handle_cancel_order(&buf)?,
                 _ => {}
             }
         }
     }
 
+    // Le pregunta al anterior si sigue vivo. Todos los inspectores usan el mismo puerto, así
+    // que no se lanza otro mientras el anterior sigue esperando la respuesta.
+    fn inspect_prev(&mut self) -> Result<(), RobotError> {
+        if let Some(inspector) = self.inspector.take() {
+            if !inspector.is_finished() {
+                self.inspector = Some(inspector);
+                return Ok(());
+            }
+            match inspector.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(error)) => error!(
+                    "[RobotInspector {}] No pude revisar a mi anterior: {}",
+                    self.id, error
+                ),
+                Err(_) => error!("[RobotInspector {}] Termino con panic", self.id),
+            }
+        }
+        let token_status = self
+            .token_status
+            .lock()?
+            .iter()
+            .map(|(ice_cream, (_, grade))| (*ice_cream, grade.clone()))
+            .collect();
+        let robot_inspector = RobotInspector::new(
+            self.id,
+            self.ring_path.clone(),
+            self.prev,
+            token_status,
+            self.detector.timeout(),
+        );
+        self.inspector = Some(thread::spawn(move || robot_inspector.inspect_prev()));
+        Ok(())
+    }
+
     fn handle_handshake(&mut self, buffer: &[u8]) -> Result<(), RobotError> {
         let mut handshake = Handshake::from_bytes(&buffer[1..]);
         info!(
@@ -530,13 +869,15 @@ impl Robot {
         let mut token = Token::from_bytes(&buffer[1..])?;
 
         token.owner = self.id;
-        let order_lock = self.order.lock()?;
-        if let Some(ref order) = *order_lock {
-            if order.items.contains_key(&token.bucket.ice_cream) {
-                bucket_tx.send((token.bucket, from))?;
-                return Ok(());
-            }
+        let orders_lock = self.orders.lock()?;
+        if orders_lock
+            .iter()
+            .any(|order| order.items.contains_key(&token.bucket.ice_cream))
+        {
+            bucket_tx.send((token.bucket, from))?;
+            return Ok(());
         }
+        drop(orders_lock);
 
         // Actualizo el token status
         let mut token_status_lock = self.token_status.lock()?;
@@ -550,11 +891,69 @@ impl Robot {
         Ok(())
     }
 
-    fn handle_order(&mut self, buffer: &[u8]) -> Result<(), RobotError> {
+    // Le avisa a una líder de un mandato viejo cuál es el actual
+    fn reject_stale_term(&self, term: Term, from: SocketAddr) -> Result<(), RobotError> {
+        info!(
+            "[RobotReceiver {}] Ignoro un mensaje del mandato {}, el actual es {}",
+            self.id, term, self.term
+        );
+        self.socket
+            .send_to(&StaleTerm::new(self.id, self.term).as_bytes(), from)?;
+        Ok(())
+    }
+
+    fn handle_order(&mut self, buffer: &[u8], from: SocketAddr) -> Result<(), RobotError> {
         let order = RobotOrder::from_bytes(&buffer[1..]);
-        info!("[RobotReceiver {}] Recibi el pedido {:?}, lo cargo para que lo maneje el OrderHandler.", self.id, order);
-        let mut order_lock = self.order.lock()?;
-        *order_lock = Some(order.order);
+        let key = (order.order.screen_id, order.order.order_id);
+        if order.order.term < self.term {
+            return self.reject_stale_term(order.order.term, from);
+        }
+        self.term = order.order.term;
+
+        // Un pedido que ya prepare no se vuelve a preparar, respondo el resultado guardado
+        let cached = self
+            .executed
+            .lock()?
+            .iter()
+            .find(|executed| executed.is_for(&order.order))
+            .map(|executed| executed.result);
+        if let Some(result) = cached {
+            info!(
+                "[RobotReceiver {}] Ya prepare el pedido {:?}, respondo el resultado {:?}",
+                self.id, key, result.status
+            );
+            self.socket.send_to(&result.as_cached_bytes(), from)?;
+            return Ok(());
+        }
+
+        let mut orders_lock = self.orders.lock()?;
+        let already_queued = orders_lock
+            .iter()
+            .any(|queued| (queued.screen_id, queued.order_id) == key);
+        let accepted = already_queued || (!self.draining && orders_lock.len() < MAX_QUEUED_ORDERS);
+        if already_queued {
+            info!(
+                "[RobotReceiver {}] El pedido {:?} ya estaba en mi cola, no lo encolo de nuevo",
+                self.id, key
+            );
+        } else if accepted {
+            info!("[RobotReceiver {}] Recibi el pedido {:?}, lo encolo para que lo maneje el OrderHandler.", self.id, order);
+            orders_lock.push_back(order.order.clone());
+        } else {
+            info!(
+                "[RobotReceiver {}] Recibi el pedido {:?} pero tengo la cola llena, lo rechazo.",
+                self.id, order
+            );
+        }
+        let status = RobotQueueStatus::new(
+            self.id,
+            order.order.screen_id,
+            order.order.order_id,
+            accepted,
+            orders_lock.len() as u8,
+        );
+        drop(orders_lock);
+        self.socket.send_to(&status.as_bytes(), from)?;
         Ok(())
     }
 
@@ -609,29 +1008,244 @@ impl Robot {
         Ok(())
     }
 
-    fn handle_newleader(&mut self, _: &[u8], from: SocketAddr) -> Result<(), RobotError> {
-        let order_lock = self.order.lock()?;
-        if let Some(ref order) = *order_lock {
+    fn handle_newleader(&mut self, buffer: &[u8], from: SocketAddr) -> Result<(), RobotError> {
+        let new_leader = NewLeader::from_bytes(&buffer[1..])?;
+        if new_leader.term < self.term {
+            return self.reject_stale_term(new_leader.term, from);
+        }
+        self.term = new_leader.term;
+        self.leader = Some(new_leader.leader);
+        let orders_lock = self.orders.lock()?;
+        let orders: Vec<(u8, u8)> = orders_lock
+            .iter()
+            .map(|order| (order.screen_id, order.order_id))
+            .collect();
+        drop(orders_lock);
+        // los que siguen en la cola se informan como en curso aunque ya esten preparados
+        let executed: Vec<OrderResult> = self
+            .executed
+            .lock()?
+            .iter()
+            .map(|executed| executed.result)
+            .filter(|result| !orders.contains(&(result.owner_id, result.order_id)))
+            .collect();
+        if !orders.is_empty() {
             info!(
-                    "[RobotReceiver {}] Me llego un NEWLEADER, le mando que estoy atendiendo el pedido {:?} a la nueva pantalla lider",
-                    self.id, order
+                    "[RobotReceiver {}] Me llego un NEWLEADER, le mando que estoy atendiendo los pedidos {:?} a la nueva pantalla lider",
+                    self.id, orders
                 );
             self.socket.send_to(
-                &RobotWithOrder::new(order.screen_id, order.order_id).as_bytes(),
+                &RobotWithOrder::new(self.capabilities.clone(), orders, executed).as_bytes(),
                 from,
             )?;
             let (result_sent, cvar) = &*self.result_sent_pair;
             let mut result_sent_lock = result_sent.lock()?;
             *result_sent_lock = 2;
             cvar.notify_one();
+        } else if !executed.is_empty() {
+            info!(
+                    "[RobotReceiver {}] Me llego un NEWLEADER, estoy libre y le mando los {} pedido(s) que ya prepare a la nueva pantalla lider",
+                    self.id, executed.len()
+                );
+            self.socket.send_to(
+                &RobotWithOrder::new(self.capabilities.clone(), orders, executed).as_bytes(),
+                from,
+            )?;
         } else {
             info!(
                     "[RobotReceiver {}] Me llego un NEWLEADER, le mando que estoy libre a la nueva pantalla lider",
                     self.id
                 );
-            self.socket
-                .send_to(&[Messages::RobotAvailable as u8], from)?;
+            self.socket.send_to(
+                &RobotAvailable::new(self.capabilities.clone()).as_bytes(),
+                from,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn handle_status_request(&mut self, from: SocketAddr) -> Result<(), RobotError> {
+        debug!("[RobotReceiver {}] Me pidieron mi estado", self.id);
+        let mut stock: Vec<(IceCream, Grams, Grade)> = self
+            .token_status
+            .lock()?
+            .iter()
+            .map(|(ice_cream, (amount, grade))| (*ice_cream, *amount, grade.clone()))
+            .collect();
+        stock.sort_by_key(|(ice_cream, _, _)| *ice_cream as u8);
+        let status = StatusReply {
+            owner: self.id,
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            orders_completed: *self.orders_completed.lock()?,
+            prev: addr_to_id_robot(self.prev),
+            next: addr_to_id_robot(*self.next.read()?),
+            ring_path: self.ring_path.clone(),
+            stock,
+            orders: self.orders.lock()?.iter().cloned().collect(),
+        };
+        self.socket.send_to(&status.as_bytes(), from)?;
+        Ok(())
+    }
+
+    // Sin una líder a la que avisarle, el robot seguiría recibiendo pedidos que ya no acepta:
+    // en ese caso rechaza el drenado
+    fn handle_drain(&mut self, from: SocketAddr) -> Result<(), RobotError> {
+        let leader = match self.leader {
+            Some(leader) => leader,
+            None => {
+                info!(
+                    "[RobotReceiver {}] Me piden drenar pero no conozco a la pantalla lider para avisarle, lo rechazo",
+                    self.id
+                );
+                self.socket
+                    .send_to(&DrainReply::new(self.id, false).as_bytes(), from)?;
+                return Ok(());
+            }
+        };
+        info!(
+            "[RobotReceiver {}] Me piden drenar, no acepto mas pedidos y termino los que tengo",
+            self.id
+        );
+        self.draining = true;
+        self.socket.send_to(
+            &RobotDraining::new(self.id).as_bytes(),
+            id_to_addr_leader(leader as u16),
+        )?;
+        self.socket
+            .send_to(&DrainReply::new(self.id, true).as_bytes(), from)?;
+        Ok(())
+    }
+
+    // Agrega su snapshot a los que viene juntando el robot 0 y lo pasa al siguiente
+    fn handle_snapshot_collect(&mut self, buffer: &[u8]) -> Result<(), RobotError> {
+        let collected = SnapshotCollect::from_bytes(&buffer[1..])?;
+        let mut snapshot = self.snapshot.lock()?.clone();
+        snapshot.merge(&collected.stock);
+        info!(
+            "[RobotReceiver {}] Agrego mi snapshot a los del anillo: {:?}",
+            self.id,
+            snapshot.entries()
+        );
+        self.socket.send_to(
+            &SnapshotCollect::new(collected.owner, snapshot.entries()).as_bytes(),
+            *self.next.read()?,
+        )?;
+        Ok(())
+    }
+
+    // El OrderHandler descarta el pedido cuando le llega un token para él. Solo se anota si
+    // el pedido está en la cola y todavía no se sirvió ningún gusto; el OrderHandler borra la
+    // marca al descartarlo o al empezar a servirlo.
+    fn handle_cancel_order(&mut self, buffer: &[u8]) -> Result<(), RobotError> {
+        let cancel = CancelOrder::from_bytes(&buffer[1..]);
+        let key = (cancel.screen_id, cancel.order_id);
+        let orders_lock = self.orders.lock()?;
+        let mut cancelled_lock = self.cancelled.lock()?;
+        let queued = orders_lock
+            .iter()
+            .any(|order| (order.screen_id, order.order_id) == key && !order.items.is_empty());
+        if queued && !self.served.lock()?.contains_key(&key) {
+            info!(
+                "[RobotReceiver {}] Me piden cancelar el pedido {:?}",
+                self.id, key
+            );
+            cancelled_lock.insert(key);
+        } else {
+            info!(
+                "[RobotReceiver {}] Ignoro la cancelacion del pedido {:?}: no lo tengo o ya lo empece",
+                self.id, key
+            );
+        }
+        Ok(())
+    }
+
+    // Otro robot sale del anillo y me indica cual es mi nuevo siguiente. Si el LEAVE es un
+    // reenvío solo se vuelve a confirmar.
+    fn handle_leave(&mut self, buffer: &[u8], from: SocketAddr) -> Result<(), RobotError> {
+        let leave = Leave::from_bytes(&buffer[1..]);
+        let new_next = id_to_addr_robot(leave.next as u16);
+        let mut next_lock = self.next.write()?;
+        if *next_lock != new_next {
+            info!(
+                "[RobotReceiver {}] El robot {} sale del anillo, mi nuevo siguiente es {}",
+                self.id, leave.owner, leave.next
+            );
+            *next_lock = new_next;
+            self.socket.send_to(
+                &Handshake::new(self.id, vec![self.id]).as_bytes(),
+                *next_lock,
+            )?;
+        }
+        drop(next_lock);
+        self.socket.send_to(&[Messages::LeaveAck as u8], from)?;
+        Ok(())
+    }
+
+    // Le pide a su anterior que lo saque del anillo, reenviándole el pedido hasta que lo
+    // confirme, y reenvia los tokens que le sigan llegando hasta que dejen de llegar.
+    fn leave_ring(&mut self) -> Result<(), RobotError> {
+        let next = *self.next.read()?;
+        let leave = Leave::new(self.id, addr_to_id_robot(next)).as_bytes();
+        let mut acked = next == self.addr;
+        let mut attempts = 0;
+        let mut resend_at = Instant::now();
+        loop {
+            if !acked && Instant::now() >= resend_at {
+                if attempts == LEAVE_ATTEMPTS {
+                    info!(
+                        "[RobotReceiver {}] Mi anterior no confirmo el LEAVE, el anillo me va a dar por caido",
+                        self.id
+                    );
+                    acked = true;
+                } else {
+                    info!(
+                        "[RobotReceiver {}] Termine mis pedidos, le pido a mi anterior que me saque del anillo",
+                        self.id
+                    );
+                    self.socket.send_to(&leave, self.prev)?;
+                    attempts += 1;
+                    resend_at = Instant::now() + LEAVE_RETRY_INTERVAL;
+                }
+            }
+            let timeout = if acked {
+                LEAVE_GRACE_PERIOD
+            } else {
+                resend_at
+                    .saturating_duration_since(Instant::now())
+                    .max(Duration::from_millis(1))
+            };
+            self.socket.set_read_timeout(Some(timeout))?;
+            let mut buf = [0; 100];
+            let (_, from) = match self.socket.recv_from(&mut buf) {
+                Ok(result) => result,
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock && acked => break,
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => continue,
+                Err(error) => return Err(error.into()),
+            };
+            match Messages::try_from(buf[0])? {
+                Messages::LeaveAck => {
+                    debug!(
+                        "[RobotReceiver {}] Mi anterior confirmo que me saco del anillo",
+                        self.id
+                    );
+                    acked = true;
+                }
+                Messages::Token => {
+                    let mut token = Token::from_bytes(&buf[1..])?;
+                    token.owner = self.id;
+                    info!(
+                        "[RobotReceiver {}] Reenvio el token de {:?} antes de salir",
+                        self.id, token.bucket.ice_cream
+                    );
+                    self.socket.send_to(&token.as_bytes(), next)?;
+                }
+                Messages::KeepAlive | Messages::KeepAliveFromResolver => {
+                    self.socket.send_to(&Alive::new(self.id).as_bytes(), from)?;
+                }
+                _ => {}
+            }
         }
+        info!("[RobotReceiver {}] Sali del anillo", self.id);
         Ok(())
     }
 
@@ -0,0 +1,143 @@
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    robot_lib::icecream::{Grams, IceCream},
+    utils::{
+        errors::RobotError,
+        messages::Grade,
+        persistence::{load_json, save_json},
+    },
+};
+
+/// Directorio donde cada robot guarda el último estado de los tokens que vio.
+pub const SNAPSHOT_DIR: &str = "snapshots";
+
+/// Cada cuánto el robot revisa si cambió el estado de los tokens para guardarlo.
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Último stock conocido de un gusto.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlavorSnapshot {
+    pub amount: Grams,
+}
+
+impl FlavorSnapshot {
+    /// Un snapshot es más fresco si tiene menos stock, ya que el stock solo baja.
+    pub fn is_fresher_than(&self, other: &FlavorSnapshot) -> bool {
+        self.amount < other.amount
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct TokenSnapshot {
+    pub flavors: HashMap<IceCream, FlavorSnapshot>,
+}
+
+impl TokenSnapshot {
+    fn path(id: u8) -> PathBuf {
+        PathBuf::from(SNAPSHOT_DIR).join(format!("robot_{}.json", id))
+    }
+
+    /// Lee el snapshot del robot. Si no existe o no se puede leer arranca sin snapshot.
+    pub fn load(id: u8) -> TokenSnapshot {
+        let path = Self::path(id);
+        match load_json(&path, &format!("[Robot {}]", id)) {
+            Some(snapshot) => {
+                info!("[Robot {}] Cargue el snapshot {:?}", id, path);
+                snapshot
+            }
+            None => {
+                info!("[Robot {}] No tengo un snapshot de los tokens guardado", id);
+                TokenSnapshot::default()
+            }
+        }
+    }
+
+    pub fn save(&self, id: u8) -> Result<(), RobotError> {
+        save_json(&Self::path(id), self)?;
+        Ok(())
+    }
+
+    /// Se queda con el snapshot más fresco de cada gusto.
+    pub fn merge(&mut self, others: &[(IceCream, FlavorSnapshot)]) {
+        for (ice_cream, other) in others {
+            match self.flavors.get(ice_cream) {
+                Some(mine) if !other.is_fresher_than(mine) => {}
+                _ => {
+                    self.flavors.insert(*ice_cream, *other);
+                }
+            }
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(IceCream, FlavorSnapshot)> {
+        let mut entries: Vec<(IceCream, FlavorSnapshot)> = self
+            .flavors
+            .iter()
+            .map(|(ice_cream, flavor)| (*ice_cream, *flavor))
+            .collect();
+        entries.sort_by_key(|(ice_cream, _)| *ice_cream as u8);
+        entries
+    }
+}
+
+/// Guarda periódicamente el estado de los tokens que vio pasar el robot. Un gusto se
+/// marca como visto cuando cambió su stock o su grado desde la última revisión.
+pub struct Snapshotter {
+    pub id: u8,
+    pub token_status: Arc<Mutex<HashMap<IceCream, (Grams, Grade)>>>,
+    pub snapshot: Arc<Mutex<TokenSnapshot>>,
+}
+
+impl Snapshotter {
+    pub fn new(
+        id: u8,
+        token_status: Arc<Mutex<HashMap<IceCream, (Grams, Grade)>>>,
+        snapshot: Arc<Mutex<TokenSnapshot>>,
+    ) -> Self {
+        Snapshotter {
+            id,
+            token_status,
+            snapshot,
+        }
+    }
+
+    pub fn run(self) -> Result<(), RobotError> {
+        let mut last_seen = self.token_status.lock()?.clone();
+        loop {
+            thread::sleep(SNAPSHOT_INTERVAL);
+            let token_status = self.token_status.lock()?.clone();
+            let mut snapshot_lock = self.snapshot.lock()?;
+            let mut changed = false;
+            for (ice_cream, status) in token_status.iter() {
+                if last_seen.get(ice_cream) != Some(status) {
+                    snapshot_lock
+                        .flavors
+                        .insert(*ice_cream, FlavorSnapshot { amount: status.0 });
+                    changed = true;
+                }
+            }
+            if changed {
+                debug!("[Snapshotter {}] Guardo el estado de los tokens", self.id);
+                if let Err(error) = snapshot_lock.save(self.id) {
+                    // sigue guardando en la próxima revisión, con los mismos cambios
+                    error!(
+                        "[Snapshotter {}] No pude guardar el snapshot: {}",
+                        self.id, error
+                    );
+                    continue;
+                }
+            }
+            drop(snapshot_lock);
+            last_seen = token_status;
+        }
+    }
+}
@@ -1,16 +1,70 @@
-use crate::robot_lib::icecream::IceCream;
+use crate::robot_lib::capabilities::Capabilities;
+use crate::robot_lib::icecream::{Container, Grams, IceCream};
+use crate::robot_lib::robot::{EXECUTED_ORDERS_CAPACITY, MAX_QUEUED_ORDERS};
+use crate::screen_lib::membership::SharedMembers;
+use crate::screen_lib::order_log::OrderCommand;
+use crate::screen_lib::scheduling::{
+    effective_priority, RingView, RobotCandidate, SchedulingPolicy,
+};
 use crate::utils::addresses::{id_to_addr_resolver, id_to_addr_robot, id_to_addr_screen};
+use crate::utils::clock::now_millis;
 use crate::utils::errors::ScreenError;
+use crate::utils::failure_detector::FailureDetector;
 use crate::utils::messages::{
-    Alive, KeepAliveFromResolver, Messages, NewLeader, Order, OrderResult, RobotOrder,
-    RobotWithOrder,
+    AbortReason, Alive, CancelOrder, Coordinator, KeepAliveFromResolver, LeaderHeartbeat, Messages,
+    NewLeader, Order, OrderProgress, OrderResult, Priority, RobotAvailable, RobotDraining,
+    RobotOrder, RobotQueueStatus, RobotWithOrder, StaleTerm, Term,
 };
 use actix::prelude::*;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use std::cmp::Reverse;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::ErrorKind;
 use std::net::{Ipv4Addr, UdpSocket};
-use std::time::Duration;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Cada cuánto el coordinador descarta los pedidos en cola que ya vencieron.
+const EXPIRED_ORDERS_CHECK: Duration = Duration::from_secs(1);
+
+/// Cada cuánto los actores de la líder se fijan si fue destituida.
+pub const FENCE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Cada cuánto el LeaderReceiver les manda un latido a las demás pantallas.
+pub const LEADER_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+// Tiempo que un resolver espera a un robot antes de mandarle un KEEPALIVE, hasta que su
+// detector de fallas aprende cada cuánto le llegan sus mensajes
+const RESOLVER_TIMEOUT: Duration = Duration::from_secs(12);
+
+// Veces que un resolver nuevo reintenta tomar su puerto mientras lo tiene el de una líder
+// destituida, que lo suelta cuando deja de esperar a su robot
+const RESOLVER_BIND_ATTEMPTS: u32 = 30;
+
+/// Compartido entre la pantalla líder y sus actores: queda en 0 mientras la líder sigue en
+/// su mandato, y cuando alguien conoce uno más nuevo guarda ese mandato y los actores paran.
+pub type LeaderFence = Arc<AtomicU32>;
+
+fn depose(fence: &LeaderFence, term: Term) {
+    fence.fetch_max(term, Ordering::SeqCst);
+}
+
+fn is_deposed(fence: &LeaderFence) -> bool {
+    fence.load(Ordering::SeqCst) != 0
+}
+
+/// Pedidos que el coordinador le agrega al resolver de un robot mientras este espera los
+/// resultados de su lote, para que el robot llene su cola sin terminar el lote antes.
+#[derive(Debug, Default)]
+pub struct ResolverInbox {
+    // el resolver está esperando resultados y va a tomar lo que se le agregue
+    listening: bool,
+    orders: Vec<OrderActorMessage>,
+}
+
+pub type SharedInbox = Arc<Mutex<ResolverInbox>>;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum RobotStatus {
@@ -26,7 +80,57 @@ pub enum RobotStatus {
 pub struct OrderActorMessage {
     pub screen_owner: u8,
     pub order_id: u8,
-    pub items: HashMap<IceCream, f32>,
+    pub items: HashMap<IceCream, Grams>,
+    pub container: Container,
+    pub priority: Priority,
+    pub deadline: u64,
+}
+
+impl OrderActorMessage {
+    pub fn total_grams(&self) -> Grams {
+        self.items.values().sum()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        now_millis() >= self.deadline
+    }
+
+    pub fn to_order(&self, term: Term) -> Order {
+        Order::new(
+            self.screen_owner,
+            self.order_id,
+            self.items.clone(),
+            self.container,
+            self.priority,
+            self.deadline,
+            term,
+        )
+    }
+}
+
+impl From<Order> for OrderActorMessage {
+    fn from(order: Order) -> Self {
+        OrderActorMessage {
+            screen_owner: order.screen_id,
+            order_id: order.order_id,
+            items: order.items,
+            container: order.container,
+            priority: order.priority,
+            deadline: order.deadline,
+        }
+    }
+}
+
+/// Cola de una líder anterior, reconstruida del log replicado o de la réplica en espera: el
+/// coordinador nuevo arranca con esos pedidos en cola, sabiendo qué robot tenía cada pedido
+/// delegado y recordando esos resultados.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RestoreOrdersActorMessage {
+    pub pending: Vec<Order>,
+    // (robot, pedido)
+    pub assigned: Vec<(u8, Order)>,
+    pub executed: Vec<OrderResult>,
 }
 
 #[derive(Message)]
@@ -35,13 +139,17 @@ pub struct RegisterResolver {
     pub address: Addr<OrderResolver>,
     pub resolver_id: u8,
     pub robot_availability: bool,
-    pub order: Option<(u8, u8)>,
+    pub orders: Vec<(u8, u8)>,
+    pub executed: Vec<OrderResult>,
+    // None si el robot no respondio al registrarse
+    pub capabilities: Option<Capabilities>,
+    pub inbox: SharedInbox,
 }
 
 #[derive(Message)]
 #[rtype(result = "Result<(), ScreenError>")]
 struct RobotOrderActorMessage {
-    order: OrderActorMessage,
+    orders: Vec<OrderActorMessage>,
     robot_id: u8,
 }
 
@@ -49,14 +157,50 @@ struct RobotOrderActorMessage {
 #[rtype(result = "()")]
 struct FallenRobotActorMessage {
     robot_id: u8,
-    order: OrderActorMessage,
+    // pedidos que el robot no llegó a entregar
+    orders: Vec<(u8, u8)>,
+    // lo que el robot ya había descontado de cada uno
+    progress: HashMap<(u8, u8), Vec<(IceCream, Grams)>>,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 struct FreeRobotActorMessage {
     robot_id: u8,
-    order: (u8, u8),
+    orders: Vec<(u8, u8)>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct DrainRobotActorMessage {
+    robot_id: u8,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct CancelOrderActorMessage {
+    screen_id: u8,
+    order_id: u8,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct ExecutedOrderActorMessage {
+    result: OrderResult,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct QueueDepthActorMessage {
+    robot_id: u8,
+    depth: u8,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct TokenSightingActorMessage {
+    robot_id: u8,
+    ice_cream: IceCream,
 }
 
 #[derive(Message)]
@@ -68,60 +212,168 @@ pub struct Inspect {
 #[derive(Message)]
 #[rtype(result = "Result<(), ScreenError>")]
 pub struct ListenRobot {
-    order: (u8, u8),
+    orders: Vec<(u8, u8)>,
 }
 
 // ====================================== LeaderReceiver ====================================== //
 
 pub struct LeaderReceiver {
     id: u8,
+    term: Term,
+    // pantallas a las que les manda los latidos, las actualiza la pantalla líder
+    peers: SharedMembers,
     order_coordinator: Addr<OrderCoordinator>,
     socket: UdpSocket,
+    fence: LeaderFence,
+    // por acá le avisa a la pantalla el error que la hace dejar de ser líder
+    failures: Sender<ScreenError>,
 }
 
 impl LeaderReceiver {
-    pub fn new(id: u8, order_coordinator: Addr<OrderCoordinator>, socket: UdpSocket) -> Self {
+    pub fn new(
+        id: u8,
+        term: Term,
+        peers: SharedMembers,
+        order_coordinator: Addr<OrderCoordinator>,
+        socket: UdpSocket,
+        fence: LeaderFence,
+        failures: Sender<ScreenError>,
+    ) -> Self {
         LeaderReceiver {
             id,
+            term,
+            peers,
             order_coordinator,
             socket,
+            fence,
+            failures,
+        }
+    }
+
+    // Les avisa a las demás pantallas que la líder sigue viva. Si no llega a alguna, esa
+    // pantalla lo va a notar por la falta de latidos.
+    fn send_heartbeats(&self) {
+        let heartbeat = LeaderHeartbeat::new(self.id, self.term).as_bytes();
+        let peers = self
+            .peers
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        for pid in peers.iter().filter(|pid| **pid != self.id) {
+            if let Err(error) = self
+                .socket
+                .send_to(&heartbeat, id_to_addr_screen(*pid as u16))
+            {
+                debug!(
+                    "[LeaderReceiver] No pude mandarle el latido a la pantalla {}: {}",
+                    pid, error
+                );
+            }
         }
     }
 
+    // Atiende los mensajes dirigidos a la líder hasta que la destituyen
     fn receive_msgs(&self) -> Result<(), ScreenError> {
-        loop {
+        let mut next_heartbeat = Instant::now();
+        while !is_deposed(&self.fence) {
+            if Instant::now() >= next_heartbeat {
+                self.send_heartbeats();
+                next_heartbeat = Instant::now() + LEADER_HEARTBEAT_INTERVAL;
+            }
+            self.socket.set_read_timeout(Some(
+                next_heartbeat
+                    .saturating_duration_since(Instant::now())
+                    .clamp(Duration::from_millis(1), FENCE_CHECK_INTERVAL),
+            ))?;
             let mut buf = [0; 1024];
-            let (len, from) = self.socket.recv_from(&mut buf)?;
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(error)
+                    if error.kind() == ErrorKind::WouldBlock
+                        || error.kind() == ErrorKind::TimedOut =>
+                {
+                    continue
+                }
+                Err(error) => return Err(error.into()),
+            };
             debug!("[LeaderReceiver] Recibí un mensaje de: {:?}", from);
             if let Ok(msg) = Messages::try_from(buf[0]) {
                 match msg {
                     // llega un mensaje de Order
                     Messages::Order => {
                         let pedido_msg = Order::from_bytes(&buf[1..len]);
-                        let pedido = OrderActorMessage {
-                            screen_owner: pedido_msg.screen_id,
-                            order_id: pedido_msg.order_id,
-                            items: pedido_msg.items,
-                        };
+                        if pedido_msg.term > self.term {
+                            // la pantalla ya conoce a una líder más nueva
+                            depose(&self.fence, pedido_msg.term);
+                            continue;
+                        }
+                        if pedido_msg.term < self.term {
+                            // la pantalla no se enteró de esta elección, se lo recuerdo y
+                            // atiendo el pedido igual
+                            info!(
+                                "[LeaderReceiver] La pantalla {} me mando un pedido del mandato {}, le aviso que el actual es {}",
+                                pedido_msg.screen_id, pedido_msg.term, self.term
+                            );
+                            self.socket.send_to(
+                                &Coordinator::new(self.id, self.term).as_bytes(),
+                                id_to_addr_screen(pedido_msg.screen_id as u16),
+                            )?;
+                        }
+                        let pedido = OrderActorMessage::from(pedido_msg);
                         info!("[LeaderReceiver] Recibí un pedido: {:?}", pedido);
                         self.order_coordinator.do_send(pedido);
                     }
+                    Messages::CancelOrder => {
+                        let cancel = CancelOrder::from_bytes(&buf[1..len]);
+                        info!(
+                            "[LeaderReceiver] La pantalla {} cancela el pedido {}",
+                            cancel.screen_id, cancel.order_id
+                        );
+                        self.order_coordinator.do_send(CancelOrderActorMessage {
+                            screen_id: cancel.screen_id,
+                            order_id: cancel.order_id,
+                        });
+                    }
                     Messages::KeepAlive => {
                         self.socket.send_to(&Alive::new(self.id).as_bytes(), from)?;
                         debug!("[LeaderReceiver] Respondo KeepAlive");
                     }
+                    Messages::RobotDraining => {
+                        let draining = RobotDraining::from_bytes(&buf[1..len]);
+                        info!(
+                            "[LeaderReceiver] El robot {} se esta drenando",
+                            draining.owner
+                        );
+                        self.order_coordinator.do_send(DrainRobotActorMessage {
+                            robot_id: draining.owner,
+                        });
+                    }
                     _ => {}
                 }
             }
         }
+        info!(
+            "[LeaderReceiver] Dejo de atender mensajes, el mandato {} termino",
+            self.term
+        );
+        Ok(())
     }
 }
 
 impl Actor for LeaderReceiver {
     type Context = SyncContext<Self>;
 
+    // Si deja de poder recibir, la líder no atiende a nadie: se destituye para que el
+    // coordinador apague el sistema y la pantalla deje el liderazgo
     fn started(&mut self, _ctx: &mut Self::Context) {
-        self.receive_msgs().unwrap();
+        if let Err(error) = self.receive_msgs() {
+            error!(
+                "[LeaderReceiver] No puedo seguir recibiendo mensajes, dejo el mandato {}: {}",
+                self.term, error
+            );
+            let _ = self.failures.send(error);
+            depose(&self.fence, self.term);
+        }
     }
 }
 
@@ -129,78 +381,649 @@ impl Actor for LeaderReceiver {
 
 #[derive(Debug)]
 pub struct OrderCoordinator {
-    fulfilled_orders: HashSet<(u8, u8)>,
+    // qué robot tiene cada pedido delegado, y el pedido si se conoce: de los que un robot
+    // informa al registrarse solo se conoce si estaban en la cola de la líder anterior
+    fulfilled_orders: HashMap<(u8, u8), (u8, Option<OrderActorMessage>)>,
+    // resultados de pedidos que los robots ya prepararon, informados al registrarse
+    executed_orders: HashMap<(u8, u8), OrderResult>,
+    // en qué orden se conocieron esos resultados, para olvidar los más viejos
+    executed_fifo: VecDeque<(u8, u8)>,
+    socket: UdpSocket,
     robot_states: HashMap<u8, (bool, Addr<OrderResolver>)>,
+    // por donde se le agregan pedidos al resolver de cada robot mientras espera resultados
+    inboxes: HashMap<u8, SharedInbox>,
+    // cuántos pedidos dijo tener en cola cada robot al recibir el último, descontando los
+    // que resolvió desde entonces
+    queue_depths: HashMap<u8, u8>,
+    // capacidades de los robots vivos, informadas al registrarse
+    robot_capabilities: HashMap<u8, Capabilities>,
     pending_orders: VecDeque<OrderActorMessage>,
+    // desde cuándo espera en la cola cada pedido, para envejecer su prioridad
+    queued_since: HashMap<(u8, u8), Instant>,
+    draining_robots: HashSet<u8>,
+    // helado que se descontó para pedidos que quedaron en un robot caído y se abortaron
+    wasted: HashMap<IceCream, Grams>,
     nresolvers: u8,
+    policy: Box<dyn SchedulingPolicy>,
+    ring: RingView,
+    term: Term,
+    fence: LeaderFence,
+    // por donde la pantalla líder recibe lo que pasa en la cola para replicarlo
+    order_log: Option<Sender<OrderCommand>>,
+    // pedidos que la líder anterior tenía delegados, por robot, hasta que los robots
+    // confirmen al registrarse cuáles tienen
+    restored_assignments: Option<HashMap<(u8, u8), (u8, OrderActorMessage)>>,
 }
 
 impl OrderCoordinator {
-    pub fn new(nresolvers: u8) -> Self {
+    pub fn new(
+        nresolvers: u8,
+        socket: UdpSocket,
+        policy: Box<dyn SchedulingPolicy>,
+        term: Term,
+        fence: LeaderFence,
+    ) -> Self {
         OrderCoordinator {
-            fulfilled_orders: HashSet::new(),
+            fulfilled_orders: HashMap::new(),
+            executed_orders: HashMap::new(),
+            executed_fifo: VecDeque::new(),
+            socket,
             robot_states: HashMap::new(),
+            inboxes: HashMap::new(),
+            queue_depths: HashMap::new(),
+            robot_capabilities: HashMap::new(),
             pending_orders: VecDeque::new(),
+            queued_since: HashMap::new(),
+            draining_robots: HashSet::new(),
+            wasted: HashMap::new(),
             nresolvers,
+            policy,
+            ring: RingView::default(),
+            term,
+            fence,
+            order_log: None,
+            restored_assignments: None,
         }
     }
 
+    pub fn with_order_log(mut self, order_log: Sender<OrderCommand>) -> Self {
+        self.order_log = Some(order_log);
+        self
+    }
+
+    // Le pasa a la pantalla lo que cambió en la cola para que lo agregue al log replicado
+    fn record(&self, command: OrderCommand) {
+        if let Some(order_log) = &self.order_log {
+            if order_log.send(command).is_err() {
+                error!("[Coordinator] La pantalla ya no replica el log de pedidos");
+            }
+        }
+    }
+
+    // El pedido no está en la cola, ni lo tiene un robot, ni ya se resolvió
+    fn is_new(&self, order: &OrderActorMessage) -> bool {
+        let key = (order.screen_owner, order.order_id);
+        !self.executed_orders.contains_key(&key)
+            && !self.fulfilled_orders.contains_key(&key)
+            && !self
+                .pending_orders
+                .iter()
+                .any(|queued| (queued.screen_owner, queued.order_id) == key)
+    }
+
+    // Compara lo que informa un robot al registrarse con lo que la líder anterior tenía
+    // delegado. Manda lo que dice el robot; la diferencia solo se avisa. Devuelve el pedido
+    // si la líder anterior lo tenía.
+    fn check_restored_assignment(
+        &mut self,
+        robot_id: u8,
+        order: (u8, u8),
+    ) -> Option<OrderActorMessage> {
+        let restored = self.restored_assignments.as_mut()?;
+        match restored.remove(&order) {
+            Some((expected, restored_order)) => {
+                if expected != robot_id {
+                    warn!(
+                        "[Coordinator] El robot {} tiene el pedido {:?}, que la lider anterior le habia delegado al robot {}",
+                        robot_id, order, expected
+                    );
+                }
+                Some(restored_order)
+            }
+            None => {
+                warn!(
+                    "[Coordinator] El robot {} tiene el pedido {:?}, que la lider anterior no tenia delegado",
+                    robot_id, order
+                );
+                None
+            }
+        }
+    }
+
+    // Los pedidos que la líder anterior tenía delegados y ningún robot informó se perdieron
+    // con su robot: vuelven a la cola
+    fn requeue_lost_assignments(&mut self) {
+        let restored = match self.restored_assignments.take() {
+            Some(restored) => restored,
+            None => return,
+        };
+        for (key, (robot_id, order)) in restored {
+            if self.fulfilled_orders.contains_key(&key) || self.executed_orders.contains_key(&key) {
+                continue;
+            }
+            warn!(
+                "[Coordinator] Ningun robot informo el pedido {:?}, que tenia el robot {}: lo vuelvo a encolar",
+                key, robot_id
+            );
+            self.enqueue(order);
+        }
+    }
+
+    // Deja de tener en cuenta al robot para repartir pedidos
+    fn forget_robot(&mut self, robot_id: u8) {
+        self.robot_capabilities.remove(&robot_id);
+        self.queue_depths.remove(&robot_id);
+        self.ring.ring.retain(|id| *id != robot_id);
+    }
+
     fn assign_order(&mut self, order: OrderActorMessage) {
         info!(
             "[Coordinator] Buscando resolver disponible para realizar el pedido: {:?}",
             order
         );
 
+        if self.reply_if_executed(&order) {
+            return;
+        }
+        if order.is_expired() {
+            self.reject_order(&order, AbortReason::Timeout);
+            return;
+        }
         if !self
             .fulfilled_orders
-            .contains(&(order.screen_owner, order.order_id))
+            .contains_key(&(order.screen_owner, order.order_id))
+        {
+            if self.is_unsupported(&order) {
+                self.reject_order(&order, AbortReason::Unsupported);
+                return;
+            }
+            if let Some(id) = self.find_available_resolver(&order) {
+                if !self.assign_batch(id, vec![order.clone()]) {
+                    self.enqueue(order);
+                }
+                return;
+            }
+            info!("[Coordinator] No se encontró ningún resolver disponible. Poniendo en cola el pedido: {:?}", order);
+            self.enqueue(order);
+        }
+    }
+
+    fn enqueue(&mut self, order: OrderActorMessage) {
+        // una pantalla que se reinició o cambió de líder puede reenviar un pedido en cola
+        if self.pending_orders.iter().any(|queued| {
+            (queued.screen_owner, queued.order_id) == (order.screen_owner, order.order_id)
+        }) {
+            return;
+        }
+        self.queued_since
+            .entry((order.screen_owner, order.order_id))
+            .or_insert_with(Instant::now);
+        self.pending_orders.push_back(order);
+    }
+
+    // Prioridad del pedido en cola, que sube con el tiempo que lleva esperando
+    fn current_priority(&self, order: &OrderActorMessage) -> u8 {
+        let waited = self
+            .queued_since
+            .get(&(order.screen_owner, order.order_id))
+            .map_or(Duration::ZERO, |since| since.elapsed());
+        effective_priority(order.priority, waited)
+    }
+
+    // Recuerda el resultado de un pedido para reenviárselo a la pantalla si lo vuelve a
+    // mandar. Se olvidan los más viejos para que un id de pedido que se reutiliza no reciba
+    // el resultado de otro pedido.
+    fn remember_result(&mut self, result: OrderResult) {
+        let key = (result.owner_id, result.order_id);
+        if self.executed_orders.insert(key, result).is_none() {
+            self.executed_fifo.push_back(key);
+        }
+        if self.executed_fifo.len() > EXECUTED_ORDERS_CAPACITY {
+            if let Some(oldest) = self.executed_fifo.pop_front() {
+                self.executed_orders.remove(&oldest);
+            }
+        }
+    }
+
+    // Si algún robot ya preparó el pedido le reenvía el resultado a la pantalla en lugar de
+    // volver a prepararlo
+    fn reply_if_executed(&self, order: &OrderActorMessage) -> bool {
+        let result = match self
+            .executed_orders
+            .get(&(order.screen_owner, order.order_id))
+        {
+            Some(result) => *result,
+            None => return false,
+        };
+        info!(
+            "[Coordinator] El pedido ({}, {}) ya se preparo, le reenvio el resultado {:?} a la pantalla",
+            order.screen_owner, order.order_id, result.status
+        );
+        if let Err(error) = self.socket.send_to(
+            &result.as_bytes(),
+            id_to_addr_screen(order.screen_owner as u16),
+        ) {
+            error!("[Coordinator] No pude reenviar el resultado: {}", error);
+        }
+        true
+    }
+
+    fn can_handle(&self, robot_id: u8, order: &OrderActorMessage) -> bool {
+        self.robot_capabilities
+            .get(&robot_id)
+            .is_some_and(|capabilities| {
+                capabilities.can_handle(order.container, order.total_grams())
+            })
+    }
+
+    // Ningún robot vivo que no se esté drenando puede preparar el pedido. Si no queda
+    // ningún robot vivo el pedido espera en la cola.
+    fn is_unsupported(&self, order: &OrderActorMessage) -> bool {
+        let mut robots = self
+            .robot_capabilities
+            .keys()
+            .filter(|id| !self.draining_robots.contains(id))
+            .peekable();
+        robots.peek().is_some() && !robots.any(|id| self.can_handle(*id, order))
+    }
+
+    fn reject_order(&mut self, order: &OrderActorMessage, reason: AbortReason) {
+        self.abort_order((order.screen_owner, order.order_id), reason);
+    }
+
+    fn abort_order(&mut self, (screen_id, order_id): (u8, u8), reason: AbortReason) {
+        info!(
+            "[Coordinator] Rechazo el pedido ({}, {}) por {:?}",
+            screen_id, order_id, reason
+        );
+        let result = OrderResult::abort(order_id, screen_id, reason);
+        self.remember_result(result);
+        self.record(OrderCommand::Resolved(result));
+        if let Err(error) = self
+            .socket
+            .send_to(&result.as_bytes(), id_to_addr_screen(screen_id as u16))
+        {
+            error!("[Coordinator] No pude enviar el rechazo: {}", error);
+        }
+    }
+
+    // El helado que el robot caído ya había descontado para un pedido que se aborta no llega
+    // a nadie: se suma a la merma para que las cuentas de stock cierren
+    fn record_waste(&mut self, robot_id: u8, order: (u8, u8), served: &[(IceCream, Grams)]) {
+        if served.is_empty() {
+            return;
+        }
+        for (ice_cream, amount) in served {
+            *self.wasted.entry(*ice_cream).or_default() += amount;
+        }
+        warn!(
+            "[Coordinator] Se pierde {:?} del pedido {:?}, que quedo en el robot caido {}. Merma total: {:?}",
+            served, order, robot_id, self.wasted
+        );
+    }
+
+    // Rechaza los pedidos en cola que ya no puede preparar ningún robot
+    fn reject_unsupported(&mut self) {
+        let (unsupported, pending): (VecDeque<_>, VecDeque<_>) =
+            std::mem::take(&mut self.pending_orders)
+                .into_iter()
+                .partition(|order| self.is_unsupported(order));
+        self.pending_orders = pending;
+        for order in unsupported.iter() {
+            self.queued_since
+                .remove(&(order.screen_owner, order.order_id));
+            self.reject_order(order, AbortReason::Unsupported);
+        }
+    }
+
+    // Descarta los pedidos que vencieron esperando en la cola
+    fn drop_expired(&mut self) {
+        let (expired, pending): (VecDeque<_>, VecDeque<_>) =
+            std::mem::take(&mut self.pending_orders)
+                .into_iter()
+                .partition(|order| order.is_expired());
+        self.pending_orders = pending;
+        for order in expired.iter() {
+            self.queued_since
+                .remove(&(order.screen_owner, order.order_id));
+            self.reject_order(order, AbortReason::Timeout);
+        }
+    }
+
+    // Si el pedido sigue en la cola lo saca y lo aborta. Si ya lo tiene un robot le reenvía
+    // la cancelación, y el robot lo aborta si todavía no empezó a servirlo.
+    fn cancel_order(&mut self, screen_id: u8, order_id: u8) {
+        let key = (screen_id, order_id);
+        if let Some(position) = self
+            .pending_orders
+            .iter()
+            .position(|order| (order.screen_owner, order.order_id) == key)
         {
-            // Buscamos un resolver disponible
-            if let Some((id, (available, resolver))) = self
+            if let Some(order) = self.pending_orders.remove(position) {
+                self.queued_since.remove(&key);
+                self.reject_order(&order, AbortReason::Cancelled);
+            }
+            return;
+        }
+        let robot_id = match self.fulfilled_orders.get(&key) {
+            Some((robot_id, _)) => *robot_id,
+            None => return,
+        };
+        if let Err(error) = self.socket.send_to(
+            &CancelOrder::new(screen_id, order_id).as_bytes(),
+            id_to_addr_robot(robot_id as u16),
+        ) {
+            error!(
+                "[Coordinator] No pude reenviar la cancelacion al robot {}: {}",
+                robot_id, error
+            );
+        }
+    }
+
+    // Lugares que le quedan al robot en su cola, según los pedidos que tiene delegados y lo
+    // último que informó
+    fn free_slots(&self, robot_id: u8) -> usize {
+        let assigned = self
+            .fulfilled_orders
+            .values()
+            .filter(|(id, _)| *id == robot_id)
+            .count();
+        let reported = self.queue_depths.get(&robot_id).copied().unwrap_or(0) as usize;
+        MAX_QUEUED_ORDERS.saturating_sub(assigned.max(reported))
+    }
+
+    fn is_listening(&self, robot_id: u8) -> bool {
+        self.inboxes.get(&robot_id).is_some_and(|inbox| {
+            inbox
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .listening
+        })
+    }
+
+    // El robot no se está drenando, tiene lugar en su cola y su resolver está libre o
+    // todavía esperando los resultados de su lote
+    fn can_take_orders(&self, robot_id: u8) -> bool {
+        let available = match self.robot_states.get(&robot_id) {
+            Some((available, _)) => *available,
+            None => return false,
+        };
+        !self.draining_robots.contains(&robot_id)
+            && self.free_slots(robot_id) > 0
+            && (available || self.is_listening(robot_id))
+    }
+
+    // La política elige entre los robots con lugar en su cola que pueden preparar el pedido
+    fn find_available_resolver(&self, order: &OrderActorMessage) -> Option<u8> {
+        let candidates: Vec<RobotCandidate> = self
+            .robot_states
+            .keys()
+            .filter(|id| self.can_take_orders(**id) && self.can_handle(**id, order))
+            .map(|id| RobotCandidate {
+                id: *id,
+                speed_percent: self.robot_capabilities[id].speed_percent,
+            })
+            .collect();
+        self.policy.select_robot(order, &candidates, &self.ring)
+    }
+
+    // Le reparte la cola a los robots con lugar en su cola, empezando por los más rápidos.
+    // Un robot que no se llevó nada porque la política prefería a otro para el primer pedido
+    // vuelve a probar cuando ese otro ya tiene su lote.
+    fn dispatch_pending(&mut self) {
+        self.reject_unsupported();
+        loop {
+            let mut available: Vec<u8> = self
                 .robot_states
-                .iter_mut()
-                .find(|(_, (available, _))| *available)
-            {
-                // Clonamos la dirección del resolver para usarla más tarde
-                let resolver_addr = resolver;
+                .keys()
+                .filter(|id| self.can_take_orders(**id))
+                .copied()
+                .collect();
+            available.sort_by_key(|id| {
+                (
+                    Reverse(
+                        self.robot_capabilities
+                            .get(id)
+                            .map_or(0, |capabilities| capabilities.speed_percent),
+                    ),
+                    *id,
+                )
+            });
+            let mut assigned = false;
+            for id in available {
+                let batch = self.next_batch(id);
+                if batch.is_empty() {
+                    continue;
+                }
+                if self.assign_batch(id, batch.clone()) {
+                    assigned = true;
+                } else {
+                    for order in batch {
+                        self.enqueue(order);
+                    }
+                }
+            }
+            if !assigned {
+                break;
+            }
+        }
+    }
 
-                // Marcamos al resolver como no disponible
-                *available = false;
+    // Le delega al resolver todos los pedidos del lote, que su robot encola. Si el resolver
+    // está esperando los resultados de otro lote se los agrega a ese. Devuelve false si no
+    // pudo delegarlos porque el resolver justo terminó su lote.
+    fn assign_batch(&mut self, robot_id: u8, orders: Vec<OrderActorMessage>) -> bool {
+        let (available, resolver) = match self.robot_states.get(&robot_id) {
+            Some((available, resolver)) => (*available, resolver.clone()),
+            None => return false,
+        };
+        if !available {
+            let inbox = match self.inboxes.get(&robot_id) {
+                Some(inbox) => inbox,
+                None => return false,
+            };
+            let mut inbox = inbox
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if !inbox.listening {
+                return false;
+            }
+            inbox.orders.extend(orders.iter().cloned());
+        }
+        for order in orders.iter() {
+            self.record(OrderCommand::Assigned {
+                screen_id: order.screen_owner,
+                order_id: order.order_id,
+                robot_id,
+            });
+            self.fulfilled_orders.insert(
+                (order.screen_owner, order.order_id),
+                (robot_id, Some(order.clone())),
+            );
+            self.queued_since
+                .remove(&(order.screen_owner, order.order_id));
+        }
+        info!(
+            "[Coordinator] Le delego {} pedido(s) al OrderResolver {}",
+            orders.len(),
+            robot_id
+        );
+        if available {
+            // Marcamos al resolver como no disponible y le enviamos el lote
+            self.robot_states
+                .insert(robot_id, (false, resolver.clone()));
+            resolver.do_send(RobotOrderActorMessage { orders, robot_id });
+        }
+        true
+    }
 
-                info!("[Coordinator] Le delego el pedido al OrderResolver {}", *id);
-                // Enviamos el mensaje al resolver del robot
-                resolver_addr.do_send(RobotOrderActorMessage {
-                    order,
-                    robot_id: *id,
-                });
+    // Saca de la cola los pedidos que puede preparar el robot, hasta llenar su cola. Primero
+    // van los de mayor prioridad y, entre ellos, la política elige el orden. Los que no
+    // puede preparar quedan en la cola. Si para el primer pedido la política prefiere a
+    // otro robot libre, el robot no se lleva nada y el pedido queda para ese otro.
+    fn next_batch(&mut self, robot_id: u8) -> Vec<OrderActorMessage> {
+        // los pedidos que ya tiene algun robot o que ya se prepararon se descartan
+        let pending: VecDeque<OrderActorMessage> = std::mem::take(&mut self.pending_orders)
+            .into_iter()
+            .filter(|order| {
+                !self
+                    .fulfilled_orders
+                    .contains_key(&(order.screen_owner, order.order_id))
+                    && !self.reply_if_executed(order)
+            })
+            .collect();
+        self.queued_since.retain(|(screen, order_id), _| {
+            pending
+                .iter()
+                .any(|order| order.screen_owner == *screen && order.order_id == *order_id)
+        });
+        self.pending_orders = pending;
 
-                return;
+        let slots = self.free_slots(robot_id);
+        let mut batch = Vec::new();
+        while batch.len() < slots {
+            let eligible: Vec<(usize, u8)> = self
+                .pending_orders
+                .iter()
+                .enumerate()
+                .filter(|(_, order)| self.can_handle(robot_id, order))
+                .map(|(i, order)| (i, self.current_priority(order)))
+                .collect();
+            let top = match eligible.iter().map(|(_, priority)| *priority).max() {
+                Some(top) => top,
+                None => break,
+            };
+            let eligible: Vec<usize> = eligible
+                .into_iter()
+                .filter(|(_, priority)| *priority == top)
+                .map(|(i, _)| i)
+                .collect();
+            let orders: Vec<&OrderActorMessage> =
+                eligible.iter().map(|i| &self.pending_orders[*i]).collect();
+            let chosen = eligible[self.policy.select_order(&orders)];
+            if batch.is_empty()
+                && self.find_available_resolver(&self.pending_orders[chosen]) != Some(robot_id)
+            {
+                break;
+            }
+            if let Some(order) = self.pending_orders.remove(chosen) {
+                batch.push(order);
             }
-            info!("[Coordinator] No se encontró ningún resolver disponible. Poniendo en cola el pedido: {:?}", order);
-            self.pending_orders.push_back(order);
         }
+        batch
     }
 
     fn free_robot(&mut self, robot_id: u8) {
+        if self.draining_robots.contains(&robot_id) {
+            info!(
+                "[Coordinator] El robot {} termino de drenarse, no le asigno mas pedidos",
+                robot_id
+            );
+            self.robot_states.remove(&robot_id);
+            self.forget_robot(robot_id);
+            return;
+        }
         if let Some(resolver) = self.robot_states.get(&robot_id) {
             let resolver_addr = resolver.1.clone();
             self.robot_states.insert(robot_id, (true, resolver_addr));
-            if let Some(order) = self.pending_orders.pop_front() {
-                self.assign_order(order);
-            }
+            // el robot ya entregó todo lo que tenía
+            self.queue_depths.remove(&robot_id);
+            // la política puede preferir a otro robot libre para el próximo pedido
+            self.dispatch_pending();
         }
     }
 
-    fn handle_robot_failure(&mut self, robot_id: u8, order: OrderActorMessage) {
+    // Lo que el robot caído ya descontó del stock no se vuelve a descontar: el próximo robot
+    // solo prepara los gustos que faltan. Si ya se habían servido todos, el pedido quedó
+    // armado en el robot caído y se aborta, igual que si no se sabe qué tenía el pedido.
+    fn handle_robot_failure(
+        &mut self,
+        robot_id: u8,
+        orders: Vec<(u8, u8)>,
+        progress: HashMap<(u8, u8), Vec<(IceCream, Grams)>>,
+    ) {
         self.robot_states.remove(&robot_id);
-        self.fulfilled_orders
-            .remove(&(order.screen_owner, order.order_id));
-        self.assign_order(order);
+        self.forget_robot(robot_id);
+        // puede que era el unico que podia preparar algunos pedidos de la cola
+        self.reject_unsupported();
+        for key in orders {
+            if !matches!(self.fulfilled_orders.get(&key), Some((id, _)) if *id == robot_id) {
+                continue;
+            }
+            let order = self
+                .fulfilled_orders
+                .remove(&key)
+                .and_then(|(_, order)| order);
+            self.record(OrderCommand::Released {
+                screen_id: key.0,
+                order_id: key.1,
+            });
+            let served = progress.get(&key).cloned().unwrap_or_default();
+            match order {
+                Some(mut order) => {
+                    for (ice_cream, _) in served.iter() {
+                        order.items.remove(ice_cream);
+                    }
+                    if !served.is_empty() {
+                        info!(
+                            "[Coordinator] El robot {} ya habia servido {:?} del pedido {:?}, faltan {:?}",
+                            robot_id, served, key, order.items
+                        );
+                    }
+                    if order.items.is_empty() {
+                        info!(
+                            "[Coordinator] El robot {} se cayo con el pedido {:?} armado sin entregar, lo aborto",
+                            robot_id, key
+                        );
+                        self.record_waste(robot_id, key, &served);
+                        self.abort_order(key, AbortReason::RobotFailure);
+                    } else {
+                        self.assign_order(order);
+                    }
+                }
+                None => {
+                    info!(
+                        "[Coordinator] El robot {} se cayo con el pedido {:?}, que no conozco para volver a armarlo: lo aborto",
+                        robot_id, key
+                    );
+                    self.record_waste(robot_id, key, &served);
+                    self.abort_order(key, AbortReason::RobotFailure);
+                }
+            }
+        }
     }
 }
 
 impl Actor for OrderCoordinator {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(EXPIRED_ORDERS_CHECK, |coordinator, _| {
+            coordinator.drop_expired()
+        });
+        // a la líder la destituyen sus actores, cuando un robot o una pantalla conoce un
+        // mandato más nuevo, o la pantalla, cuando le llega el COORDINATOR de otra
+        ctx.run_interval(FENCE_CHECK_INTERVAL, |coordinator, _| {
+            if is_deposed(&coordinator.fence) {
+                info!(
+                    "[Coordinator] Destituyeron a la lider del mandato {} (la otra lider tiene el {}), apago su sistema",
+                    coordinator.term,
+                    coordinator.fence.load(Ordering::SeqCst)
+                );
+                System::current().stop();
+            }
+        });
+    }
 }
 
 impl Handler<RegisterResolver> for OrderCoordinator {
@@ -213,26 +1036,36 @@ impl Handler<RegisterResolver> for OrderCoordinator {
             msg.resolver_id,
             (msg.robot_availability, msg.address.clone()),
         );
-        if let Some(order) = msg.order {
-            debug!("[Coordinator] Ya habia orden siendo resuelta");
-            self.fulfilled_orders.insert(order);
-            msg.address.try_send(ListenRobot { order })?;
+        self.inboxes.insert(msg.resolver_id, msg.inbox);
+        for result in msg.executed {
+            self.remember_result(result);
+        }
+        if let Some(capabilities) = msg.capabilities {
+            self.robot_capabilities
+                .insert(msg.resolver_id, capabilities);
+            // se asume que el anillo recorre a los robots en orden de id
+            self.ring.ring.push(msg.resolver_id);
+            self.ring.ring.sort_unstable();
+        }
+        if !msg.orders.is_empty() {
+            debug!("[Coordinator] Ya habia ordenes siendo resueltas");
+            for order in msg.orders.iter() {
+                let restored = self.check_restored_assignment(msg.resolver_id, *order);
+                self.fulfilled_orders
+                    .insert(*order, (msg.resolver_id, restored));
+                self.record(OrderCommand::Assigned {
+                    screen_id: order.0,
+                    order_id: order.1,
+                    robot_id: msg.resolver_id,
+                });
+            }
+            msg.address.try_send(ListenRobot { orders: msg.orders })?;
         };
         if self.nresolvers == 0 {
             info!("[Coordinator] Ya no hay mas resolvers por registrarse, chequeo pedidos");
-            let initial_pending_orders_amount = self.pending_orders.len();
-            for _ in 0..initial_pending_orders_amount {
-                if let Some(order) = self.pending_orders.pop_front() {
-                    if !self
-                        .fulfilled_orders
-                        .contains(&(order.screen_owner, order.order_id))
-                    {
-                        self.pending_orders.push_back(order);
-                    } else {
-                        self.assign_order(order);
-                    }
-                }
-            }
+            self.requeue_lost_assignments();
+            // los pedidos que ya tiene algun robot se descartan, el resto se reparte en lotes
+            self.dispatch_pending();
         }
         Ok(())
     }
@@ -243,11 +1076,14 @@ impl Handler<OrderActorMessage> for OrderCoordinator {
 
     fn handle(&mut self, msg: OrderActorMessage, _ctx: &mut Self::Context) {
         info!("[Coordinator] Me llego el pedido {:?}", msg);
+        if self.is_new(&msg) {
+            self.record(OrderCommand::Accepted(msg.to_order(self.term)));
+        }
         if self.nresolvers == 0 {
             self.assign_order(msg);
         } else {
             info!("[Coordinator] Mando el pedido directamente en la cola");
-            self.pending_orders.push_back(msg);
+            self.enqueue(msg);
         }
     }
 }
@@ -256,7 +1092,107 @@ impl Handler<FallenRobotActorMessage> for OrderCoordinator {
     type Result = ();
 
     fn handle(&mut self, msg: FallenRobotActorMessage, _ctx: &mut Self::Context) {
-        self.handle_robot_failure(msg.robot_id, msg.order);
+        self.handle_robot_failure(msg.robot_id, msg.orders, msg.progress);
+    }
+}
+
+impl Handler<DrainRobotActorMessage> for OrderCoordinator {
+    type Result = ();
+
+    fn handle(&mut self, msg: DrainRobotActorMessage, _ctx: &mut Self::Context) {
+        info!(
+            "[Coordinator] Marco al robot {} como no disponible por drenado",
+            msg.robot_id
+        );
+        self.draining_robots.insert(msg.robot_id);
+        if let Some((true, _)) = self.robot_states.get(&msg.robot_id) {
+            // estaba libre, no hay pedidos que esperar
+            self.robot_states.remove(&msg.robot_id);
+            self.forget_robot(msg.robot_id);
+        }
+        if self.nresolvers == 0 {
+            self.reject_unsupported();
+        }
+    }
+}
+
+impl Handler<CancelOrderActorMessage> for OrderCoordinator {
+    type Result = ();
+
+    fn handle(&mut self, msg: CancelOrderActorMessage, _ctx: &mut Self::Context) {
+        self.cancel_order(msg.screen_id, msg.order_id);
+    }
+}
+
+impl Handler<ExecutedOrderActorMessage> for OrderCoordinator {
+    type Result = ();
+
+    fn handle(&mut self, msg: ExecutedOrderActorMessage, _ctx: &mut Self::Context) {
+        self.remember_result(msg.result);
+        self.record(OrderCommand::Resolved(msg.result));
+        // el robot dejó un lugar libre en su cola
+        if let Some((robot_id, _)) = self
+            .fulfilled_orders
+            .remove(&(msg.result.owner_id, msg.result.order_id))
+        {
+            if let Some(depth) = self.queue_depths.get_mut(&robot_id) {
+                *depth = depth.saturating_sub(1);
+            }
+            if self.nresolvers == 0 {
+                self.dispatch_pending();
+            }
+        }
+    }
+}
+
+impl Handler<QueueDepthActorMessage> for OrderCoordinator {
+    type Result = ();
+
+    fn handle(&mut self, msg: QueueDepthActorMessage, _ctx: &mut Self::Context) {
+        self.queue_depths.insert(msg.robot_id, msg.depth);
+    }
+}
+
+impl Handler<RestoreOrdersActorMessage> for OrderCoordinator {
+    type Result = ();
+
+    fn handle(&mut self, msg: RestoreOrdersActorMessage, _ctx: &mut Self::Context) {
+        info!(
+            "[Coordinator] Retomo de la lider anterior {} pedido(s) en cola, {} delegado(s) y {} resultado(s)",
+            msg.pending.len(),
+            msg.assigned.len(),
+            msg.executed.len()
+        );
+        for result in msg.executed {
+            self.remember_result(result);
+        }
+        self.restored_assignments = Some(
+            msg.assigned
+                .into_iter()
+                .map(|(robot_id, order)| {
+                    let order = OrderActorMessage::from(order);
+                    ((order.screen_owner, order.order_id), (robot_id, order))
+                })
+                .collect(),
+        );
+        // se reparten cuando terminen de registrarse los resolvers
+        for order in msg.pending {
+            let order = OrderActorMessage::from(order);
+            if !self
+                .executed_orders
+                .contains_key(&(order.screen_owner, order.order_id))
+            {
+                self.enqueue(order);
+            }
+        }
+    }
+}
+
+impl Handler<TokenSightingActorMessage> for OrderCoordinator {
+    type Result = ();
+
+    fn handle(&mut self, msg: TokenSightingActorMessage, _ctx: &mut Self::Context) {
+        self.ring.sightings.insert(msg.ice_cream, msg.robot_id);
     }
 }
 
@@ -265,7 +1201,17 @@ impl Handler<FreeRobotActorMessage> for OrderCoordinator {
 
     fn handle(&mut self, msg: FreeRobotActorMessage, _ctx: &mut Self::Context) {
         info!("[Coordinator] Robot {} disponible", msg.robot_id);
-        self.fulfilled_orders.remove(&msg.order);
+        for order in msg.orders.iter() {
+            // los que ya se resolvieron se liberaron con su resultado
+            if !matches!(self.fulfilled_orders.get(order), Some((id, _)) if *id == msg.robot_id) {
+                continue;
+            }
+            self.fulfilled_orders.remove(order);
+            self.record(OrderCommand::Released {
+                screen_id: order.0,
+                order_id: order.1,
+            });
+        }
         self.free_robot(msg.robot_id);
     }
 }
@@ -275,20 +1221,100 @@ impl Handler<FreeRobotActorMessage> for OrderCoordinator {
 #[derive(Debug)]
 pub struct OrderResolver {
     id: u8,
+    leader_id: u8,
+    term: Term,
+    fence: LeaderFence,
     order_coordinator: Addr<OrderCoordinator>,
     socket: UdpSocket,
+    robot_detector: FailureDetector,
+    inbox: SharedInbox,
+}
+
+/// Lo que el resolver le delegó a su robot y lo que sabe de cada pedido.
+#[derive(Debug, Default)]
+pub struct RobotBatch {
+    // pedidos cuyo contenido conoce el resolver, para devolverlos si el robot no los prepara
+    pub orders: Vec<OrderActorMessage>,
+    // todos los pedidos que se le delegaron al robot
+    pub keys: Vec<(u8, u8)>,
+    pub pending: HashSet<(u8, u8)>,
+    // los que el robot rechazó por tener la cola llena
+    pub rejected: Vec<(u8, u8)>,
+    // lo que el robot ya descontó de cada pedido
+    pub progress: HashMap<(u8, u8), Vec<(IceCream, Grams)>>,
+}
+
+impl RobotBatch {
+    // Pedidos que el robot tenía antes de que se registrara el resolver, sin su contenido
+    fn from_keys(keys: Vec<(u8, u8)>) -> Self {
+        RobotBatch {
+            pending: keys.iter().copied().collect(),
+            keys,
+            ..Default::default()
+        }
+    }
+
+    fn push(&mut self, order: OrderActorMessage) {
+        let key = (order.screen_owner, order.order_id);
+        self.pending.insert(key);
+        self.keys.push(key);
+        self.orders.push(order);
+    }
+
+    // Pedidos de contenido conocido que el robot no llegó a resolver
+    fn unresolved(&self) -> Vec<OrderActorMessage> {
+        self.orders
+            .iter()
+            .filter(|order| {
+                let key = (order.screen_owner, order.order_id);
+                self.pending.contains(&key) || self.rejected.contains(&key)
+            })
+            .cloned()
+            .collect()
+    }
 }
 
 impl OrderResolver {
-    pub fn new(id: u8, order_coordinator: Addr<OrderCoordinator>) -> Self {
-        let socket = UdpSocket::bind(id_to_addr_resolver(id as u16)).unwrap();
-        socket
-            .set_read_timeout(Some(Duration::from_secs(12)))
-            .unwrap();
+    pub fn new(
+        id: u8,
+        leader_id: u8,
+        term: Term,
+        fence: LeaderFence,
+        order_coordinator: Addr<OrderCoordinator>,
+        socket: UdpSocket,
+    ) -> Self {
         OrderResolver {
             id,
+            leader_id,
+            term,
+            fence,
             order_coordinator,
             socket,
+            robot_detector: FailureDetector::new(RESOLVER_TIMEOUT),
+            inbox: SharedInbox::default(),
+        }
+    }
+
+    // Los robots le mandan los resultados siempre al mismo puerto, sea cual sea la líder
+    pub fn bind_socket(id: u8) -> std::io::Result<UdpSocket> {
+        let mut attempts = 0;
+        loop {
+            match UdpSocket::bind(id_to_addr_resolver(id as u16)) {
+                Err(error)
+                    if error.kind() == ErrorKind::AddrInUse
+                        && attempts < RESOLVER_BIND_ATTEMPTS =>
+                {
+                    if attempts == 0 {
+                        info!(
+                            "[Resolver {}] El puerto lo tiene el resolver de otra lider, espero que lo suelte",
+                            id
+                        );
+                    }
+                    attempts += 1;
+                    std::thread::sleep(FENCE_CHECK_INTERVAL);
+                }
+                result => return result,
+            }
         }
     }
 
@@ -305,12 +1331,64 @@ impl OrderResolver {
         Ok(())
     }
 
-    // Recibe respuesta del Robot
-
-    pub fn receive_order_result_ka(
+    // Le envía el pedido al robot y lo agrega a los que espera
+    fn delegate(
         &self,
         robot_id: u8,
-    ) -> Result<Option<OrderResult>, ScreenError> {
+        order: OrderActorMessage,
+        batch: &mut RobotBatch,
+    ) -> Result<(), ScreenError> {
+        let order_msg = RobotOrder::new(self.id, order.to_order(self.term));
+        self.send_order_to_robot(order_msg, id_to_addr_robot(robot_id as u16))?;
+        batch.push(order);
+        Ok(())
+    }
+
+    // Le envía al robot los pedidos que le agregó el coordinador. Si no hay nuevos ni
+    // quedan pendientes deja de tomarlos, y devuelve false.
+    fn take_new_orders(&self, robot_id: u8, batch: &mut RobotBatch) -> Result<bool, ScreenError> {
+        let mut inbox = self.inbox.lock()?;
+        let orders = std::mem::take(&mut inbox.orders);
+        if orders.is_empty() && batch.pending.is_empty() {
+            inbox.listening = false;
+            return Ok(false);
+        }
+        drop(inbox);
+        for order in orders {
+            self.delegate(robot_id, order, batch)?;
+        }
+        Ok(true)
+    }
+
+    // Deja de tomar pedidos del coordinador. Los que llegó a agregarle y no se enviaron
+    // quedan pendientes, para devolverlos con el resto.
+    fn stop_listening(&self, batch: &mut RobotBatch) -> Result<(), ScreenError> {
+        let mut inbox = self.inbox.lock()?;
+        inbox.listening = false;
+        for order in inbox.orders.drain(..) {
+            batch.push(order);
+        }
+        Ok(())
+    }
+
+    // Recibe las respuestas del Robot hasta que no queden pedidos pendientes, enviándole los
+    // que le agregue el coordinador mientras tanto. Devuelve false si el robot no respondió.
+    pub fn receive_order_results_ka(
+        &mut self,
+        robot_id: u8,
+        batch: &mut RobotBatch,
+    ) -> Result<bool, ScreenError> {
+        self.inbox.lock()?.listening = true;
+        let alive = self.wait_order_results(robot_id, batch);
+        self.stop_listening(batch)?;
+        alive
+    }
+
+    fn wait_order_results(
+        &mut self,
+        robot_id: u8,
+        batch: &mut RobotBatch,
+    ) -> Result<bool, ScreenError> {
         debug!(
             "[Resolver {}] Esperando respuesta del robot {}:",
             self.id, robot_id
@@ -319,9 +1397,24 @@ impl OrderResolver {
 
         let mut waiting_keepalive = false;
         let mut buf = [0; 1024];
+        // el tiempo que el robot estuvo sin pedidos no cuenta
+        self.robot_detector.restart();
+        let mut silent_until = Instant::now() + self.robot_detector.timeout();
 
-        loop {
-            match self.socket.recv_from(&mut buf) {
+        // si destituyen a la líder, los resultados que falten los recibe el resolver de la nueva
+        while !is_deposed(&self.fence) && self.take_new_orders(robot_id, batch)? {
+            // no espera más que FENCE_CHECK_INTERVAL para tomar a tiempo los pedidos nuevos
+            self.socket.set_read_timeout(Some(
+                silent_until
+                    .saturating_duration_since(Instant::now())
+                    .clamp(Duration::from_millis(1), FENCE_CHECK_INTERVAL),
+            ))?;
+            let received = self.socket.recv_from(&mut buf);
+            if received.is_ok() {
+                self.robot_detector.heartbeat();
+                silent_until = Instant::now() + self.robot_detector.timeout();
+            }
+            match received {
                 Ok((size, from)) => match buf[0] {
                     x if x == Messages::Alive as u8 => {
                         let alive = Alive::from_bytes(&buf[1..size]);
@@ -331,6 +1424,64 @@ impl OrderResolver {
                         );
                         waiting_keepalive = false;
                     }
+                    x if x == Messages::RobotQueueStatus as u8 => {
+                        let status = RobotQueueStatus::from_bytes(&buf[1..size]);
+                        info!(
+                            "[Resolver {}] El robot {} tiene {} pedido(s) en cola",
+                            self.id, status.owner, status.depth
+                        );
+                        self.order_coordinator.do_send(QueueDepthActorMessage {
+                            robot_id: status.owner,
+                            depth: status.depth,
+                        });
+                        let key = (status.screen_id, status.order_id);
+                        if !status.accepted && batch.pending.remove(&key) {
+                            batch.rejected.push(key);
+                        }
+                        waiting_keepalive = false;
+                    }
+                    x if x == Messages::OrderProgress as u8 => {
+                        let order_progress = OrderProgress::from_bytes(&buf[1..size])?;
+                        debug!(
+                            "[Resolver {}] El robot {} ya sirvio {:?} del pedido ({}, {})",
+                            self.id,
+                            robot_id,
+                            order_progress.served,
+                            order_progress.screen_id,
+                            order_progress.order_id
+                        );
+                        // el ultimo gusto servido indica por donde paso ese token
+                        if let Some((ice_cream, _)) = order_progress.served.last() {
+                            self.order_coordinator.do_send(TokenSightingActorMessage {
+                                robot_id: order_progress.owner,
+                                ice_cream: *ice_cream,
+                            });
+                        }
+                        let key = (order_progress.screen_id, order_progress.order_id);
+                        if batch.pending.contains(&key) {
+                            batch.progress.insert(key, order_progress.served);
+                        }
+                        waiting_keepalive = false;
+                    }
+                    x if x == Messages::CachedOrderResult as u8 => {
+                        // el robot ya lo habia preparado, no espera ACK
+                        let order_result = OrderResult::from_bytes(&buf[1..size])?;
+                        info!(
+                            "[Resolver {}] El robot {} ya habia preparado el pedido: {:?}",
+                            self.id, robot_id, order_result
+                        );
+                        if batch
+                            .pending
+                            .remove(&(order_result.owner_id, order_result.order_id))
+                        {
+                            self.send_result_to_screen(order_result, order_result.owner_id)?;
+                        }
+                        waiting_keepalive = false;
+                    }
+                    x if x == Messages::StaleTerm as u8 => {
+                        self.report_stale_term(&buf[1..size])?;
+                        return Ok(true);
+                    }
                     x if x == Messages::OrderResult as u8 => {
                         let order_result = OrderResult::from_bytes(&buf[1..size])?;
                         info!(
@@ -338,7 +1489,13 @@ impl OrderResolver {
                                 self.id, robot_id, order_result
                             );
                         self.socket.send_to(&[Messages::Ack as u8], from)?;
-                        return Ok(Some(order_result));
+                        if batch
+                            .pending
+                            .remove(&(order_result.owner_id, order_result.order_id))
+                        {
+                            self.send_result_to_screen(order_result, order_result.owner_id)?;
+                        }
+                        waiting_keepalive = false;
                     }
                     _ => {
                         error!(
@@ -349,11 +1506,22 @@ impl OrderResolver {
                         );
                     }
                 },
+                Err(ref e)
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        && Instant::now() < silent_until => {}
                 Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                     // Timeout alcanzado sin recibir nada
+                    debug!(
+                        "[Resolver {}] El robot {} lleva {:?} sin responder (phi {:.1})",
+                        self.id,
+                        robot_id,
+                        self.robot_detector.timeout(),
+                        self.robot_detector.phi()
+                    );
                     if waiting_keepalive {
                         // No hay respuesta al keepalive, asumir que el robot está muerto
-                        break;
+                        info!("[Resolver {}] El robot {} no respondió, asumiendo que está muerto y reenviando los pedidos", self.id, robot_id);
+                        return Ok(false);
                     } else {
                         // Enviar mensaje keep_alive al robot
                         let keep_alive = KeepAliveFromResolver::new(self.id);
@@ -361,12 +1529,95 @@ impl OrderResolver {
                         self.socket.send_to(&keep_alive_msg, robot_addr)?;
                         waiting_keepalive = true;
                     }
+                    silent_until = Instant::now() + self.robot_detector.timeout();
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+        Ok(true)
+    }
+
+    // Le avisa al robot de la nueva líder y espera su registro: ROBOTWITHORDER,
+    // ROBOTAVAILABLE o STALETERM. Mientras tanto el robot puede seguir reenviando resultados
+    // de pedidos de la líder anterior; se confirman y se guardan para informarlos con el
+    // registro. Si el robot sigue vivo pero no llega su respuesta, le vuelve a mandar el
+    // NEWLEADER. Devuelve None si el robot no responde.
+    fn await_robot_registration(
+        &mut self,
+        early_results: &mut Vec<OrderResult>,
+    ) -> Result<Option<(Messages, Vec<u8>)>, ScreenError> {
+        let robot_addr = id_to_addr_robot(self.id as u16);
+        let new_leader = NewLeader::new(self.id, self.leader_id, self.term).as_bytes();
+        self.socket.send_to(&new_leader, robot_addr)?;
+        let mut deadline = Instant::now() + self.robot_detector.timeout();
+        let mut heard = false;
+        let mut buf = [0; 1024];
+        loop {
+            if Instant::now() >= deadline {
+                if !heard {
+                    return Ok(None);
                 }
+                debug!(
+                    "[Resolver {}] El robot no respondio al NEWLEADER, se lo reenvio",
+                    self.id
+                );
+                self.socket.send_to(&new_leader, robot_addr)?;
+                deadline = Instant::now() + self.robot_detector.timeout();
+                heard = false;
+            }
+            self.socket.set_read_timeout(Some(
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .max(Duration::from_millis(1)),
+            ))?;
+            let (size, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(error) if error.kind() == ErrorKind::WouldBlock => continue,
                 Err(error) => return Err(error.into()),
+            };
+            heard = true;
+            let msg = match Messages::try_from(buf[0]) {
+                Ok(msg) => msg,
+                Err(_) => continue,
+            };
+            match msg {
+                Messages::RobotWithOrder | Messages::RobotAvailable | Messages::StaleTerm => {
+                    return Ok(Some((msg, buf[1..size].to_vec())));
+                }
+                Messages::OrderResult | Messages::CachedOrderResult => {
+                    let result = OrderResult::from_bytes(&buf[1..size])?;
+                    if msg == Messages::OrderResult {
+                        self.socket.send_to(&[Messages::Ack as u8], from)?;
+                    }
+                    info!(
+                        "[Resolver {}] El robot me entrego {:?} antes de registrarse, lo guardo",
+                        self.id, result
+                    );
+                    let key = (result.owner_id, result.order_id);
+                    if !early_results
+                        .iter()
+                        .any(|early| (early.owner_id, early.order_id) == key)
+                    {
+                        early_results.push(result);
+                    }
+                }
+                _ => debug!(
+                    "[Resolver {}] Ignoro un {:?} mientras espero el registro del robot",
+                    self.id, msg
+                ),
             }
         }
-        info!("[Resolver {}] El robot {} no respondió, asumiendo que está muerto y reenviando el pedido", self.id, robot_id);
-        Ok(None)
+    }
+
+    // El robot conoce un mandato más nuevo, la líder queda destituida
+    fn report_stale_term(&self, buffer: &[u8]) -> Result<(), ScreenError> {
+        let stale = StaleTerm::from_bytes(buffer)?;
+        info!(
+            "[Resolver {}] El robot {} ya esta en el mandato {}, el mio es el {}",
+            self.id, stale.owner, stale.term, self.term
+        );
+        depose(&self.fence, stale.term);
+        Ok(())
     }
 
     fn send_result_to_screen(
@@ -382,25 +1633,67 @@ impl OrderResolver {
             "[Resolver {}] Envíe resultado a la screen {:?}",
             self.id, screen_addr
         );
+        self.order_coordinator.do_send(ExecutedOrderActorMessage {
+            result: order_result,
+        });
         Ok(())
     }
 
+    // Le encola al robot todos los pedidos y espera sus resultados. Devuelve si el robot
+    // sigue vivo junto con lo que se le delegó.
     fn communicate_with_robot(
-        &self,
+        &mut self,
         robot_id: u8,
-        order: OrderActorMessage,
-    ) -> Result<bool, ScreenError> {
-        let robot_addr = id_to_addr_robot(robot_id as u16);
-        let order_msg = RobotOrder::new(self.id, order.screen_owner, order.order_id, order.items);
+        orders: Vec<OrderActorMessage>,
+    ) -> Result<(bool, RobotBatch), ScreenError> {
+        let mut batch = RobotBatch::default();
+        for order in orders {
+            self.delegate(robot_id, order, &mut batch)?;
+        }
 
-        self.send_order_to_robot(order_msg, robot_addr)?;
+        let alive = self.receive_order_results_ka(robot_id, &mut batch)?;
+        Ok((alive, batch))
+    }
 
-        if let Some(order_result) = self.receive_order_result_ka(robot_id)? {
-            self.send_result_to_screen(order_result, order.screen_owner)?;
-            Ok(true)
-        } else {
-            Ok(false)
+    // Le informa al coordinador cómo terminó el lote. Si el robot sigue vivo lo libera y le
+    // devuelve los pedidos que rechazó. Si se cayó, le pasa los que no entregó junto con lo
+    // que ya había descontado de cada uno, para que el coordinador los reparta o los aborte.
+    fn report_batch(&self, robot_id: u8, alive: bool, batch: RobotBatch) {
+        let unresolved = batch.unresolved();
+        if alive {
+            info!(
+                "[Resolver {}] comunico al coordinator que el robot {} termino los pedidos {:?}",
+                self.id, robot_id, batch.keys
+            );
+            self.order_coordinator.do_send(FreeRobotActorMessage {
+                robot_id,
+                orders: batch.keys,
+            });
+            // los que rechazo el robot vuelven a la cola del coordinator
+            for order in unresolved {
+                self.order_coordinator.do_send(order);
+            }
+            return;
         }
+        let lost: Vec<(u8, u8)> = batch
+            .keys
+            .into_iter()
+            .filter(|key| batch.pending.contains(key) || batch.rejected.contains(key))
+            .collect();
+        let progress = batch
+            .progress
+            .into_iter()
+            .filter(|(key, _)| lost.contains(key))
+            .collect();
+        info!(
+            "[Resolver {}] comunico al coordinator que el robot {} esta caido y los pedidos perdidos {:?}",
+            self.id, robot_id, lost
+        );
+        self.order_coordinator.do_send(FallenRobotActorMessage {
+            robot_id,
+            orders: lost,
+            progress,
+        });
     }
 }
 
@@ -417,24 +1710,13 @@ impl Handler<RobotOrderActorMessage> for OrderResolver {
         ctx: &mut Self::Context,
     ) -> Self::Result {
         debug!(
-            "[Resolver {}] Recibi el pedido {:?}",
-            self.id, robot_order.order
+            "[Resolver {}] Recibi los pedidos {:?}",
+            self.id, robot_order.orders
         );
-        let order = robot_order.order;
         let robot_id = robot_order.robot_id;
-        if self.communicate_with_robot(robot_id, order.clone())? {
-            info!(
-                "[Resolver {}] comunico al coordinator que el robot {} termino el pedido {:?}",
-                self.id, robot_id, order
-            );
-            self.order_coordinator.do_send(FreeRobotActorMessage {
-                robot_id,
-                order: (order.screen_owner, order.order_id),
-            });
-        } else {
-            info!("[Resolver {}] comunico al coordinator que el robot {} esta caido y la orden perdida {:?}", self.id, robot_id, order);
-            self.order_coordinator
-                .do_send(FallenRobotActorMessage { robot_id, order });
+        let (alive, batch) = self.communicate_with_robot(robot_id, robot_order.orders)?;
+        self.report_batch(robot_id, alive, batch);
+        if !alive {
             ctx.stop();
         }
         Ok(())
@@ -445,14 +1727,22 @@ impl Handler<Inspect> for OrderResolver {
     type Result = Result<(), ScreenError>;
 
     fn handle(&mut self, robot_order: Inspect, ctx: &mut Self::Context) -> Self::Result {
-        self.socket.send_to(
-            &NewLeader::new(self.id).as_bytes(),
-            id_to_addr_robot(self.id as u16),
-        )?;
-        let mut buf = [0; 1024];
-        let (size, _) = match self.socket.recv_from(&mut buf) {
-            Ok(result) => result,
-            Err(error) if error.kind() == ErrorKind::WouldBlock => {
+        let mut early_results = Vec::new();
+        let reply = self.await_robot_registration(&mut early_results)?;
+        // los pedidos que el robot entregó antes de registrarse ya están preparados
+        for result in early_results.iter() {
+            self.socket.send_to(
+                &result.as_bytes(),
+                id_to_addr_screen(result.owner_id as u16),
+            )?;
+        }
+        let early_keys: Vec<(u8, u8)> = early_results
+            .iter()
+            .map(|result| (result.owner_id, result.order_id))
+            .collect();
+        let (msg, body) = match reply {
+            Some(reply) => reply,
+            None => {
                 info!(
                     "[Resolver {}] Le mando al Coordinator mi registro con robot caido",
                     self.id
@@ -461,27 +1751,45 @@ impl Handler<Inspect> for OrderResolver {
                     address: robot_order.resolver,
                     resolver_id: self.id,
                     robot_availability: false,
-                    order: None,
+                    orders: vec![],
+                    executed: early_results,
+                    capabilities: None,
+                    inbox: self.inbox.clone(),
                 })?;
                 ctx.stop();
                 return Ok(());
             }
-            Err(error) => return Err(error.into()),
         };
-        if let Ok(msg) = Messages::try_from(buf[0]) {
-            if msg == Messages::RobotWithOrder {
-                let order = RobotWithOrder::from_bytes(&buf[1..size]);
+        match msg {
+            Messages::RobotWithOrder => {
+                let robot_with_order = RobotWithOrder::from_bytes(&body)?;
+                let orders: Vec<(u8, u8)> = robot_with_order
+                    .orders
+                    .into_iter()
+                    .filter(|key| !early_keys.contains(key))
+                    .collect();
+                let mut executed = robot_with_order.executed;
+                executed.retain(|result| !early_keys.contains(&(result.owner_id, result.order_id)));
+                executed.extend(early_results);
+                let robot_availability = orders.is_empty();
                 info!(
-                    "[Resolver {}] Le mando al Coordinator mi registro con robot trabajando",
-                    self.id
+                    "[Resolver {}] Le mando al Coordinator mi registro con robot {} y {} pedido(s) ya preparado(s)",
+                    self.id,
+                    if robot_availability { "libre" } else { "trabajando" },
+                    executed.len()
                 );
                 self.order_coordinator.try_send(RegisterResolver {
                     address: robot_order.resolver,
                     resolver_id: self.id,
-                    robot_availability: false,
-                    order: Some((order.screen_id, order.order_id)),
+                    robot_availability,
+                    orders,
+                    executed,
+                    capabilities: Some(robot_with_order.capabilities),
+                    inbox: self.inbox.clone(),
                 })?;
-            } else {
+            }
+            Messages::RobotAvailable => {
+                let robot_available = RobotAvailable::from_bytes(&body)?;
                 info!(
                     "[Resolver {}] Le mando al Coordinator mi registro con robot libre",
                     self.id
@@ -490,10 +1798,14 @@ impl Handler<Inspect> for OrderResolver {
                     address: robot_order.resolver,
                     resolver_id: self.id,
                     robot_availability: true,
-                    order: None,
+                    orders: vec![],
+                    executed: early_results,
+                    capabilities: Some(robot_available.capabilities),
+                    inbox: self.inbox.clone(),
                 })?;
-            };
-        };
+            }
+            _ => self.report_stale_term(&body)?,
+        }
         Ok(())
     }
 }
@@ -501,19 +1813,14 @@ impl Handler<Inspect> for OrderResolver {
 impl Handler<ListenRobot> for OrderResolver {
     type Result = Result<(), ScreenError>;
 
-    fn handle(&mut self, msg: ListenRobot, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: ListenRobot, ctx: &mut Self::Context) -> Self::Result {
         debug!("[Resolver {}] Me llego un  ListenRobot", self.id);
-        if let Some(order_result) = self.receive_order_result_ka(self.id)? {
-            self.send_result_to_screen(order_result, msg.order.0)?;
-            debug!(
-                "[Resolver {}] comunico al coordinator que el robot {} terminó el pedido {:?}",
-                self.id, self.id, msg.order
-            );
-            self.order_coordinator.do_send(FreeRobotActorMessage {
-                robot_id: self.id,
-                order: msg.order,
-            });
-        };
+        let mut batch = RobotBatch::from_keys(msg.orders);
+        let alive = self.receive_order_results_ka(self.id, &mut batch)?;
+        self.report_batch(self.id, alive, batch);
+        if !alive {
+            ctx.stop();
+        }
         Ok(())
     }
 }
@@ -0,0 +1,58 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{net::Ipv4Addr, path::PathBuf};
+
+use crate::{
+    screen_lib::screen::OrderState,
+    utils::{
+        errors::ScreenError,
+        messages::{Order, Term},
+        persistence::{load_json, save_json},
+    },
+};
+
+/// Directorio donde cada pantalla guarda su checkpoint.
+pub const CHECKPOINT_DIR: &str = "checkpoints";
+
+/// Pedido en curso tal como queda guardado. Los instantes se guardan en milisegundos desde
+/// el epoch para que sigan valiendo en otro proceso.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CheckpointOrder {
+    pub order: Order,
+    pub state: OrderState,
+    pub taken_at: u64,
+    pub cancel_at: Option<u64>,
+}
+
+/// Lo que necesita una pantalla reiniciada para seguir donde estaba: hasta qué línea de la
+/// fuente ya tomó pedidos, el próximo id de pedido, la líder que conocía con su mandato y los
+/// pedidos que todavía no terminó de cobrar.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScreenCheckpoint {
+    pub source: String,
+    pub line: usize,
+    pub next_order_id: u8,
+    pub leader: (Ipv4Addr, u16),
+    #[serde(default)]
+    pub term: Term,
+    pub orders: Vec<CheckpointOrder>,
+}
+
+impl ScreenCheckpoint {
+    fn path(id: u8) -> PathBuf {
+        PathBuf::from(CHECKPOINT_DIR).join(format!("screen_{}.json", id))
+    }
+
+    /// Lee el checkpoint de la pantalla. Si no existe o está corrupto arranca de cero.
+    pub fn load(id: u8) -> Option<ScreenCheckpoint> {
+        let path = Self::path(id);
+        let checkpoint = load_json(&path, &format!("[Screen {}]", id))?;
+        info!("[Screen {}] Cargue el checkpoint {:?}", id, path);
+        Some(checkpoint)
+    }
+
+    pub fn save(&self, id: u8) -> Result<(), ScreenError> {
+        save_json(&Self::path(id), self)?;
+        Ok(())
+    }
+}
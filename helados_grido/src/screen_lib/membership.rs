@@ -0,0 +1,68 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::utils::failure_detector::FailureDetector;
+
+/// Si una pantalla pasa este tiempo sin responder los latidos de la líder, la líder la saca
+/// de las elecciones. Es el plazo inicial de su detector de fallas.
+pub const MEMBER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Las pantallas a las que el LeaderReceiver les manda los latidos. La pantalla líder la
+/// actualiza cuando alguna entra o sale.
+pub type SharedMembers = Arc<RwLock<Vec<u8>>>;
+
+/// Las pantallas ordenadas y sin repetir, siempre con la propia.
+pub fn normalize(id: u8, mut members: Vec<u8>) -> Vec<u8> {
+    members.push(id);
+    members.sort_unstable();
+    members.dedup();
+    members
+}
+
+/// Lado de la líder: un detector de fallas por pantalla, que se alimenta con las respuestas
+/// a sus latidos.
+#[derive(Debug, Default)]
+pub struct MemberWatch {
+    detectors: BTreeMap<u8, FailureDetector>,
+}
+
+impl MemberWatch {
+    pub fn new(id: u8, members: &[u8]) -> Self {
+        let mut watch = MemberWatch::default();
+        for member in members.iter().filter(|member| **member != id) {
+            watch.watch(*member);
+        }
+        watch
+    }
+
+    /// Empieza a esperar las respuestas de la pantalla, o las vuelve a esperar desde ahora
+    /// si ya la vigilaba.
+    pub fn watch(&mut self, member: u8) {
+        self.detectors
+            .entry(member)
+            .or_insert_with(|| FailureDetector::new(MEMBER_TIMEOUT))
+            .restart();
+    }
+
+    pub fn alive(&mut self, member: u8) {
+        if let Some(detector) = self.detectors.get_mut(&member) {
+            detector.heartbeat();
+        }
+    }
+
+    pub fn forget(&mut self, member: u8) {
+        self.detectors.remove(&member);
+    }
+
+    /// Las pantallas que dejaron de responder los latidos.
+    pub fn departed(&self) -> Vec<u8> {
+        self.detectors
+            .iter()
+            .filter(|(_, detector)| detector.is_suspected())
+            .map(|(member, _)| *member)
+            .collect()
+    }
+}
@@ -1,2 +1,10 @@
 pub mod actors;
+pub mod checkpoint;
+pub mod membership;
+pub mod order_log;
+pub mod order_source;
+pub mod raft;
+pub mod scheduling;
 pub mod screen;
+pub mod standby;
+pub mod validation;
@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    robot_lib::robot::EXECUTED_ORDERS_CAPACITY,
+    screen_lib::actors::RestoreOrdersActorMessage,
+    utils::{
+        errors::ParseError,
+        messages::{Order, OrderResult},
+    },
+};
+
+/// Lo que le pasa a la cola del coordinador, en el orden en que pasa. La líder se lo pasa a
+/// su pantalla para replicarlo, con Raft o a la pantalla de respaldo.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum OrderCommand {
+    // el coordinador tomó un pedido nuevo
+    Accepted(Order),
+    // el pedido se le delegó a un robot
+    Assigned {
+        screen_id: u8,
+        order_id: u8,
+        robot_id: u8,
+    },
+    // el robot lo devolvió sin prepararlo, o se cayó, y el pedido vuelve a la cola
+    Released {
+        screen_id: u8,
+        order_id: u8,
+    },
+    // el pedido se preparó o se abortó
+    Resolved(OrderResult),
+}
+
+impl OrderCommand {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            OrderCommand::Accepted(order) => {
+                let mut buf_msg = vec![0];
+                buf_msg.extend_from_slice(&order.as_bytes()[1..]);
+                buf_msg
+            }
+            OrderCommand::Resolved(result) => {
+                let mut buf_msg = vec![1];
+                buf_msg.extend_from_slice(&result.as_bytes()[1..]);
+                buf_msg
+            }
+            OrderCommand::Assigned {
+                screen_id,
+                order_id,
+                robot_id,
+            } => vec![2, *screen_id, *order_id, *robot_id],
+            OrderCommand::Released {
+                screen_id,
+                order_id,
+            } => vec![3, *screen_id, *order_id],
+        }
+    }
+
+    // Largo en bytes del comando serializado
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            OrderCommand::Accepted(order) => 1 + order.encoded_len(),
+            OrderCommand::Resolved(_) => 5,
+            OrderCommand::Assigned { .. } => 4,
+            OrderCommand::Released { .. } => 3,
+        }
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<OrderCommand, ParseError> {
+        let kind = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        let payload = &buffer[1..];
+        let byte = |i: usize| payload.get(i).copied().ok_or(ParseError::ConversionError);
+        match kind {
+            0 => Ok(OrderCommand::Accepted(Order::try_from_bytes(payload)?)),
+            1 => Ok(OrderCommand::Resolved(OrderResult::from_bytes(payload)?)),
+            2 => Ok(OrderCommand::Assigned {
+                screen_id: byte(0)?,
+                order_id: byte(1)?,
+                robot_id: byte(2)?,
+            }),
+            3 => Ok(OrderCommand::Released {
+                screen_id: byte(0)?,
+                order_id: byte(1)?,
+            }),
+            _ => Err(ParseError::ConversionError),
+        }
+    }
+}
+
+/// La cola del coordinador armada a partir de sus cambios: los pedidos aceptados que todavía
+/// no se resolvieron, en el orden en que llegaron, qué robot tiene cada uno y los últimos
+/// resultados.
+#[derive(Debug, Default, Clone)]
+pub struct QueueMirror {
+    accepted: Vec<Order>,
+    assigned: HashMap<(u8, u8), u8>,
+    executed: VecDeque<OrderResult>,
+}
+
+impl QueueMirror {
+    pub fn apply(&mut self, command: &OrderCommand) {
+        match command {
+            OrderCommand::Accepted(order) => {
+                let key = (order.screen_id, order.order_id);
+                if !self
+                    .accepted
+                    .iter()
+                    .any(|accepted| (accepted.screen_id, accepted.order_id) == key)
+                {
+                    self.accepted.push(order.clone());
+                }
+            }
+            OrderCommand::Assigned {
+                screen_id,
+                order_id,
+                robot_id,
+            } => {
+                self.assigned.insert((*screen_id, *order_id), *robot_id);
+            }
+            OrderCommand::Released {
+                screen_id,
+                order_id,
+            } => {
+                self.assigned.remove(&(*screen_id, *order_id));
+            }
+            OrderCommand::Resolved(result) => {
+                let key = (result.owner_id, result.order_id);
+                self.accepted
+                    .retain(|order| (order.screen_id, order.order_id) != key);
+                self.assigned.remove(&key);
+                if self
+                    .executed
+                    .iter()
+                    .any(|executed| (executed.owner_id, executed.order_id) == key)
+                {
+                    return;
+                }
+                self.executed.push_back(*result);
+                if self.executed.len() > EXECUTED_ORDERS_CAPACITY {
+                    self.executed.pop_front();
+                }
+            }
+        }
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.accepted.len()
+    }
+
+    /// Los comandos que llevan una copia vacía a este mismo estado.
+    pub fn commands(&self) -> Vec<OrderCommand> {
+        let mut commands: Vec<OrderCommand> = self
+            .executed
+            .iter()
+            .map(|result| OrderCommand::Resolved(*result))
+            .collect();
+        for order in self.accepted.iter() {
+            commands.push(OrderCommand::Accepted(order.clone()));
+            if let Some(robot_id) = self.assigned.get(&(order.screen_id, order.order_id)) {
+                commands.push(OrderCommand::Assigned {
+                    screen_id: order.screen_id,
+                    order_id: order.order_id,
+                    robot_id: *robot_id,
+                });
+            }
+        }
+        commands
+    }
+
+    /// Lo que necesita el coordinador de una líder nueva para arrancar con esta cola.
+    pub fn restore(&self) -> RestoreOrdersActorMessage {
+        let mut pending = Vec::new();
+        let mut assigned = Vec::new();
+        for order in self.accepted.iter() {
+            match self.assigned.get(&(order.screen_id, order.order_id)) {
+                Some(robot_id) => assigned.push((*robot_id, order.clone())),
+                None => pending.push(order.clone()),
+            }
+        }
+        RestoreOrdersActorMessage {
+            pending,
+            assigned,
+            executed: self.executed.iter().copied().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        robot_lib::icecream::{Container, IceCream},
+        utils::messages::{OrderStatus, Priority},
+    };
+
+    fn accepted() -> OrderCommand {
+        OrderCommand::Accepted(Order::new(
+            1,
+            7,
+            HashMap::from([(IceCream::Lemon, 250)]),
+            Container::Cup,
+            Priority::Normal,
+            1_000,
+            3,
+        ))
+    }
+
+    #[test]
+    fn commands_roundtrip() {
+        let commands = [
+            accepted(),
+            OrderCommand::Resolved(OrderResult::new(OrderStatus::Ready, 7, 1)),
+            OrderCommand::Assigned {
+                screen_id: 1,
+                order_id: 7,
+                robot_id: 2,
+            },
+            OrderCommand::Released {
+                screen_id: 1,
+                order_id: 7,
+            },
+        ];
+        for command in commands {
+            let bytes = command.as_bytes();
+            assert_eq!(bytes.len(), command.encoded_len());
+            let parsed = OrderCommand::from_bytes(&bytes).unwrap();
+            assert_eq!(parsed.as_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn truncated_commands_are_errors() {
+        let resolved = OrderCommand::Resolved(OrderResult::new(OrderStatus::Ready, 7, 1));
+        for command in [accepted(), resolved] {
+            let bytes = command.as_bytes();
+            for len in 0..bytes.len() {
+                assert!(OrderCommand::from_bytes(&bytes[..len]).is_err());
+            }
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use crate::screen_lib::validation::{validate_order, RejectedOrder, RejectionReport};
+use crate::utils::{addresses::id_to_addr_order_input, errors::ScreenError, messages::OrderJson};
+use log::{error, info, warn};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Split, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+/// Cada cuánto el `FollowFileSource` vuelve a mirar si el archivo creció.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Tamaño máximo del cuerpo de un pedido recibido por HTTP.
+const MAX_HTTP_BODY: usize = 64 * 1024;
+
+/// Cuánto espera la fuente HTTP cada parte del request antes de cortar la conexión, para que
+/// un cliente que se conecta y no manda nada no bloquee al resto.
+const HTTP_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Un pedido junto con la línea de la fuente de la que salió.
+#[derive(Debug)]
+pub struct SourcedOrder {
+    pub line: usize,
+    pub order: OrderJson,
+}
+
+/// De dónde toma una pantalla los pedidos de sus clientes.
+pub trait OrderSource: Send {
+    fn describe(&self) -> String;
+
+    /// Si al volver a abrir la fuente se leen los mismos pedidos en las mismas líneas, y
+    /// por lo tanto una pantalla reiniciada puede saltearse los que ya tomó.
+    fn replayable(&self) -> bool {
+        false
+    }
+
+    /// Bloquea hasta que llegue el próximo pedido, o uno que no pasó la validación. Devuelve
+    /// `None` cuando no van a llegar más pedidos.
+    fn next_order(&mut self) -> Option<Result<SourcedOrder, RejectedOrder>>;
+}
+
+fn parse_order(line_number: usize, line: &[u8]) -> Result<SourcedOrder, RejectedOrder> {
+    match validate_order(line) {
+        Ok(order) => Ok(SourcedOrder {
+            line: line_number,
+            order,
+        }),
+        Err(reason) => Err(RejectedOrder::new(line_number, line, reason)),
+    }
+}
+
+/// Lee los pedidos de un archivo JSONL y termina cuando se acaba.
+pub struct FileSource {
+    path: String,
+    lines: Split<BufReader<File>>,
+    line_number: usize,
+}
+
+impl FileSource {
+    pub fn open(path: &str) -> Result<Self, ScreenError> {
+        let file = File::open(path)?;
+        Ok(FileSource {
+            path: path.to_string(),
+            lines: BufReader::new(file).split(b'\n'),
+            line_number: 0,
+        })
+    }
+}
+
+impl OrderSource for FileSource {
+    fn describe(&self) -> String {
+        format!("el archivo {}", self.path)
+    }
+
+    fn replayable(&self) -> bool {
+        true
+    }
+
+    fn next_order(&mut self) -> Option<Result<SourcedOrder, RejectedOrder>> {
+        for line in self.lines.by_ref() {
+            self.line_number += 1;
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    error!("[OrderSource] Error al leer {}: {}", self.path, err);
+                    return None;
+                }
+            };
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            return Some(parse_order(self.line_number, &line));
+        }
+        None
+    }
+}
+
+/// Lee los pedidos de un archivo JSONL y, al llegar al final, se queda esperando que se
+/// agreguen más líneas, como `tail -f`.
+pub struct FollowFileSource {
+    path: String,
+    reader: BufReader<File>,
+    // línea que todavía no se terminó de escribir
+    partial: Vec<u8>,
+    line_number: usize,
+}
+
+impl FollowFileSource {
+    pub fn open(path: &str) -> Result<Self, ScreenError> {
+        let file = File::open(path)?;
+        Ok(FollowFileSource {
+            path: path.to_string(),
+            reader: BufReader::new(file),
+            partial: Vec::new(),
+            line_number: 0,
+        })
+    }
+}
+
+impl OrderSource for FollowFileSource {
+    fn describe(&self) -> String {
+        format!("el archivo {} (siguiendo)", self.path)
+    }
+
+    fn replayable(&self) -> bool {
+        true
+    }
+
+    fn next_order(&mut self) -> Option<Result<SourcedOrder, RejectedOrder>> {
+        loop {
+            match self.reader.read_until(b'\n', &mut self.partial) {
+                Ok(0) => thread::sleep(FOLLOW_POLL_INTERVAL),
+                Ok(_) if self.partial.ends_with(b"\n") => {
+                    self.line_number += 1;
+                    let line = std::mem::take(&mut self.partial);
+                    if line.trim_ascii().is_empty() {
+                        continue;
+                    }
+                    return Some(parse_order(self.line_number, &line));
+                }
+                // la línea quedó a medias, se completa en la próxima lectura
+                Ok(_) => thread::sleep(FOLLOW_POLL_INTERVAL),
+                Err(err) => {
+                    error!("[OrderSource] Error al leer {}: {}", self.path, err);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Lee un pedido JSON por línea de la entrada estándar, hasta que se cierre.
+#[derive(Default)]
+pub struct StdinSource {
+    line_number: usize,
+}
+
+impl OrderSource for StdinSource {
+    fn describe(&self) -> String {
+        "la entrada estandar".to_string()
+    }
+
+    fn next_order(&mut self) -> Option<Result<SourcedOrder, RejectedOrder>> {
+        loop {
+            let mut line = Vec::new();
+            match io::stdin().lock().read_until(b'\n', &mut line) {
+                Ok(0) => return None,
+                Ok(_) => {
+                    self.line_number += 1;
+                    if line.trim_ascii().is_empty() {
+                        continue;
+                    }
+                    return Some(parse_order(self.line_number, &line));
+                }
+                Err(err) => {
+                    error!("[OrderSource] Error al leer stdin: {}", err);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Recibe pedidos por HTTP local: cada `POST /orders` con un pedido JSON en el cuerpo es un
+/// cliente nuevo. Responde `202` si lo acepta, `400` si el pedido no es válido y `408` si el
+/// request no llega a tiempo. En el reporte de rechazos, la "línea" es el número de pedido
+/// recibido.
+pub struct HttpSource {
+    listener: TcpListener,
+    received: usize,
+}
+
+impl HttpSource {
+    pub fn bind(addr: impl ToSocketAddrs) -> Result<Self, ScreenError> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(HttpSource {
+            listener,
+            received: 0,
+        })
+    }
+
+    // Lee un request HTTP/1.1 y devuelve el método, la ruta y el cuerpo
+    fn read_request(stream: &mut TcpStream) -> io::Result<(String, String, Vec<u8>)> {
+        stream.set_read_timeout(Some(HTTP_READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        if content_length > MAX_HTTP_BODY {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "el cuerpo del pedido es demasiado grande",
+            ));
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        Ok((method, path, body))
+    }
+
+    fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        if let Err(err) = stream.write_all(response.as_bytes()) {
+            warn!("[OrderSource] No pude responder el request HTTP: {}", err);
+        }
+    }
+
+    fn handle(&mut self, stream: &mut TcpStream) -> Option<Result<SourcedOrder, RejectedOrder>> {
+        let (method, path, body) = match Self::read_request(stream) {
+            Ok(request) => request,
+            Err(err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                warn!("[OrderSource] El cliente HTTP no termino de mandar el request a tiempo");
+                Self::respond(
+                    stream,
+                    "408 Request Timeout",
+                    "{\"error\": \"el request no llego a tiempo\"}",
+                );
+                return None;
+            }
+            Err(err) => {
+                Self::respond(
+                    stream,
+                    "400 Bad Request",
+                    &format!("{{\"error\": \"{}\"}}", err),
+                );
+                return None;
+            }
+        };
+        if path != "/orders" {
+            Self::respond(stream, "404 Not Found", "{\"error\": \"ruta desconocida\"}");
+            return None;
+        }
+        if method != "POST" {
+            Self::respond(
+                stream,
+                "405 Method Not Allowed",
+                "{\"error\": \"solo se aceptan POST\"}",
+            );
+            return None;
+        }
+        self.received += 1;
+        let result = parse_order(self.received, &body);
+        match &result {
+            Ok(_) => Self::respond(stream, "202 Accepted", "{\"accepted\": true}"),
+            Err(rejected) => Self::respond(
+                stream,
+                "400 Bad Request",
+                &format!("{{\"error\": {:?}}}", rejected.reason.to_string()),
+            ),
+        }
+        Some(result)
+    }
+}
+
+impl OrderSource for HttpSource {
+    fn describe(&self) -> String {
+        match self.listener.local_addr() {
+            Ok(addr) => format!("http://{}/orders", addr),
+            Err(_) => "HTTP".to_string(),
+        }
+    }
+
+    fn next_order(&mut self) -> Option<Result<SourcedOrder, RejectedOrder>> {
+        loop {
+            match self.listener.accept() {
+                Ok((mut stream, _)) => {
+                    if let Some(result) = self.handle(&mut stream) {
+                        return Some(result);
+                    }
+                }
+                Err(err) => warn!("[OrderSource] No pude aceptar la conexion: {}", err),
+            }
+        }
+    }
+}
+
+/// Arma la fuente de pedidos a partir del argumento de la pantalla: `stdin` (o `-`),
+/// `http` (en el puerto 7000 + id), `http:<puerto>`, `follow:<archivo>` o un archivo.
+pub fn from_spec(spec: &str, screen_id: u8) -> Result<Box<dyn OrderSource>, ScreenError> {
+    if spec == "-" || spec == "stdin" {
+        return Ok(Box::new(StdinSource::default()));
+    }
+    if spec == "http" {
+        return Ok(Box::new(HttpSource::bind(id_to_addr_order_input(
+            screen_id as u16,
+        ))?));
+    }
+    if let Some(port) = spec.strip_prefix("http:") {
+        let port: u16 = port.parse().map_err(|_| {
+            ScreenError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("puerto invalido: {port}"),
+            ))
+        })?;
+        return Ok(Box::new(HttpSource::bind(("127.0.0.1", port))?));
+    }
+    if let Some(path) = spec.strip_prefix("follow:") {
+        return Ok(Box::new(FollowFileSource::open(path)?));
+    }
+    Ok(Box::new(FileSource::open(spec)?))
+}
+
+/// Corre la fuente en su propio hilo y le pasa los pedidos a la pantalla por un channel,
+/// para que la pantalla siga atendiendo mensajes mientras espera clientes. Los pedidos que
+/// no pasan la validación se anotan en el reporte de rechazos y no llegan a la pantalla. Las
+/// líneas hasta `skip_through` ya se tomaron antes de un reinicio y se saltean. El channel
+/// se cierra cuando la fuente se queda sin pedidos.
+pub fn spawn(
+    mut source: Box<dyn OrderSource>,
+    mut report: RejectionReport,
+    skip_through: usize,
+) -> Receiver<SourcedOrder> {
+    let (orders_tx, orders_rx) = mpsc::channel();
+    thread::spawn(move || {
+        info!("[OrderSource] Tomo los pedidos de {}", source.describe());
+        if skip_through > 0 {
+            info!(
+                "[OrderSource] Salteo hasta la linea {}, ya tomada antes del reinicio",
+                skip_through
+            );
+        }
+        while let Some(result) = source.next_order() {
+            let line = match &result {
+                Ok(order) => order.line,
+                Err(rejected) => rejected.line,
+            };
+            if line <= skip_through {
+                continue;
+            }
+            let order = match result {
+                Ok(order) => order,
+                Err(rejected) => {
+                    error!(
+                        "[OrderSource] Rechazo la linea {} de {}: {} ({})",
+                        rejected.line,
+                        source.describe(),
+                        rejected.reason,
+                        rejected.content
+                    );
+                    if let Err(err) = report.record(&source.describe(), &rejected) {
+                        warn!(
+                            "[OrderSource] No pude escribir el reporte {:?}: {}",
+                            report.path(),
+                            err
+                        );
+                    }
+                    continue;
+                }
+            };
+            if orders_tx.send(order).is_err() {
+                return;
+            }
+        }
+        info!("[OrderSource] No quedan pedidos en {}", source.describe());
+    });
+    orders_rx
+}
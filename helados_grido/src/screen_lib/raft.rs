@@ -0,0 +1,640 @@
+use log::{info, warn};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    screen_lib::{
+        checkpoint::CHECKPOINT_DIR,
+        order_log::{OrderCommand, QueueMirror},
+        screen::MAX_COMMANDS_PER_MESSAGE,
+    },
+    utils::{
+        errors::{ParseError, ScreenError},
+        messages::Term,
+        persistence::{load_json, save_json},
+    },
+};
+
+/// Variable de entorno que elige cómo se elige a la líder: `bully` (por defecto) o `raft`.
+pub const ELECTION_VAR: &str = "ELECTION";
+
+/// Cada cuánto la líder les manda AppendEntries a las demás pantallas aunque no haya
+/// entradas nuevas.
+pub const RAFT_HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+
+// Una pantalla que no recibe latidos durante este tiempo se postula. Es al azar dentro del
+// rango para que no se postulen todas a la vez.
+const ELECTION_TIMEOUT_MIN_MS: u64 = 3000;
+const ELECTION_TIMEOUT_MAX_MS: u64 = 6000;
+
+// Entradas confirmadas que se juntan en el log antes de compactarlas en el snapshot
+const COMPACT_THRESHOLD: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElectionMode {
+    Bully,
+    Raft,
+}
+
+/// Lee el modo de elección de la variable de entorno `ELECTION`. Si no está definida o no
+/// se reconoce se usa Bully.
+pub fn election_mode_from_env() -> ElectionMode {
+    match env::var(ELECTION_VAR).as_deref() {
+        Ok("raft") => ElectionMode::Raft,
+        Ok("bully") | Err(_) => ElectionMode::Bully,
+        Ok(other) => {
+            warn!(
+                "[Screen] No conozco el modo de eleccion {:?}, uso bully",
+                other
+            );
+            ElectionMode::Bully
+        }
+    }
+}
+
+/// Entrada del log replicado, con el mandato de la líder que la agregó.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogEntry {
+    pub term: Term,
+    pub command: OrderCommand,
+}
+
+impl LogEntry {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf_msg = self.term.to_be_bytes().to_vec();
+        buf_msg.extend_from_slice(&self.command.as_bytes());
+        buf_msg
+    }
+
+    // Largo en bytes de la entrada serializada
+    pub fn encoded_len(&self) -> usize {
+        4 + self.command.encoded_len()
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<LogEntry, ParseError> {
+        let term = buffer.get(..4).ok_or(ParseError::ConversionError)?;
+        let term = Term::from_be_bytes(term.try_into()?);
+        let command = OrderCommand::from_bytes(&buffer[4..])?;
+        Ok(LogEntry { term, command })
+    }
+}
+
+// Lo que se guarda en disco: en Raft el mandato, el voto y el log tienen que sobrevivir a un
+// reinicio para no votar dos veces en un mandato ni perder entradas confirmadas. Las entradas
+// ya compactadas se guardan como los comandos que rehacen la cola hasta `snapshot_index`.
+#[derive(Serialize, Deserialize, Default)]
+struct RaftCheckpoint {
+    term: Term,
+    voted_for: Option<u8>,
+    #[serde(default)]
+    snapshot_index: usize,
+    #[serde(default)]
+    snapshot_term: Term,
+    #[serde(default)]
+    snapshot: Vec<OrderCommand>,
+    log: Vec<LogEntry>,
+}
+
+// Snapshot que la líder está mandando en pedazos
+#[derive(Debug)]
+struct IncomingSnapshot {
+    last_index: usize,
+    last_term: Term,
+    next_seq: u32,
+    mirror: QueueMirror,
+}
+
+/// Estado de Raft de una pantalla. Los índices del log empiezan en 1; el 0 es el log vacío.
+/// Las entradas confirmadas se compactan en `snapshot`, y `log` tiene solo las que siguen a
+/// `snapshot_index`.
+#[derive(Debug)]
+pub struct RaftState {
+    pub voted_for: Option<u8>,
+    snapshot: QueueMirror,
+    snapshot_index: usize,
+    snapshot_term: Term,
+    log: Vec<LogEntry>,
+    pub commit_index: usize,
+    // votos recibidos como candidata en el mandato actual
+    votes: HashSet<u8>,
+    // como líder: próxima entrada a mandarle y hasta dónde coincide el log de cada pantalla
+    next_index: HashMap<u8, usize>,
+    match_index: HashMap<u8, usize>,
+    incoming: Option<IncomingSnapshot>,
+    pub election_deadline: Instant,
+    pub next_heartbeat: Instant,
+}
+
+impl RaftState {
+    fn path(id: u8) -> PathBuf {
+        PathBuf::from(CHECKPOINT_DIR).join(format!("raft_{}.json", id))
+    }
+
+    fn new(voted_for: Option<u8>) -> RaftState {
+        RaftState {
+            voted_for,
+            snapshot: QueueMirror::default(),
+            snapshot_index: 0,
+            snapshot_term: 0,
+            log: Vec::new(),
+            commit_index: 0,
+            votes: HashSet::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            incoming: None,
+            election_deadline: Instant::now() + random_election_timeout(),
+            next_heartbeat: Instant::now(),
+        }
+    }
+
+    /// Lee el estado guardado de la pantalla, o arranca con el log vacío. Devuelve también
+    /// el último mandato que conocía.
+    pub fn load(id: u8) -> (Term, RaftState) {
+        let saved: RaftCheckpoint =
+            load_json(&Self::path(id), &format!("[Screen {}]", id)).unwrap_or_default();
+        if saved.snapshot_index > 0 || !saved.log.is_empty() {
+            info!(
+                "[Screen {}] Cargue el log de Raft hasta la entrada {} ({} sin compactar) del mandato {}",
+                id,
+                saved.snapshot_index + saved.log.len(),
+                saved.log.len(),
+                saved.term
+            );
+        }
+        let mut raft = RaftState::new(saved.voted_for);
+        for command in saved.snapshot.iter() {
+            raft.snapshot.apply(command);
+        }
+        raft.snapshot_index = saved.snapshot_index;
+        raft.snapshot_term = saved.snapshot_term;
+        // lo compactado ya estaba confirmado
+        raft.commit_index = saved.snapshot_index;
+        raft.log = saved.log;
+        (saved.term, raft)
+    }
+
+    /// Guarda el mandato, el voto, el snapshot y el resto del log, igual que el checkpoint
+    /// de la pantalla.
+    pub fn save(&self, id: u8, term: Term) -> Result<(), ScreenError> {
+        let saved = RaftCheckpoint {
+            term,
+            voted_for: self.voted_for,
+            snapshot_index: self.snapshot_index,
+            snapshot_term: self.snapshot_term,
+            snapshot: self.snapshot.commands(),
+            log: self.log.clone(),
+        };
+        save_json(&Self::path(id), &saved)?;
+        Ok(())
+    }
+
+    pub fn last_index(&self) -> usize {
+        self.snapshot_index + self.log.len()
+    }
+
+    pub fn last_term(&self) -> Term {
+        self.log
+            .last()
+            .map_or(self.snapshot_term, |entry| entry.term)
+    }
+
+    // Mandato de la entrada, o None si no la tiene o ya está compactada
+    fn term_at(&self, index: usize) -> Option<Term> {
+        if index == self.snapshot_index {
+            return Some(self.snapshot_term);
+        }
+        if index < self.snapshot_index {
+            return None;
+        }
+        self.log
+            .get(index - self.snapshot_index - 1)
+            .map(|entry| entry.term)
+    }
+
+    pub fn reset_election_deadline(&mut self) {
+        self.election_deadline = Instant::now() + random_election_timeout();
+    }
+
+    // Cantidad de votos o de copias que hacen mayoría entre todas las pantallas
+    fn quorum(cluster_size: usize) -> usize {
+        cluster_size / 2 + 1
+    }
+
+    /// Se postula en un mandato nuevo: se vota a sí misma y espera los votos del resto.
+    pub fn start_candidacy(&mut self, id: u8) {
+        self.voted_for = Some(id);
+        self.votes = HashSet::from([id]);
+        self.reset_election_deadline();
+    }
+
+    /// Anota un voto a favor y devuelve si ya tiene mayoría.
+    pub fn add_vote(&mut self, voter: u8, cluster_size: usize) -> bool {
+        self.votes.insert(voter);
+        self.votes.len() >= Self::quorum(cluster_size)
+    }
+
+    /// Solo se vota a una candidata cuyo log está al menos tan al día como el propio, así la
+    /// líder electa tiene todas las entradas confirmadas.
+    pub fn can_vote(&self, candidate: u8, last_log_index: usize, last_log_term: Term) -> bool {
+        let up_to_date = (last_log_term, last_log_index) >= (self.last_term(), self.last_index());
+        up_to_date && self.voted_for.is_none_or(|voted| voted == candidate)
+    }
+
+    pub fn start_leading(&mut self, peers: &[u8]) {
+        self.next_index = peers
+            .iter()
+            .map(|peer| (*peer, self.last_index() + 1))
+            .collect();
+        self.match_index = peers.iter().map(|peer| (*peer, 0)).collect();
+        self.next_heartbeat = Instant::now();
+    }
+
+    /// Como líder, empieza a mandarle el log a una pantalla que se sumó.
+    pub fn watch_peer(&mut self, peer: u8) {
+        let next = self.last_index() + 1;
+        self.next_index.entry(peer).or_insert(next);
+        self.match_index.entry(peer).or_insert(0);
+    }
+
+    /// Como líder, deja de contar a una pantalla que se fue.
+    pub fn forget_peer(&mut self, peer: u8) {
+        self.next_index.remove(&peer);
+        self.match_index.remove(&peer);
+    }
+
+    pub fn append(&mut self, term: Term, command: OrderCommand) {
+        self.log.push(LogEntry { term, command });
+    }
+
+    /// Lo que hay que mandarle a la pantalla: el índice y mandato de la entrada anterior y
+    /// las entradas que siguen. Si le faltan entradas que ya se compactaron devuelve None, y
+    /// hay que mandarle el snapshot.
+    pub fn entries_for(&self, peer: u8) -> Option<(usize, Term, Vec<LogEntry>)> {
+        let next = self
+            .next_index
+            .get(&peer)
+            .copied()
+            .unwrap_or(self.last_index() + 1)
+            .max(1);
+        if next <= self.snapshot_index {
+            return None;
+        }
+        let prev_index = next - 1;
+        let entries = self
+            .log
+            .iter()
+            .skip(prev_index - self.snapshot_index)
+            .take(MAX_COMMANDS_PER_MESSAGE)
+            .cloned()
+            .collect();
+        Some((prev_index, self.term_at(prev_index).unwrap_or(0), entries))
+    }
+
+    /// El snapshot para una pantalla que quedó atrás: hasta qué entrada llega, su mandato y
+    /// los comandos que rehacen la cola, en pedazos que entran en el buffer de la pantalla.
+    pub fn snapshot_chunks(&self) -> (usize, Term, Vec<Vec<OrderCommand>>) {
+        let commands = self.snapshot.commands();
+        let mut chunks: Vec<Vec<OrderCommand>> = commands
+            .chunks(MAX_COMMANDS_PER_MESSAGE)
+            .map(|chunk| chunk.to_vec())
+            .collect();
+        if chunks.is_empty() {
+            chunks.push(Vec::new());
+        }
+        (self.snapshot_index, self.snapshot_term, chunks)
+    }
+
+    /// Agrega las entradas que manda la líder si el log coincide hasta `prev_index`, y
+    /// devuelve hasta dónde coincide ahora. Las entradas propias que no coinciden con las de
+    /// la líder se descartan. Si no coincide devuelve desde qué índice tiene que volver a
+    /// mandar la líder: el siguiente al último si el log es más corto, o el primero del
+    /// mandato que no coincide, para saltearlo entero.
+    pub fn follow(
+        &mut self,
+        prev_index: usize,
+        prev_term: Term,
+        mut entries: Vec<LogEntry>,
+        leader_commit: usize,
+    ) -> Result<usize, usize> {
+        let (mut prev_index, mut prev_term) = (prev_index, prev_term);
+        if prev_index < self.snapshot_index {
+            // lo que ya está compactado está confirmado, y es igual al log de la líder
+            let covered = (self.snapshot_index - prev_index).min(entries.len());
+            entries.drain(..covered);
+            prev_index += covered;
+            if prev_index < self.snapshot_index {
+                return Ok(self.snapshot_index);
+            }
+            prev_term = self.snapshot_term;
+        }
+        match self.term_at(prev_index) {
+            None => return Err(self.last_index() + 1),
+            Some(term) if term != prev_term => {
+                let mut conflict = prev_index;
+                while conflict > self.snapshot_index + 1 && self.term_at(conflict - 1) == Some(term)
+                {
+                    conflict -= 1;
+                }
+                return Err(conflict);
+            }
+            Some(_) => {}
+        }
+        let matched = prev_index + entries.len();
+        for (offset, entry) in entries.into_iter().enumerate() {
+            let index = prev_index + offset + 1;
+            match self.term_at(index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => self.log.truncate(index - self.snapshot_index - 1),
+                None => {}
+            }
+            self.log.push(entry);
+        }
+        if leader_commit > self.commit_index {
+            self.commit_index = leader_commit.min(matched).max(self.commit_index);
+        }
+        Ok(matched)
+    }
+
+    /// Junta los pedazos del snapshot que manda la líder. Con el último lo instala y devuelve
+    /// hasta dónde coincide ahora el log. Si falta un pedazo descarta lo que juntó; la líder
+    /// lo vuelve a mandar entero con el próximo latido.
+    pub fn receive_snapshot(
+        &mut self,
+        last_index: usize,
+        last_term: Term,
+        seq: u32,
+        done: bool,
+        commands: Vec<OrderCommand>,
+    ) -> Option<usize> {
+        if seq == 0 {
+            self.incoming = Some(IncomingSnapshot {
+                last_index,
+                last_term,
+                next_seq: 0,
+                mirror: QueueMirror::default(),
+            });
+        }
+        let incoming = match self.incoming.as_mut() {
+            Some(incoming)
+                if incoming.next_seq == seq
+                    && (incoming.last_index, incoming.last_term) == (last_index, last_term) =>
+            {
+                incoming
+            }
+            _ => {
+                self.incoming = None;
+                return None;
+            }
+        };
+        for command in commands.iter() {
+            incoming.mirror.apply(command);
+        }
+        incoming.next_seq += 1;
+        if !done {
+            return None;
+        }
+        let incoming = self.incoming.take()?;
+        self.install_snapshot(incoming.last_index, incoming.last_term, incoming.mirror);
+        Some(last_index)
+    }
+
+    // Reemplaza lo compactado por el snapshot de la líder. Si el log propio tiene la última
+    // entrada del snapshot se queda con las que siguen; si no, las descarta todas.
+    fn install_snapshot(&mut self, last_index: usize, last_term: Term, mirror: QueueMirror) {
+        if last_index <= self.snapshot_index {
+            return;
+        }
+        if self.term_at(last_index) == Some(last_term) {
+            self.log.drain(..last_index - self.snapshot_index);
+        } else {
+            self.log.clear();
+        }
+        self.snapshot = mirror;
+        self.snapshot_index = last_index;
+        self.snapshot_term = last_term;
+        self.commit_index = self.commit_index.max(last_index);
+    }
+
+    /// La líder anota la respuesta de una pantalla. Si la pantalla no tenía la entrada
+    /// anterior, la próxima vez se le manda desde el índice que indicó en la respuesta.
+    pub fn acknowledge(
+        &mut self,
+        peer: u8,
+        success: bool,
+        match_index: usize,
+        conflict_index: usize,
+    ) {
+        if success {
+            let matched = self.match_index.entry(peer).or_insert(0);
+            *matched = (*matched).max(match_index);
+            self.next_index.insert(peer, *matched + 1);
+        } else {
+            let last_index = self.last_index();
+            if let Some(next) = self.next_index.get_mut(&peer) {
+                *next = conflict_index.clamp(1, last_index + 1);
+            }
+        }
+    }
+
+    /// Confirma la entrada más nueva que tiene la mayoría de las pantallas, siempre que sea
+    /// del mandato actual. Devuelve si avanzó.
+    pub fn advance_commit(&mut self, id: u8, term: Term, cluster_size: usize) -> bool {
+        let mut matched: Vec<usize> = self
+            .match_index
+            .iter()
+            .filter(|(peer, _)| **peer != id)
+            .map(|(_, index)| *index)
+            .collect();
+        matched.push(self.last_index());
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let majority = match matched.get(Self::quorum(cluster_size) - 1) {
+            Some(index) => *index,
+            None => return false,
+        };
+        if majority > self.commit_index && self.term_at(majority) == Some(term) {
+            self.commit_index = majority;
+            return true;
+        }
+        false
+    }
+
+    /// Pasa al snapshot las entradas confirmadas cuando ya son COMPACT_THRESHOLD, así el log
+    /// y lo que se guarda en disco no crecen sin límite. Devuelve si compactó.
+    pub fn compact(&mut self) -> bool {
+        let committed = self.commit_index.saturating_sub(self.snapshot_index);
+        if committed < COMPACT_THRESHOLD {
+            return false;
+        }
+        for entry in self.log.drain(..committed) {
+            self.snapshot.apply(&entry.command);
+            self.snapshot_term = entry.term;
+        }
+        self.snapshot_index = self.commit_index;
+        true
+    }
+
+    /// Rehace la cola a partir del snapshot y el resto del log, para la pantalla que pasa a
+    /// ser la líder.
+    pub fn replay(&self) -> QueueMirror {
+        let mut mirror = self.snapshot.clone();
+        for entry in self.log.iter() {
+            mirror.apply(&entry.command);
+        }
+        mirror
+    }
+}
+
+fn random_election_timeout() -> Duration {
+    Duration::from_millis(
+        rand::thread_rng().gen_range(ELECTION_TIMEOUT_MIN_MS..=ELECTION_TIMEOUT_MAX_MS),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(term: Term) -> LogEntry {
+        LogEntry {
+            term,
+            command: OrderCommand::Released {
+                screen_id: 0,
+                order_id: 0,
+            },
+        }
+    }
+
+    fn with_log(terms: &[Term]) -> RaftState {
+        let mut raft = RaftState::new(None);
+        for term in terms {
+            raft.append(*term, entry(*term).command);
+        }
+        raft
+    }
+
+    #[test]
+    fn follow_truncates_conflicting_suffix() {
+        let mut raft = with_log(&[1, 1, 2, 2]);
+        assert_eq!(raft.follow(2, 1, vec![entry(3)], 0), Ok(3));
+        assert_eq!(raft.last_index(), 3);
+        assert_eq!(raft.last_term(), 3);
+    }
+
+    #[test]
+    fn follow_keeps_matching_entries() {
+        let mut raft = with_log(&[1, 1, 2]);
+        assert_eq!(raft.follow(1, 1, vec![entry(1)], 2), Ok(2));
+        assert_eq!(raft.last_index(), 3);
+        assert_eq!(raft.commit_index, 2);
+    }
+
+    #[test]
+    fn follow_returns_conflict_index() {
+        let mut raft = with_log(&[1, 2, 2, 2]);
+        // el mandato 2 no coincide: la líder tiene que volver a mandar desde su primera entrada
+        assert_eq!(raft.follow(4, 3, vec![entry(3)], 0), Err(2));
+        // al log le faltan entradas: desde la siguiente a la última
+        assert_eq!(raft.follow(10, 3, vec![], 0), Err(5));
+        assert_eq!(raft.last_index(), 4);
+    }
+
+    #[test]
+    fn acknowledge_jumps_to_conflict_index() {
+        let mut raft = with_log(&[1, 1, 1, 1]);
+        raft.start_leading(&[0, 1]);
+        raft.acknowledge(1, false, 0, 2);
+        assert_eq!(raft.entries_for(1).map(|(prev, _, _)| prev), Some(1));
+    }
+
+    #[test]
+    fn advance_commit_only_counts_current_term() {
+        let mut raft = with_log(&[1, 2]);
+        raft.start_leading(&[0, 1, 2]);
+        raft.acknowledge(1, true, 2, 0);
+        // la mayoría tiene la entrada 2, pero es de un mandato anterior
+        assert!(!raft.advance_commit(0, 3, 3));
+        assert_eq!(raft.commit_index, 0);
+
+        raft.append(3, entry(3).command);
+        raft.acknowledge(1, true, 3, 0);
+        assert!(raft.advance_commit(0, 3, 3));
+        assert_eq!(raft.commit_index, 3);
+    }
+
+    #[test]
+    fn can_vote_requires_up_to_date_log() {
+        let raft = with_log(&[1, 2]);
+        assert!(!raft.can_vote(1, 5, 1));
+        assert!(!raft.can_vote(1, 1, 2));
+        assert!(raft.can_vote(1, 2, 2));
+        assert!(raft.can_vote(1, 1, 3));
+    }
+
+    #[test]
+    fn can_vote_once_per_term() {
+        let mut raft = with_log(&[1]);
+        raft.voted_for = Some(1);
+        assert!(raft.can_vote(1, 1, 1));
+        assert!(!raft.can_vote(2, 1, 1));
+    }
+
+    #[test]
+    fn compact_keeps_uncommitted_tail() {
+        let terms = vec![1; COMPACT_THRESHOLD + 4];
+        let mut raft = with_log(&terms);
+        raft.commit_index = COMPACT_THRESHOLD - 1;
+        assert!(!raft.compact());
+
+        raft.commit_index = COMPACT_THRESHOLD + 1;
+        assert!(raft.compact());
+        assert_eq!(raft.last_index(), COMPACT_THRESHOLD + 4);
+        assert_eq!(raft.log.len(), 3);
+        assert_eq!(raft.term_at(COMPACT_THRESHOLD), None);
+        assert_eq!(raft.term_at(COMPACT_THRESHOLD + 1), Some(1));
+
+        // a una pantalla que no tiene lo compactado hay que mandarle el snapshot
+        raft.start_leading(&[0, 1]);
+        raft.acknowledge(1, false, 0, 1);
+        assert!(raft.entries_for(1).is_none());
+    }
+
+    #[test]
+    fn follow_skips_compacted_entries() {
+        let terms = vec![1; COMPACT_THRESHOLD + 2];
+        let mut raft = with_log(&terms);
+        raft.commit_index = COMPACT_THRESHOLD;
+        raft.compact();
+        let entries = vec![entry(1), entry(1), entry(1), entry(2)];
+        let prev_index = COMPACT_THRESHOLD - 1;
+        assert_eq!(
+            raft.follow(prev_index, 1, entries, 0),
+            Ok(COMPACT_THRESHOLD + 3)
+        );
+        assert_eq!(raft.last_term(), 2);
+    }
+
+    #[test]
+    fn snapshot_installs_after_last_chunk() {
+        let mut raft = with_log(&[1]);
+        let command = entry(2).command;
+        assert_eq!(
+            raft.receive_snapshot(5, 2, 0, false, vec![command.clone()]),
+            None
+        );
+        // un pedazo fuera de orden descarta lo juntado
+        assert_eq!(raft.receive_snapshot(5, 2, 2, true, vec![]), None);
+        assert_eq!(raft.receive_snapshot(5, 2, 1, true, vec![]), None);
+
+        assert_eq!(raft.receive_snapshot(5, 2, 0, false, vec![command]), None);
+        assert_eq!(raft.receive_snapshot(5, 2, 1, true, vec![]), Some(5));
+        assert_eq!(raft.last_index(), 5);
+        assert_eq!(raft.last_term(), 2);
+        assert_eq!(raft.commit_index, 5);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::robot_lib::icecream::{Container, IceCream};
+use crate::screen_lib::actors::OrderActorMessage;
+use crate::utils::messages::Priority;
+use log::{info, warn};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Debug;
+use std::time::Duration;
+
+/// Variable de entorno con la que se elige la política de planificación del
+/// `OrderCoordinator`: `fifo` (por defecto), `shortest`, `priority` o `ring`.
+pub const SCHEDULING_POLICY_VAR: &str = "SCHEDULING_POLICY";
+
+/// Cada cuánto tiempo en cola un pedido sube un nivel de prioridad, para que los pedidos
+/// normales no esperen para siempre detrás de los express y VIP.
+pub const PRIORITY_AGING: Duration = Duration::from_secs(15);
+
+/// Prioridad con la que compite un pedido que lleva `waited` en la cola. Nunca supera a la
+/// de un pedido VIP.
+pub fn effective_priority(priority: Priority, waited: Duration) -> u8 {
+    let aged = waited.as_secs() / PRIORITY_AGING.as_secs();
+    (priority as u64 + aged).min(Priority::Vip as u64) as u8
+}
+
+/// Robot libre que puede preparar el pedido que se está asignando.
+#[derive(Debug, Clone, Copy)]
+pub struct RobotCandidate {
+    pub id: u8,
+    pub speed_percent: u16,
+}
+
+/// Lo que sabe el coordinador del anillo: el orden de los robots y el último robot por el
+/// que se vio pasar cada token.
+#[derive(Debug, Default, Clone)]
+pub struct RingView {
+    pub ring: Vec<u8>,
+    pub sightings: HashMap<IceCream, u8>,
+}
+
+impl RingView {
+    // Cuántos saltos tiene que dar un token para ir del robot `from` al robot `to`
+    fn distance(&self, from: u8, to: u8) -> Option<usize> {
+        let from = self.ring.iter().position(|id| *id == from)?;
+        let to = self.ring.iter().position(|id| *id == to)?;
+        Some((to + self.ring.len() - from) % self.ring.len())
+    }
+}
+
+/// Decide en qué orden se atienden los pedidos en cola y a qué robot se le asigna cada uno.
+pub trait SchedulingPolicy: Debug + Send {
+    fn name(&self) -> &'static str;
+
+    /// Elige cuál de los pedidos en cola se asigna primero. `pending` respeta el orden de
+    /// llegada y nunca está vacío.
+    fn select_order(&self, pending: &[&OrderActorMessage]) -> usize;
+
+    /// Elige el robot al que se le asigna el pedido. Por defecto el más rápido, y entre
+    /// los igual de rápidos el de menor id.
+    fn select_robot(
+        &self,
+        _order: &OrderActorMessage,
+        candidates: &[RobotCandidate],
+        _ring: &RingView,
+    ) -> Option<u8> {
+        candidates
+            .iter()
+            .max_by_key(|candidate| (candidate.speed_percent, Reverse(candidate.id)))
+            .map(|candidate| candidate.id)
+    }
+}
+
+/// Atiende los pedidos por orden de llegada.
+#[derive(Debug, Default)]
+pub struct Fifo;
+
+impl SchedulingPolicy for Fifo {
+    fn name(&self) -> &'static str {
+        "fifo"
+    }
+
+    fn select_order(&self, _pending: &[&OrderActorMessage]) -> usize {
+        0
+    }
+}
+
+/// Atiende primero los pedidos con menos gramos, que se preparan más rápido.
+#[derive(Debug, Default)]
+pub struct ShortestFirst;
+
+impl SchedulingPolicy for ShortestFirst {
+    fn name(&self) -> &'static str {
+        "shortest"
+    }
+
+    fn select_order(&self, pending: &[&OrderActorMessage]) -> usize {
+        pending
+            .iter()
+            .enumerate()
+            .min_by_key(|(i, order)| (order.total_grams(), *i))
+            .map_or(0, |(i, _)| i)
+    }
+}
+
+/// Atiende los pedidos por clase y por orden de llegada dentro de cada clase: primero los
+/// cucuruchos, que se comen en el momento, después los vasos y por último los potes.
+#[derive(Debug, Default)]
+pub struct PriorityClasses;
+
+impl PriorityClasses {
+    pub fn class(order: &OrderActorMessage) -> u8 {
+        match order.container {
+            Container::Cone => 0,
+            Container::Cup => 1,
+            Container::KiloPot => 2,
+        }
+    }
+}
+
+impl SchedulingPolicy for PriorityClasses {
+    fn name(&self) -> &'static str {
+        "priority"
+    }
+
+    fn select_order(&self, pending: &[&OrderActorMessage]) -> usize {
+        pending
+            .iter()
+            .enumerate()
+            .min_by_key(|(i, order)| (Self::class(order), *i))
+            .map_or(0, |(i, _)| i)
+    }
+}
+
+/// Atiende por orden de llegada, pero le asigna cada pedido al robot al que antes le van a
+/// llegar los tokens de sus gustos, según dónde se los vio por última vez.
+#[derive(Debug, Default)]
+pub struct RingAware;
+
+impl RingAware {
+    // Saltos que suman los tokens del pedido hasta llegar al robot. Un gusto que nunca se
+    // vio cuenta como medio anillo.
+    pub fn cost(order: &OrderActorMessage, robot_id: u8, ring: &RingView) -> usize {
+        order
+            .items
+            .keys()
+            .map(|ice_cream| {
+                ring.sightings
+                    .get(ice_cream)
+                    .and_then(|seen_at| ring.distance(*seen_at, robot_id))
+                    .unwrap_or(ring.ring.len() / 2)
+            })
+            .sum()
+    }
+}
+
+impl SchedulingPolicy for RingAware {
+    fn name(&self) -> &'static str {
+        "ring"
+    }
+
+    fn select_order(&self, _pending: &[&OrderActorMessage]) -> usize {
+        0
+    }
+
+    fn select_robot(
+        &self,
+        order: &OrderActorMessage,
+        candidates: &[RobotCandidate],
+        ring: &RingView,
+    ) -> Option<u8> {
+        candidates
+            .iter()
+            .min_by_key(|candidate| {
+                (
+                    Self::cost(order, candidate.id, ring),
+                    Reverse(candidate.speed_percent),
+                    candidate.id,
+                )
+            })
+            .map(|candidate| candidate.id)
+    }
+}
+
+pub fn policy_from_name(name: &str) -> Option<Box<dyn SchedulingPolicy>> {
+    match name {
+        "fifo" => Some(Box::new(Fifo)),
+        "shortest" => Some(Box::new(ShortestFirst)),
+        "priority" => Some(Box::new(PriorityClasses)),
+        "ring" => Some(Box::new(RingAware)),
+        _ => None,
+    }
+}
+
+/// Lee la política de la variable de entorno `SCHEDULING_POLICY`. Si no está definida o no
+/// se reconoce se usa FIFO.
+pub fn policy_from_env() -> Box<dyn SchedulingPolicy> {
+    let name = env::var(SCHEDULING_POLICY_VAR).unwrap_or_else(|_| "fifo".to_string());
+    let policy = policy_from_name(&name).unwrap_or_else(|| {
+        warn!(
+            "[Coordinator] No conozco la politica de planificacion {:?}, uso fifo",
+            name
+        );
+        Box::new(Fifo)
+    });
+    info!(
+        "[Coordinator] Uso la politica de planificacion {}",
+        policy.name()
+    );
+    policy
+}
@@ -1,76 +1,289 @@
-use log::{debug, info};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
 use std::{
-    io,
+    collections::BTreeMap,
     net::{Ipv4Addr, SocketAddr, UdpSocket},
-    thread,
+    sync::{
+        atomic::Ordering,
+        mpsc::{self, Receiver, TryRecvError},
+        Arc, RwLock,
+    },
+    thread::{self, JoinHandle},
 };
 
 use crate::utils::{
-    addresses::{id_to_addr_leader, id_to_addr_screen},
+    addresses::{addr_to_id_leader, id_to_addr_leader, id_to_addr_screen},
     errors::ScreenError,
+    failure_detector::FailureDetector,
     messages::{
-        Alive, BullyElection, BullyOk, Coordinator, GatewayResponse, KeepAlive, Messages, Order,
-        OrderJson, OrderResult, OrderStatus,
+        Alive, AppendEntries, AppendReply, BullyElection, BullyOk, CancelOrder, Coordinator,
+        GatewayReply, GatewayResponse, InstallSnapshot, KeepAlive, LeaderHeartbeat, LeaderQuery,
+        Membership, Messages, Order, OrderResult, OrderStatus, Priority, QueueResync, QueueUpdate,
+        RequestVote, ScreenJoin, ScreenLeave, StaleTerm, Term, Vote,
     },
 };
 
-use std::fs::File;
-use std::io::{BufRead, BufReader, ErrorKind, Lines};
-use std::time::Duration;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
 
-use crate::screen_lib::actors::{Inspect, LeaderReceiver, OrderCoordinator, OrderResolver};
+use crate::screen_lib::actors::{
+    Inspect, LeaderFence, LeaderReceiver, OrderCoordinator, OrderResolver,
+    RestoreOrdersActorMessage,
+};
+use crate::screen_lib::checkpoint::{CheckpointOrder, ScreenCheckpoint};
+use crate::screen_lib::membership::{self, MemberWatch, SharedMembers};
+use crate::screen_lib::order_log::{OrderCommand, QueueMirror};
+use crate::screen_lib::order_source::{self, OrderSource, SourcedOrder};
+use crate::screen_lib::raft::{
+    election_mode_from_env, ElectionMode, RaftState, RAFT_HEARTBEAT_INTERVAL,
+};
+use crate::screen_lib::scheduling::policy_from_env;
+use crate::screen_lib::standby::{standby_for, StandbyMirror, StandbyStream, STANDBY_TIMEOUT};
+use crate::screen_lib::validation::RejectionReport;
+use crate::utils::clock::now_millis;
 use actix::prelude::*;
 use ScreenStatus::*;
 
-use crate::gateway::gateway_action::GatewayAction;
+use crate::gateway::gateway_action::{Cents, GatewayAction};
 
 const CARDNUMBER: u32 = 32231244;
-const PRICE: f64 = 1000.0;
+const PRICE: Cents = 100_000;
+// Recargo de los pedidos que se atienden antes que el resto
+const EXPRESS_SURCHARGE: Cents = 25_000;
+const VIP_SURCHARGE: Cents = 50_000;
+// Tiempo que espera un cliente cuyo pedido no indica `sla_secs`
+const DEFAULT_ORDER_SLA: Duration = Duration::from_secs(60);
+// Tiempo que espera las respuestas en una elección Bully, y a la líder al arrancar
+const LEADER_TIMEOUT: Duration = Duration::from_secs(10);
+// Tiempo sin latidos ni mensajes de la líder antes de mandarle un KEEPALIVE, y luego antes de
+// darla por caída, hasta que el detector de fallas aprende cada cuánto llegan. La líder manda
+// un latido cada LEADER_HEARTBEAT_INTERVAL.
+const LEADER_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(3);
+// Tiempo que espera al arrancar que alguna pantalla le diga quién es la líder antes de
+// arrancar una elección, y cada cuánto vuelve a preguntar mientras tanto
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const DISCOVERY_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Cantidad de clientes que una pantalla atiende a la vez: toma el pedido del siguiente
+/// mientras se preparan los anteriores.
+pub const MAX_ORDERS_IN_FLIGHT: usize = 3;
+
+// Si el gateway no responde la captura en este tiempo, se la vuelve a pedir
+const CAPTURE_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+// Cada cuánto se fija si llegó otro cliente mientras hay lugar para atenderlo
+const ORDER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Tamaño del buffer con el que la pantalla recibe cada mensaje.
+pub const SCREEN_BUFFER_SIZE: usize = 1024;
+
+/// Máximo de comandos de la cola que van en un mismo AppendEntries o QueueUpdate. Un
+/// comando ocupa a lo sumo 38 bytes (un pedido con `MAX_SCOOPS` gustos), así que el mensaje
+/// entra en `SCREEN_BUFFER_SIZE`.
+pub const MAX_COMMANDS_PER_MESSAGE: usize = 16;
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum ScreenStatus {
     ReadingOrder,
-    WaitingGatewayResponse,
-    WaitingRobotResponse,
-    ChargingOrder,
     ElectingNewLeader,
     BeingLeader,
 }
 
+/// En qué paso está cada pedido en curso de la pantalla.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum OrderState {
+    WaitingGatewayResponse,
+    WaitingRobotResponse,
+}
+
+/// Pedido de un cliente que la pantalla todavía no terminó de cobrar.
+#[derive(Debug)]
+pub struct InFlightOrder {
+    pub order: Order,
+    pub state: OrderState,
+    // cuándo se cargó el pedido, para saber cuánto esperó el cliente
+    pub since: Instant,
+    // cuándo cancela el cliente el pedido si todavía no está listo
+    pub cancel_at: Option<Instant>,
+    // el pedido se tomó antes de que se reiniciara la pantalla
+    pub restored: bool,
+    // cuándo se vuelve a pedir la captura si el gateway no respondió
+    pub capture_retry_at: Instant,
+    // ya se pidió la captura más de una vez
+    pub capture_retried: bool,
+}
+
 //====================// 🖥 SCREEN 🖥 //====================//
 pub struct Screen {
     pub id: u8,
     pub status: ScreenStatus,
     pub socket: UdpSocket,
     pub leader: (Ipv4Addr, u16),
+    // mandato de la líder actual, va en cada pedido que se le manda
+    pub term: Term,
+    // las pantallas que participan de las elecciones: al arrancar las de la línea de
+    // comandos, después las que anuncia la líder
     pub peers: Vec<u8>,
-    pub file_lines: Lines<BufReader<File>>,
-    pub current_order: Option<Order>,
+    // las mismas, para los latidos del LeaderReceiver
+    pub shared_peers: SharedMembers,
+    // como líder, qué pantallas siguen respondiendo los latidos
+    pub member_watch: Option<MemberWatch>,
+    // de dónde salen los pedidos, hasta que la pantalla empieza a tomarlos
+    pub source: Option<Box<dyn OrderSource>>,
+    pub source_name: String,
+    // última línea de la fuente de la que se tomó un pedido
+    pub source_line: usize,
+    // la pantalla retomó su estado de un checkpoint al arrancar
+    pub resumed: bool,
+    // pedidos en curso por id de pedido
+    pub orders: BTreeMap<u8, InFlightOrder>,
+    pub next_order_id: u8,
+    // cuándo se le manda un KEEPALIVE a la líder si no dio señales de vida
+    pub leader_deadline: Instant,
+    // aprende cada cuánto da señales de vida la líder
+    pub leader_detector: FailureDetector,
+    pub waiting_keepalive: bool,
+    // en una elección Bully, hasta cuándo se espera el COORDINATOR de la pantalla mayor que
+    // respondió OK
+    pub coordinator_deadline: Option<Instant>,
     pub nrobots: u8,
+    // hilo del sistema de actores, mientras la pantalla es la líder
+    pub leader_system: Option<JoinHandle<Result<(), ScreenError>>>,
+    // con qué mandato se destituye al sistema de actores de la líder
+    pub leader_fence: LeaderFence,
+    // estado de Raft, si se elige a la líder con Raft en lugar de Bully
+    pub raft: Option<RaftState>,
+    // como líder, lo que cambia en la cola del coordinador
+    pub order_log: Option<Receiver<OrderCommand>>,
+    // como líder con Bully, lo que le manda de la cola a su respaldo
+    pub standby_stream: Option<StandbyStream>,
+    // como respaldo de la líder con Bully, la copia de su cola
+    pub standby_mirror: Option<StandbyMirror>,
 }
 
 impl Screen {
     pub fn new(
         id: u8,
         peers: Vec<u8>,
-        file_name: String,
+        source: Box<dyn OrderSource>,
         nrobots: u8,
     ) -> Result<Self, ScreenError> {
         let socket = Self::create_socket(id)?;
+        let peers = membership::normalize(id, peers);
         let (leader, status) = Self::determine_leader(id, &peers);
-        let file_lines = Self::open_file(file_name)?;
-        let current_order = None;
+        let source_name = source.describe();
+        let replayable = source.replayable();
 
-        Ok(Screen {
+        let mut screen = Screen {
             id,
             status,
             socket,
             leader,
+            term: 0,
+            shared_peers: Arc::new(RwLock::new(peers.clone())),
+            member_watch: None,
             peers,
-            file_lines,
-            current_order,
+            source: Some(source),
+            source_name,
+            source_line: 0,
+            resumed: false,
+            orders: BTreeMap::new(),
+            next_order_id: 1,
+            leader_deadline: Instant::now() + LEADER_TIMEOUT,
+            leader_detector: FailureDetector::new(LEADER_HEARTBEAT_TIMEOUT),
+            waiting_keepalive: false,
+            coordinator_deadline: None,
             nrobots,
-        })
+            leader_system: None,
+            leader_fence: LeaderFence::default(),
+            raft: None,
+            order_log: None,
+            standby_stream: None,
+            standby_mirror: None,
+        };
+        if let Some(checkpoint) = ScreenCheckpoint::load(id) {
+            screen.restore(checkpoint, replayable);
+        }
+        if election_mode_from_env() == ElectionMode::Raft {
+            info!("[Screen {}] Elijo a la lider con Raft", id);
+            let (term, raft) = RaftState::load(id);
+            screen.raft = Some(raft);
+            screen.adopt_term(screen.term.max(term));
+        }
+        Ok(screen)
+    }
+
+    // Retoma el estado guardado antes del reinicio. Las líneas ya tomadas solo se saltean
+    // si la fuente es la misma y vuelve a dar los mismos pedidos.
+    fn restore(&mut self, checkpoint: ScreenCheckpoint, replayable: bool) {
+        if replayable && checkpoint.source == self.source_name {
+            self.source_line = checkpoint.line;
+        }
+        self.next_order_id = checkpoint.next_order_id;
+        if self.status != BeingLeader {
+            self.leader = checkpoint.leader;
+        }
+        let now = now_millis();
+        for saved in checkpoint.orders {
+            let waited = Duration::from_millis(now.saturating_sub(saved.taken_at));
+            self.orders.insert(
+                saved.order.order_id,
+                InFlightOrder {
+                    since: Instant::now()
+                        .checked_sub(waited)
+                        .unwrap_or_else(Instant::now),
+                    cancel_at: saved
+                        .cancel_at
+                        .map(|at| Instant::now() + Duration::from_millis(at.saturating_sub(now))),
+                    order: saved.order,
+                    state: saved.state,
+                    restored: true,
+                    capture_retry_at: Instant::now() + CAPTURE_RETRY_INTERVAL,
+                    capture_retried: false,
+                },
+            );
+        }
+        self.adopt_term(checkpoint.term);
+        self.resumed = true;
+        info!(
+            "[Screen {}] Retomo desde la linea {} con el proximo pedido {} y {} pedido(s) en curso",
+            self.id,
+            self.source_line,
+            self.next_order_id,
+            self.orders.len()
+        );
+    }
+
+    // Guarda lo necesario para seguir donde estaba si la pantalla se reinicia. Si no puede
+    // guardarlo sigue atendiendo igual.
+    fn save_checkpoint(&self) {
+        let now = Instant::now();
+        let now_ms = now_millis();
+        let checkpoint = ScreenCheckpoint {
+            source: self.source_name.clone(),
+            line: self.source_line,
+            next_order_id: self.next_order_id,
+            leader: self.leader,
+            term: self.term,
+            orders: self
+                .orders
+                .values()
+                .map(|in_flight| CheckpointOrder {
+                    order: in_flight.order.clone(),
+                    state: in_flight.state,
+                    taken_at: now_ms.saturating_sub(in_flight.since.elapsed().as_millis() as u64),
+                    cancel_at: in_flight
+                        .cancel_at
+                        .map(|at| now_ms + at.saturating_duration_since(now).as_millis() as u64),
+                })
+                .collect(),
+        };
+        if let Err(error) = checkpoint.save(self.id) {
+            error!(
+                "[Screen {}] No pude guardar el checkpoint: {}",
+                self.id, error
+            );
+        }
     }
 
     fn create_socket(id: u8) -> Result<UdpSocket, ScreenError> {
@@ -93,57 +306,199 @@ impl Screen {
         (leader, ReadingOrder)
     }
 
-    fn open_file(file_name: String) -> Result<io::Lines<io::BufReader<File>>, ScreenError> {
-        let file = File::open(file_name)?;
-        Ok(io::BufReader::new(file).lines())
+    fn order_price(priority: Priority) -> Cents {
+        match priority {
+            Priority::Normal => PRICE,
+            Priority::Express => PRICE + EXPRESS_SURCHARGE,
+            Priority::Vip => PRICE + VIP_SURCHARGE,
+        }
     }
 
-    // Inicializa el sistema de actores del Líder
-    fn run_leader_logic(&self) -> Result<(), ScreenError> {
-        info!("[Screen {}] Soy la Pantalla Líder.", self.id);
-
-        let system = System::new();
+    // Inicializa el sistema de actores del Líder en su propio hilo. Vuelve cuando el
+    // LeaderReceiver ya puede recibir pedidos.
+    // El coordinador arranca con la cola que se reconstruye del log de Raft o de la copia del
+    // respaldo, y le pasa a la pantalla lo que cambia para replicarlo.
+    fn spawn_leader_system(
+        &self,
+        order_log: Option<mpsc::Sender<OrderCommand>>,
+        restore: Option<RestoreOrdersActorMessage>,
+    ) -> Result<JoinHandle<Result<(), ScreenError>>, ScreenError> {
         let id = self.id;
-        system.block_on(async {
-            let coordinator = OrderCoordinator::new(self.nrobots).start();
-
-            // registro de los resolvers -> uno por cada robot.
-            for i in 0..self.nrobots {
-                let resolver = SyncArbiter::start(1, {
-                    let addr = coordinator.clone();
-                    move || OrderResolver::new(i, addr.clone())
-                });
-                resolver.try_send(Inspect {
-                    resolver: resolver.clone(),
-                })?;
-            }
-
-            SyncArbiter::start(1, {
-                move || {
-                    LeaderReceiver::new(
-                        id,
-                        coordinator.clone(),
-                        UdpSocket::bind(id_to_addr_leader(id as u16)).unwrap(),
-                    )
+        let nrobots = self.nrobots;
+        let peers = self.shared_peers.clone();
+        let term = self.term;
+        let fence = self.leader_fence.clone();
+        let receiver_socket = UdpSocket::bind(id_to_addr_leader(id as u16))?;
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        let leader_system = thread::spawn(move || {
+            let system = System::new();
+            let (failure_tx, failure_rx) = mpsc::channel();
+            let setup = system.block_on(async {
+                let coordinator_socket = UdpSocket::bind((Ipv4Addr::new(127, 0, 0, 1), 0))?;
+                let mut coordinator = OrderCoordinator::new(
+                    nrobots,
+                    coordinator_socket,
+                    policy_from_env(),
+                    term,
+                    fence.clone(),
+                );
+                if let Some(order_log) = order_log {
+                    coordinator = coordinator.with_order_log(order_log);
+                }
+                let coordinator = coordinator.start();
+                if let Some(restore) = restore {
+                    coordinator.do_send(restore);
                 }
+
+                SyncArbiter::start(1, {
+                    let coordinator = coordinator.clone();
+                    let fence = fence.clone();
+                    move || {
+                        LeaderReceiver::new(
+                            id,
+                            term,
+                            peers.clone(),
+                            coordinator.clone(),
+                            receiver_socket.try_clone().unwrap(),
+                            fence.clone(),
+                            failure_tx.clone(),
+                        )
+                    }
+                });
+
+                Ok::<_, ScreenError>(coordinator)
             });
+            let coordinator = match setup {
+                Ok(coordinator) => coordinator,
+                Err(error) => {
+                    let _ = ready_tx.send(Err(error));
+                    return Ok(());
+                }
+            };
+            if ready_tx.send(Ok(())).is_err() {
+                return Ok(());
+            }
 
-            for pid in self.peers.iter() {
-                if *pid != self.id {
-                    let coordinator_msg = Coordinator::new(self.id);
-                    self.socket
-                        .send_to(&coordinator_msg.as_bytes(), id_to_addr_screen(*pid as u16))?;
+            // registro de los resolvers -> uno por cada robot. Tomar su puerto puede esperar a
+            // que lo suelte la líder anterior, así que la pantalla no espera a que terminen.
+            let resolvers = system.block_on(async {
+                for i in 0..nrobots {
+                    let socket = OrderResolver::bind_socket(i)?;
+                    let resolver = SyncArbiter::start(1, {
+                        let addr = coordinator.clone();
+                        let fence = fence.clone();
+                        move || {
+                            OrderResolver::new(
+                                i,
+                                id,
+                                term,
+                                fence.clone(),
+                                addr.clone(),
+                                socket.try_clone().unwrap(),
+                            )
+                        }
+                    });
+                    resolver.try_send(Inspect {
+                        resolver: resolver.clone(),
+                    })?;
                 }
+                Ok::<(), ScreenError>(())
+            });
+            if let Err(error) = resolvers {
+                // sin resolvers no puede coordinar: apaga al LeaderReceiver y deja el mandato
+                fence.fetch_max(term, Ordering::SeqCst);
+                return Err(error);
             }
 
-            Ok::<(), ScreenError>(())
-        })?;
+            system.run()?;
+            match failure_rx.try_recv() {
+                Ok(error) => Err(error),
+                Err(_) => Ok(()),
+            }
+        });
 
-        system.run()?;
-        Ok(())
+        ready_rx.recv()??;
+        Ok(leader_system)
+    }
+
+    // Pasa a ser la líder sin dejar de atender a sus clientes: sus pedidos siguen el mismo
+    // camino que los del resto, a través de su propio LeaderReceiver
+    fn become_leader(&mut self) -> Result<(), ScreenError> {
+        info!(
+            "[Screen {}] Soy la Pantalla Líder del mandato {}.",
+            self.id, self.term
+        );
+        let (order_log, restore) = match self.raft.as_mut() {
+            Some(raft) => {
+                raft.start_leading(&self.peers);
+                let restore = raft.replay().restore();
+                let (order_log, order_log_rx) = mpsc::channel();
+                self.order_log = Some(order_log_rx);
+                (Some(order_log), Some(restore))
+            }
+            None => self.start_standby_stream(),
+        };
+        self.leader_fence = LeaderFence::default();
+        self.leader_system = Some(self.spawn_leader_system(order_log, restore)?);
+        self.leader = id_to_addr_leader(self.id as u16);
+        self.leader_is_alive();
+        self.member_watch = Some(MemberWatch::new(self.id, &self.peers));
+
+        for pid in self.peers.iter() {
+            if *pid != self.id {
+                let coordinator_msg = Coordinator::new(self.id, self.term);
+                self.socket
+                    .send_to(&coordinator_msg.as_bytes(), id_to_addr_screen(*pid as u16))?;
+            }
+        }
+        self.announce_membership()?;
+        self.resync_standby()?;
+        self.save_checkpoint();
+        // los pedidos que le había mandado a la líder anterior ahora los coordina ella
+        self.resend_orders(self.leader)
     }
 
     pub fn leader_election(&mut self) -> Result<(), ScreenError> {
+        if self.raft.is_some() {
+            return self.raft_election();
+        }
+        self.send_elections()?;
+
+        // termina cuando llega el COORDINATOR del nuevo lider o cuando nadie responde y el
+        // lider pasa a ser esta pantalla
+        self.status = ElectingNewLeader;
+        while self.status == ElectingNewLeader {
+            self.listen_socket()?;
+            // se revisa después de cada mensaje: los latidos u otros OK no estiran la espera
+            if self.status == ElectingNewLeader && self.coordinator_overdue() {
+                // la pantalla que respondió OK se cayó antes de anunciarse, se vuelve a
+                // elegir entre las que quedan
+                info!(
+                    "[Screen {}] No llego el COORDINATOR despues del OK, reinicio la eleccion",
+                    self.id
+                );
+                self.send_elections()?;
+            } else if let Some(deadline) = self.coordinator_deadline {
+                self.socket.set_read_timeout(Some(
+                    deadline
+                        .saturating_duration_since(Instant::now())
+                        .max(Duration::from_millis(1)),
+                ))?;
+            }
+        }
+        self.coordinator_deadline = None;
+        Ok(())
+    }
+
+    fn coordinator_overdue(&self) -> bool {
+        self.coordinator_deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    // Les manda ELECTION a las pantallas de ID mayor y espera sus OK
+    fn send_elections(&mut self) -> Result<(), ScreenError> {
+        self.coordinator_deadline = None;
         for pid in self.peers.iter() {
             if *pid > self.id {
                 let election = BullyElection { owner: self.id };
@@ -156,27 +511,13 @@ impl Screen {
             }
         }
         self.socket
-            .set_read_timeout(Some(Duration::from_secs(10)))?;
-
-        let prev_status = self.status;
-        self.status = ElectingNewLeader;
-        self.listen_socket()?;
-
-        if self.status != BeingLeader {
-            self.status = prev_status;
-        }
-
-        if self.status == WaitingRobotResponse {
-            if let Some(ref order) = self.current_order {
-                self.socket.send_to(&order.as_bytes(), self.leader)?;
-            }
-        }
+            .set_read_timeout(Some(self.election_timeout()))?;
         Ok(())
     }
 
     // Handler de la election 🤝
     pub fn handle_election(&mut self, buffer: &[u8], from: SocketAddr) -> Result<(), ScreenError> {
-        let owner = buffer[1];
+        let owner = BullyElection::from_bytes(buffer).owner;
         info!("[Screen {}] Llego un BullyElection de {}", self.id, owner);
         // como el que lo mandó es menor (id), le mando OK y continuo el proceso de elección
         let msg = BullyOk { owner: self.id };
@@ -192,18 +533,1226 @@ impl Screen {
         Ok(())
     }
 
-    pub fn handle_coordinator(&mut self, buffer: &[u8]) {
-        let leader = buffer[0];
-        info!("[Screen {}] Tenemos nuevo lider! Es {}", self.id, leader);
+    // La líder dio señales de vida, se vuelve a esperar el plazo completo del detector
+    fn leader_is_alive(&mut self) {
+        self.waiting_keepalive = false;
+        self.leader_detector.heartbeat();
+        self.leader_deadline = self.leader_detector.deadline();
+        if let Some(raft) = self.raft.as_mut() {
+            raft.reset_election_deadline();
+        }
+    }
+
+    // Pasa a usar el mandato en todos los pedidos, también en los que hay que reenviar
+    fn adopt_term(&mut self, term: Term) {
+        self.term = term;
+        for in_flight in self.orders.values_mut() {
+            in_flight.order.term = term;
+        }
+    }
+
+    // Deja de ser la líder: destituye a su sistema de actores y espera que termine
+    fn step_down(&mut self, term: Term) {
+        self.leader_fence.fetch_max(term, Ordering::SeqCst);
+        let newest = self.leader_fence.load(Ordering::SeqCst);
+        info!(
+            "[Screen {}] Dejo de ser la lider del mandato {}, ahora es el {}",
+            self.id, self.term, newest
+        );
+        if let Some(leader_system) = self.leader_system.take() {
+            match leader_system.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(error)) => error!(
+                    "[Screen {}] El sistema de actores de la lider termino con error: {}",
+                    self.id, error
+                ),
+                Err(_) => error!(
+                    "[Screen {}] El sistema de actores de la lider termino con panic",
+                    self.id
+                ),
+            }
+        }
+        self.order_log = None;
+        self.standby_stream = None;
+        self.member_watch = None;
+        self.adopt_term(self.term.max(newest));
+        self.status = ReadingOrder;
+    }
+
+    pub fn handle_coordinator(
+        &mut self,
+        buffer: &[u8],
+        from: SocketAddr,
+    ) -> Result<(), ScreenError> {
+        let coordinator = Coordinator::from_bytes(buffer)?;
+        let leader = coordinator.owner;
+        if coordinator.term < self.term {
+            // una líder de una elección vieja, le aviso el mandato actual
+            info!(
+                "[Screen {}] Ignoro el COORDINATOR de {} del mandato {}, el actual es {}",
+                self.id, leader, coordinator.term, self.term
+            );
+            self.socket
+                .send_to(&StaleTerm::new(self.id, self.term).as_bytes(), from)?;
+            return Ok(());
+        }
+        if self.status == BeingLeader {
+            if coordinator.term == self.term && leader < self.id {
+                // dos líderes del mismo mandato: sigue la de id mayor
+                self.socket
+                    .send_to(&Coordinator::new(self.id, self.term).as_bytes(), from)?;
+                return Ok(());
+            }
+            self.step_down(coordinator.term);
+        }
+        info!(
+            "[Screen {}] Tenemos nuevo lider! Es {} con el mandato {}",
+            self.id, leader, coordinator.term
+        );
+        self.observe_term(coordinator.term);
+        if self
+            .standby_mirror
+            .as_ref()
+            .is_some_and(|mirror| mirror.leader != leader)
+        {
+            // la copia era de la cola de otra líder
+            self.standby_mirror = None;
+        }
         self.leader = id_to_addr_leader(leader as u16);
+        if self.status == ElectingNewLeader {
+            self.status = ReadingOrder;
+        }
+        // lo que tardó el cambio de líder no cuenta como intervalo entre mensajes
+        self.leader_detector.restart();
+        self.leader_is_alive();
+        self.save_checkpoint();
+        // la nueva líder puede no conocer a esta pantalla
+        self.join_leader()?;
+        // la nueva líder no sabe de los pedidos que le habíamos mandado a la anterior
+        self.resend_orders(self.leader)
+    }
+
+    // Latido del LeaderReceiver de una líder
+    pub fn handle_leader_heartbeat(&mut self, buffer: &[u8]) -> Result<(), ScreenError> {
+        let heartbeat = LeaderHeartbeat::from_bytes(buffer)?;
+        let leader_screen = id_to_addr_screen(heartbeat.owner as u16);
+        if heartbeat.term < self.term {
+            // una líder destituida que todavía no se enteró, le aviso el mandato actual
+            self.socket.send_to(
+                &StaleTerm::new(self.id, self.term).as_bytes(),
+                leader_screen,
+            )?;
+            return Ok(());
+        }
+        if heartbeat.term == self.term
+            && self.status != BeingLeader
+            && id_to_addr_leader(heartbeat.owner as u16) == self.leader
+        {
+            if self.status == ElectingNewLeader && self.raft.is_none() {
+                info!(
+                    "[Screen {}] La lider {} sigue viva, dejo la eleccion",
+                    self.id, heartbeat.owner
+                );
+                self.status = ReadingOrder;
+            }
+            self.leader_is_alive();
+            // la líder también vigila a las pantallas
+            self.socket
+                .send_to(&Alive::new(self.id).as_bytes(), leader_screen)?;
+            return Ok(());
+        }
+        // una líder que esta pantalla no conocía: es como si hubiera llegado su COORDINATOR
+        let coordinator = Coordinator::new(heartbeat.owner, heartbeat.term).as_bytes();
+        self.handle_coordinator(&coordinator[1..], leader_screen.into())
+    }
+
+    // ======== MIEMBROS ========
+
+    // Le avisa a la líder que esta pantalla participa de las elecciones
+    fn join_leader(&self) -> Result<(), ScreenError> {
+        if self.status == BeingLeader {
+            return Ok(());
+        }
+        let leader = addr_to_id_leader(self.leader);
+        self.socket.send_to(
+            &ScreenJoin::new(self.id).as_bytes(),
+            id_to_addr_screen(leader as u16),
+        )?;
+        Ok(())
+    }
+
+    // Pasa a usar las pantallas que anuncia la líder, o las que ve como líder. Devuelve si
+    // cambiaron.
+    fn set_peers(&mut self, members: Vec<u8>) -> Result<bool, ScreenError> {
+        let members = membership::normalize(self.id, members);
+        if members == self.peers {
+            return Ok(false);
+        }
+        info!(
+            "[Screen {}] Las pantallas de las elecciones ahora son {:?}",
+            self.id, members
+        );
+        if let (BeingLeader, Some(raft)) = (self.status, self.raft.as_mut()) {
+            for peer in members.iter().filter(|peer| !self.peers.contains(peer)) {
+                raft.watch_peer(*peer);
+            }
+            for peer in self.peers.iter().filter(|peer| !members.contains(peer)) {
+                raft.forget_peer(*peer);
+            }
+        }
+        self.peers = members.clone();
+        *self
+            .shared_peers
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = members;
+        if self.status == BeingLeader {
+            let standby = standby_for(self.id, &self.peers);
+            if self
+                .standby_stream
+                .as_mut()
+                .is_some_and(|stream| stream.retarget(standby))
+            {
+                if let Some(standby) = standby {
+                    info!(
+                        "[Screen {}] Mi respaldo ahora es la pantalla {}",
+                        self.id, standby
+                    );
+                }
+                self.resync_standby()?;
+            }
+        } else if self
+            .standby_mirror
+            .as_ref()
+            .is_some_and(|mirror| standby_for(mirror.leader, &self.peers) != Some(self.id))
+        {
+            // la líder ahora le copia la cola a otra pantalla
+            self.standby_mirror = None;
+        }
+        Ok(true)
+    }
+
+    // Como líder, les manda a las demás pantallas quiénes participan de las elecciones
+    fn announce_membership(&self) -> Result<(), ScreenError> {
+        let membership = Membership::new(self.id, self.term, self.peers.clone()).as_bytes();
+        for pid in self.peers.iter().filter(|pid| **pid != self.id) {
+            self.socket
+                .send_to(&membership, id_to_addr_screen(*pid as u16))?;
+        }
+        Ok(())
+    }
+
+    pub fn handle_screen_join(&mut self, buffer: &[u8]) -> Result<(), ScreenError> {
+        let join = ScreenJoin::from_bytes(buffer)?;
+        if let Some(watch) = self.member_watch.as_mut() {
+            watch.watch(join.owner);
+        }
+        let mut members = self.peers.clone();
+        members.push(join.owner);
+        if self.set_peers(members)? {
+            info!(
+                "[Screen {}] La pantalla {} se suma a las elecciones",
+                self.id, join.owner
+            );
+            return self.announce_membership();
+        }
+        // ya la conocía, solo le contesto a ella
+        self.socket.send_to(
+            &Membership::new(self.id, self.term, self.peers.clone()).as_bytes(),
+            id_to_addr_screen(join.owner as u16),
+        )?;
+        Ok(())
+    }
+
+    pub fn handle_screen_leave(&mut self, buffer: &[u8]) -> Result<(), ScreenError> {
+        let leave = ScreenLeave::from_bytes(buffer)?;
+        info!(
+            "[Screen {}] La pantalla {} se va, deja las elecciones",
+            self.id, leave.owner
+        );
+        self.remove_peer(leave.owner)
+    }
+
+    fn remove_peer(&mut self, member: u8) -> Result<(), ScreenError> {
+        if let Some(watch) = self.member_watch.as_mut() {
+            watch.forget(member);
+        }
+        let members = self
+            .peers
+            .iter()
+            .copied()
+            .filter(|pid| *pid != member)
+            .collect();
+        if self.set_peers(members)? {
+            self.announce_membership()?;
+        }
+        Ok(())
+    }
+
+    // Como líder con Bully, saca de las elecciones a las pantallas que dejaron de responder
+    // los latidos. Con Raft no: una líder aislada sacaría a las demás y se armaría su propia
+    // mayoría.
+    fn check_members(&mut self) -> Result<(), ScreenError> {
+        if self.raft.is_some() {
+            return Ok(());
+        }
+        let departed = match &self.member_watch {
+            Some(watch) => watch.departed(),
+            None => return Ok(()),
+        };
+        for member in departed {
+            warn!(
+                "[Screen {}] La pantalla {} no responde los latidos, la saco de las elecciones",
+                self.id, member
+            );
+            self.remove_peer(member)?;
+            // si igual estaba viva, se entera y se vuelve a sumar
+            self.socket.send_to(
+                &Membership::new(self.id, self.term, self.peers.clone()).as_bytes(),
+                id_to_addr_screen(member as u16),
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn handle_membership(&mut self, buffer: &[u8]) -> Result<(), ScreenError> {
+        let membership = Membership::from_bytes(buffer)?;
+        if membership.term < self.term || membership.owner == self.id {
+            return Ok(());
+        }
+        let leader = id_to_addr_leader(membership.owner as u16);
+        if membership.term > self.term || leader != self.leader {
+            // una líder que esta pantalla no conocía: es como si hubiera llegado su COORDINATOR
+            let coordinator = Coordinator::new(membership.owner, membership.term).as_bytes();
+            let leader_screen = id_to_addr_screen(membership.owner as u16);
+            self.handle_coordinator(&coordinator[1..], leader_screen.into())?;
+            if leader != self.leader {
+                return Ok(());
+            }
+        }
+        if !membership.members.contains(&self.id) {
+            info!(
+                "[Screen {}] La lider {} no me cuenta en las elecciones, me vuelvo a sumar",
+                self.id, membership.owner
+            );
+            self.join_leader()?;
+        }
+        self.set_peers(membership.members)?;
+        Ok(())
+    }
+
+    // ======== BÚSQUEDA DE LA LÍDER ========
+
+    // Les pregunta a las demás pantallas quién es la líder
+    fn query_leader(&self) -> Result<(), ScreenError> {
+        let query = LeaderQuery::new(self.id).as_bytes();
+        for pid in self.peers.iter().filter(|pid| **pid != self.id) {
+            self.socket
+                .send_to(&query, id_to_addr_screen(*pid as u16))?;
+        }
+        Ok(())
+    }
+
+    // Al arrancar, espera a que una líder se anuncie antes de tomar pedidos. Si nadie la
+    // anuncia a tiempo, por ejemplo porque la de id mayor nunca arrancó, busca una con una
+    // elección.
+    fn discover_leader(&mut self) -> Result<(), ScreenError> {
+        info!(
+            "[Screen {}] Busco a la lider entre las pantallas {:?}",
+            self.id, self.peers
+        );
+        let deadline = Instant::now() + DISCOVERY_TIMEOUT;
+        let mut next_query = Instant::now();
+        let mut buf = [0; SCREEN_BUFFER_SIZE];
+        while Instant::now() < deadline {
+            if Instant::now() >= next_query {
+                self.query_leader()?;
+                next_query = Instant::now() + DISCOVERY_RETRY_INTERVAL;
+            }
+            self.socket.set_read_timeout(Some(
+                next_query
+                    .min(deadline)
+                    .saturating_duration_since(Instant::now())
+                    .max(Duration::from_millis(1)),
+            ))?;
+            let (size, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(error)
+                    if error.kind() == ErrorKind::WouldBlock
+                        || error.kind() == ErrorKind::TimedOut =>
+                {
+                    continue
+                }
+                Err(error) => return Err(error.into()),
+            };
+            let msg = match Messages::try_from(buf[0]) {
+                Ok(msg) => msg,
+                Err(_) => continue,
+            };
+            let payload = &buf[1..size];
+            // solo sirve el anuncio de una líder de un mandato que no sea viejo
+            let announced = match msg {
+                Messages::Coordinator => Some(Coordinator::from_bytes(payload)?.term),
+                Messages::Membership => Some(Membership::from_bytes(payload)?.term),
+                Messages::LeaderHeartbeat => Some(LeaderHeartbeat::from_bytes(payload)?.term),
+                Messages::AppendEntries => Some(AppendEntries::from_bytes(payload)?.term),
+                Messages::InstallSnapshot => Some(InstallSnapshot::from_bytes(payload)?.term),
+                _ => None,
+            };
+            let valid = announced.is_some_and(|term| term >= self.term);
+            match msg {
+                Messages::Coordinator => self.handle_coordinator(payload, from)?,
+                Messages::Membership => self.handle_membership(payload)?,
+                Messages::LeaderHeartbeat => self.handle_leader_heartbeat(payload)?,
+                Messages::AppendEntries => self.handle_append_entries(payload, from)?,
+                Messages::InstallSnapshot => self.handle_install_snapshot(payload, from)?,
+                Messages::RequestVote => self.handle_request_vote(payload, from)?,
+                Messages::BullyElection => {
+                    // otra pantalla tampoco encontró a la líder, sigo su elección
+                    return self.handle_election(payload, from);
+                }
+                _ => debug!(
+                    "[Screen {}] Ignoro un {:?} mientras busco a la lider",
+                    self.id, msg
+                ),
+            }
+            if valid {
+                return Ok(());
+            }
+        }
+        info!(
+            "[Screen {}] Ninguna pantalla me dijo quien es la lider, arranco una eleccion",
+            self.id
+        );
+        self.leader_election()
+    }
+
+    // La líder contesta con su COORDINATOR. Las demás le pasan la pregunta a su líder, una
+    // sola vez para que no quede dando vueltas.
+    pub fn handle_leader_query(&self, buffer: &[u8]) -> Result<(), ScreenError> {
+        let query = LeaderQuery::from_bytes(buffer)?;
+        if self.status == BeingLeader {
+            self.socket.send_to(
+                &Coordinator::new(self.id, self.term).as_bytes(),
+                id_to_addr_screen(query.owner as u16),
+            )?;
+            return Ok(());
+        }
+        let leader = addr_to_id_leader(self.leader);
+        if query.forwarded
+            || self.status == ElectingNewLeader
+            || leader == self.id
+            || leader == query.owner
+        {
+            return Ok(());
+        }
+        let forwarded = LeaderQuery {
+            owner: query.owner,
+            forwarded: true,
+        };
+        self.socket
+            .send_to(&forwarded.as_bytes(), id_to_addr_screen(leader as u16))?;
+        Ok(())
+    }
+
+    // ======== RESPALDO DE LA LÍDER ========
+
+    // Con Bully, la líder le manda lo que cambia en su cola a la pantalla que la reemplazaría.
+    // Si esta pantalla era ese respaldo, el coordinador arranca con la cola copiada.
+    fn start_standby_stream(
+        &mut self,
+    ) -> (
+        Option<mpsc::Sender<OrderCommand>>,
+        Option<RestoreOrdersActorMessage>,
+    ) {
+        let mirror = self
+            .standby_mirror
+            .take()
+            .and_then(StandbyMirror::into_mirror);
+        if let Some(mirror) = &mirror {
+            info!(
+                "[Screen {}] Retomo la cola que copie de la lider anterior: {} pedido(s) sin resolver",
+                self.id,
+                mirror.pending_len()
+            );
+        }
+        let restore = mirror.as_ref().map(QueueMirror::restore);
+        let standby = standby_for(self.id, &self.peers);
+        if let Some(standby) = standby {
+            info!(
+                "[Screen {}] Le copio la cola a mi respaldo, la pantalla {}",
+                self.id, standby
+            );
+        }
+        let (order_log, order_log_rx) = mpsc::channel();
+        self.order_log = Some(order_log_rx);
+        self.standby_stream = Some(StandbyStream::new(standby, mirror.unwrap_or_default()));
+        (Some(order_log), restore)
+    }
+
+    // El respaldo espera menos que el resto para reemplazar a la líder, porque ya tiene su cola
+    fn election_timeout(&self) -> Duration {
+        if self.standby_mirror.is_some() {
+            STANDBY_TIMEOUT
+        } else {
+            LEADER_TIMEOUT
+        }
+    }
+
+    fn send_queue_updates(
+        &self,
+        standby: Option<u8>,
+        updates: Vec<QueueUpdate>,
+    ) -> Result<(), ScreenError> {
+        let standby = match standby {
+            Some(standby) => standby,
+            None => return Ok(()),
+        };
+        for update in updates {
+            self.socket
+                .send_to(&update.as_bytes(), id_to_addr_screen(standby as u16))?;
+        }
+        Ok(())
+    }
+
+    // Le manda al respaldo la cola entera
+    fn resync_standby(&mut self) -> Result<(), ScreenError> {
+        let (id, term) = (self.id, self.term);
+        let (standby, updates) = match self.standby_stream.as_mut() {
+            Some(stream) => (stream.standby, stream.resync(id, term)),
+            None => return Ok(()),
+        };
+        self.send_queue_updates(standby, updates)
+    }
+
+    // Como líder con Bully, le manda al respaldo lo que cambió en la cola, o un latido
+    fn stream_to_standby(&mut self) -> Result<(), ScreenError> {
+        let (id, term) = (self.id, self.term);
+        let (stream, order_log) = match (self.standby_stream.as_mut(), &self.order_log) {
+            (Some(stream), Some(order_log)) => (stream, order_log),
+            _ => return Ok(()),
+        };
+        let commands: Vec<OrderCommand> = order_log.try_iter().collect();
+        let standby = stream.standby;
+        let updates = stream.updates(id, term, commands);
+        self.send_queue_updates(standby, updates)
+    }
+
+    pub fn handle_queue_update(
+        &mut self,
+        buffer: &[u8],
+        from: SocketAddr,
+    ) -> Result<(), ScreenError> {
+        let update = QueueUpdate::from_bytes(buffer)?;
+        if self.raft.is_some()
+            || self.status == BeingLeader
+            || update.term < self.term
+            || id_to_addr_leader(update.owner as u16) != self.leader
+        {
+            return Ok(());
+        }
+        self.leader_is_alive();
+        let id = self.id;
+        let mirror = match self.standby_mirror.as_mut() {
+            Some(mirror) if mirror.leader == update.owner => mirror,
+            _ => self
+                .standby_mirror
+                .insert(StandbyMirror::new(id, update.owner)),
+        };
+        if !mirror.receive(id, update) {
+            self.socket
+                .send_to(&QueueResync::new(id).as_bytes(), from)?;
+        }
+        Ok(())
+    }
+
+    pub fn handle_queue_resync(&mut self, buffer: &[u8]) -> Result<(), ScreenError> {
+        let resync = QueueResync::from_bytes(buffer)?;
+        if self
+            .standby_stream
+            .as_ref()
+            .is_some_and(|stream| stream.standby == Some(resync.owner))
+        {
+            info!(
+                "[Screen {}] Mi respaldo {} me pide la cola entera",
+                self.id, resync.owner
+            );
+            self.resync_standby()?;
+        }
+        Ok(())
+    }
+
+    // ======== ELECCIÓN RAFT ========
+
+    // Guarda el mandato, el voto y el log. Si no puede, sigue igual.
+    fn save_raft(&self) {
+        if let Some(raft) = &self.raft {
+            if let Err(error) = raft.save(self.id, self.term) {
+                error!(
+                    "[Screen {}] No pude guardar el estado de Raft: {}",
+                    self.id, error
+                );
+            }
+        }
+    }
+
+    // Se postula hasta ganar, hasta que aparezca una líder del mandato o hasta que alguien
+    // tenga un mandato más nuevo. Sin mayoría, se vuelve a postular cuando vence el plazo.
+    fn raft_election(&mut self) -> Result<(), ScreenError> {
+        self.status = ElectingNewLeader;
+        self.start_candidacy()?;
+        while self.status == ElectingNewLeader {
+            let deadline = match &self.raft {
+                Some(raft) => raft.election_deadline,
+                None => break,
+            };
+            self.socket.set_read_timeout(Some(
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .max(Duration::from_millis(1)),
+            ))?;
+            self.listen_socket()?;
+        }
+        Ok(())
+    }
+
+    fn start_candidacy(&mut self) -> Result<(), ScreenError> {
+        self.adopt_term(self.term + 1);
+        let raft = match self.raft.as_mut() {
+            Some(raft) => raft,
+            None => return Ok(()),
+        };
+        raft.start_candidacy(self.id);
+        info!(
+            "[Screen {}] Me postulo como lider del mandato {}",
+            self.id, self.term
+        );
+        // sin otras pantallas, el voto propio ya es mayoría
+        if raft.add_vote(self.id, self.peers.len()) {
+            self.status = BeingLeader;
+        }
+        let request = RequestVote {
+            owner: self.id,
+            term: self.term,
+            last_log_index: raft.last_index() as u32,
+            last_log_term: raft.last_term(),
+        };
+        self.save_raft();
+        for pid in self.peers.iter() {
+            if *pid != self.id {
+                self.socket
+                    .send_to(&request.as_bytes(), id_to_addr_screen(*pid as u16))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Alguien tiene un mandato más nuevo: lo adopta y vuelve a seguir a una líder
+    fn observe_term(&mut self, term: Term) {
+        if term <= self.term {
+            return;
+        }
+        if self.status == BeingLeader {
+            self.step_down(term);
+        }
+        if self.status == ElectingNewLeader {
+            self.status = ReadingOrder;
+        }
+        self.adopt_term(term);
+        if let Some(raft) = self.raft.as_mut() {
+            raft.voted_for = None;
+        }
+        self.save_raft();
+    }
+
+    pub fn handle_request_vote(
+        &mut self,
+        buffer: &[u8],
+        from: SocketAddr,
+    ) -> Result<(), ScreenError> {
+        let request = RequestVote::from_bytes(buffer)?;
+        self.observe_term(request.term);
+        let raft = match self.raft.as_mut() {
+            Some(raft) => raft,
+            None => return Ok(()),
+        };
+        let granted = request.term == self.term
+            && raft.can_vote(
+                request.owner,
+                request.last_log_index as usize,
+                request.last_log_term,
+            );
+        if granted {
+            raft.voted_for = Some(request.owner);
+            raft.reset_election_deadline();
+            self.save_raft();
+        }
+        info!(
+            "[Screen {}] {} mi voto a {} para el mandato {}",
+            self.id,
+            if granted { "Le doy" } else { "No le doy" },
+            request.owner,
+            request.term
+        );
+        let vote = Vote {
+            owner: self.id,
+            term: self.term,
+            granted,
+        };
+        self.socket.send_to(&vote.as_bytes(), from)?;
+        Ok(())
+    }
+
+    pub fn handle_vote(&mut self, buffer: &[u8]) -> Result<(), ScreenError> {
+        let vote = Vote::from_bytes(buffer)?;
+        self.observe_term(vote.term);
+        if self.status != ElectingNewLeader || vote.term != self.term || !vote.granted {
+            return Ok(());
+        }
+        let cluster_size = self.peers.len();
+        if let Some(raft) = self.raft.as_mut() {
+            if raft.add_vote(vote.owner, cluster_size) {
+                info!(
+                    "[Screen {}] Tengo la mayoria de los votos del mandato {}",
+                    self.id, self.term
+                );
+                self.status = BeingLeader;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn handle_append_entries(
+        &mut self,
+        buffer: &[u8],
+        from: SocketAddr,
+    ) -> Result<(), ScreenError> {
+        let append = AppendEntries::from_bytes(buffer)?;
+        let mut reply = AppendReply {
+            owner: self.id,
+            term: self.term,
+            success: false,
+            match_index: 0,
+            conflict_index: 0,
+        };
+        if append.term < self.term {
+            // una líder de un mandato viejo, la respuesta le avisa el actual
+            self.socket.send_to(&reply.as_bytes(), from)?;
+            return Ok(());
+        }
+        self.follow_raft_leader(append.owner, append.term)?;
+
+        let raft = match self.raft.as_mut() {
+            Some(raft) => raft,
+            None => return Ok(()),
+        };
+        let appended = !append.entries.is_empty();
+        let followed = raft.follow(
+            append.prev_log_index as usize,
+            append.prev_log_term,
+            append.entries,
+            append.leader_commit as usize,
+        );
+        let compacted = raft.compact();
+        match followed {
+            Ok(matched) => {
+                reply.success = true;
+                reply.match_index = matched as u32;
+            }
+            Err(conflict) => reply.conflict_index = conflict as u32,
+        }
+        if (appended && reply.success) || compacted {
+            self.save_raft();
+        }
+        reply.term = self.term;
+        self.socket.send_to(&reply.as_bytes(), from)?;
+        Ok(())
+    }
+
+    pub fn handle_install_snapshot(
+        &mut self,
+        buffer: &[u8],
+        from: SocketAddr,
+    ) -> Result<(), ScreenError> {
+        let install = InstallSnapshot::from_bytes(buffer)?;
+        let mut reply = AppendReply {
+            owner: self.id,
+            term: self.term,
+            success: false,
+            match_index: 0,
+            conflict_index: 0,
+        };
+        if install.term < self.term {
+            self.socket.send_to(&reply.as_bytes(), from)?;
+            return Ok(());
+        }
+        self.follow_raft_leader(install.owner, install.term)?;
+
+        let raft = match self.raft.as_mut() {
+            Some(raft) => raft,
+            None => return Ok(()),
+        };
+        let matched = match raft.receive_snapshot(
+            install.last_index as usize,
+            install.last_term,
+            install.seq,
+            install.done,
+            install.commands,
+        ) {
+            Some(matched) => matched,
+            // faltan pedazos
+            None => return Ok(()),
+        };
+        info!(
+            "[Screen {}] Instale el snapshot de la lider hasta la entrada {}",
+            self.id, matched
+        );
+        self.save_raft();
+        reply.term = self.term;
+        reply.success = true;
+        reply.match_index = matched as u32;
+        self.socket.send_to(&reply.as_bytes(), from)?;
+        Ok(())
+    }
+
+    // Reconoce a la líder del mandato que le manda el log, que puede ser una nueva.
+    fn follow_raft_leader(&mut self, owner: u8, term: Term) -> Result<(), ScreenError> {
+        self.observe_term(term);
+        if self.status == ElectingNewLeader {
+            // otra pantalla ganó la elección de este mandato
+            self.status = ReadingOrder;
+        }
+        let leader = id_to_addr_leader(owner as u16);
+        if self.leader != leader {
+            info!(
+                "[Screen {}] La lider del mandato {} es {}",
+                self.id, term, owner
+            );
+            self.leader = leader;
+            self.leader_detector.restart();
+            self.save_checkpoint();
+            self.resend_orders(leader)?;
+            self.join_leader()?;
+        }
+        self.leader_is_alive();
+        Ok(())
+    }
+
+    pub fn handle_append_reply(&mut self, buffer: &[u8]) -> Result<(), ScreenError> {
+        let reply = AppendReply::from_bytes(buffer)?;
+        self.observe_term(reply.term);
+        if self.status != BeingLeader || reply.term != self.term {
+            return Ok(());
+        }
+        let cluster_size = self.peers.len();
+        let mut compacted = false;
+        if let Some(raft) = self.raft.as_mut() {
+            raft.acknowledge(
+                reply.owner,
+                reply.success,
+                reply.match_index as usize,
+                reply.conflict_index as usize,
+            );
+            if raft.advance_commit(self.id, self.term, cluster_size) {
+                debug!(
+                    "[Screen {}] La mayoria ya tiene el log hasta la entrada {}",
+                    self.id, raft.commit_index
+                );
+                compacted = raft.compact();
+            }
+        }
+        if compacted {
+            self.save_raft();
+        }
+        if !reply.success {
+            // le faltaban entradas anteriores, se las manda desde donde indicó
+            self.send_append_entries(reply.owner)?;
+        }
+        Ok(())
+    }
+
+    fn send_append_entries(&self, peer: u8) -> Result<(), ScreenError> {
+        let raft = match &self.raft {
+            Some(raft) => raft,
+            None => return Ok(()),
+        };
+        let (prev_log_index, prev_log_term, entries) = match raft.entries_for(peer) {
+            Some(entries) => entries,
+            None => return self.send_snapshot(peer),
+        };
+        let append = AppendEntries {
+            owner: self.id,
+            term: self.term,
+            prev_log_index: prev_log_index as u32,
+            prev_log_term,
+            leader_commit: raft.commit_index as u32,
+            entries,
+        };
+        self.socket
+            .send_to(&append.as_bytes(), id_to_addr_screen(peer as u16))?;
+        Ok(())
+    }
+
+    // Le manda el snapshot entero a una pantalla a la que le faltan entradas ya compactadas.
+    fn send_snapshot(&self, peer: u8) -> Result<(), ScreenError> {
+        let raft = match &self.raft {
+            Some(raft) => raft,
+            None => return Ok(()),
+        };
+        let (last_index, last_term, chunks) = raft.snapshot_chunks();
+        let total = chunks.len();
+        for (seq, commands) in chunks.into_iter().enumerate() {
+            let install = InstallSnapshot {
+                owner: self.id,
+                term: self.term,
+                last_index: last_index as u32,
+                last_term,
+                seq: seq as u32,
+                done: seq + 1 == total,
+                commands,
+            };
+            self.socket
+                .send_to(&install.as_bytes(), id_to_addr_screen(peer as u16))?;
+        }
+        Ok(())
+    }
+
+    // Como líder, agrega al log lo que pasó en la cola del coordinador y se lo manda al
+    // resto. Si no hay nada nuevo, igual manda un latido cada RAFT_HEARTBEAT_INTERVAL.
+    fn replicate(&mut self) -> Result<(), ScreenError> {
+        let (raft, order_log) = match (self.raft.as_mut(), &self.order_log) {
+            (Some(raft), Some(order_log)) => (raft, order_log),
+            _ => return Ok(()),
+        };
+        let mut appended = false;
+        while let Ok(command) = order_log.try_recv() {
+            raft.append(self.term, command);
+            appended = true;
+        }
+        if !appended && Instant::now() < raft.next_heartbeat {
+            return Ok(());
+        }
+        raft.next_heartbeat = Instant::now() + RAFT_HEARTBEAT_INTERVAL;
+        if appended {
+            raft.advance_commit(self.id, self.term, self.peers.len());
+            raft.compact();
+            self.save_raft();
+        }
+        for pid in self.peers.iter() {
+            if *pid != self.id {
+                self.send_append_entries(*pid)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Reconcilia los pedidos que quedaron en curso antes del reinicio. A los que esperaban
+    // al gateway se les vuelve a pedir la captura, y si el gateway ya la tenía responde
+    // DuplicatedOrder. Los que ya se cobraron vuelven a la líder, que reenvía el resultado
+    // si ya se prepararon.
+    fn recover_orders(&mut self) -> Result<(), ScreenError> {
+        for in_flight in self.orders.values() {
+            if in_flight.state == OrderState::WaitingGatewayResponse {
+                self.send_capture(&in_flight.order)?;
+            }
+        }
+        self.leader_is_alive();
+        self.resend_orders(self.leader)
+    }
+
+    // Le manda todos los pedidos ya cobrados que esperan su resultado
+    fn resend_orders(&self, to: (Ipv4Addr, u16)) -> Result<(), ScreenError> {
+        for (order_id, in_flight) in self.orders.iter() {
+            if in_flight.state == OrderState::WaitingRobotResponse {
+                debug!(
+                    "[Screen {}] Le vuelvo a mandar el pedido {} a la lider",
+                    self.id, order_id
+                );
+                self.socket.send_to(&in_flight.order.as_bytes(), to)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Toma el pedido del siguiente cliente y le pide al gateway que capture el pago
+    fn take_order(&mut self, sourced: SourcedOrder) -> Result<(), ScreenError> {
+        let order = sourced.order;
+        let order_id = self.next_order_id;
+        self.next_order_id = self.next_order_id.wrapping_add(1);
+        let priority = order.priority;
+        let sla = order
+            .sla_secs
+            .map_or(DEFAULT_ORDER_SLA, Duration::from_secs);
+        let order_msg = Order::new(
+            self.id,
+            order_id,
+            order.items,
+            order.container,
+            priority,
+            now_millis().saturating_add(u64::try_from(sla.as_millis()).unwrap_or(u64::MAX)),
+            self.term,
+        );
+        println!("[Screen {}] Cree la orden {:?}", self.id, order_msg);
+        self.send_capture(&order_msg)?;
+
+        self.orders.insert(
+            order_id,
+            InFlightOrder {
+                order: order_msg,
+                state: OrderState::WaitingGatewayResponse,
+                since: Instant::now(),
+                cancel_at: order
+                    .cancel_after_secs
+                    .and_then(|secs| Instant::now().checked_add(Duration::from_secs(secs))),
+                restored: false,
+                capture_retry_at: Instant::now() + CAPTURE_RETRY_INTERVAL,
+                capture_retried: false,
+            },
+        );
+        self.source_line = sourced.line;
+        self.save_checkpoint();
+        Ok(())
+    }
+
+    // ======== PARTE DONDE SE COMUNICA CON EL GATEWAY ========
+    fn send_capture(&self, order: &Order) -> Result<(), ScreenError> {
+        let prepare = GatewayAction::Capture {
+            order_id: order.order_id,
+            card_number: CARDNUMBER,
+            amount: Self::order_price(order.priority),
+            owner_id: self.id,
+            priority: order.priority,
+        };
+        let prepare_bytes: Vec<u8> = prepare.clone().into();
+
+        self.socket
+            .send_to(&prepare_bytes, (Ipv4Addr::new(127, 0, 0, 1), 6000))?;
+        info!(
+            "[Screen {}] Envíe Capture al Gateway {:?}",
+            self.id, prepare
+        );
+        Ok(())
+    }
+
+    // ======== PARTE DONDE SE LE ENVIA EL PEDIDO AL LIDER ========
+    pub fn handle_gateway_response(&mut self, buf: &[u8]) -> Result<(), ScreenError> {
+        let reply = GatewayReply::from_bytes(buf)?;
+        let in_flight = match self.orders.get_mut(&reply.order_id) {
+            Some(in_flight) if in_flight.state == OrderState::WaitingGatewayResponse => in_flight,
+            _ => {
+                debug!(
+                    "[Screen {}] Respuesta del gateway para un pedido que no espera una: {:?}",
+                    self.id, reply
+                );
+                return Ok(());
+            }
+        };
+
+        // un pedido retomado o cuya captura se reintentó puede estar capturado de antes
+        let captured = reply.response == GatewayResponse::Acknowledge
+            || ((in_flight.restored || in_flight.capture_retried)
+                && reply.response == GatewayResponse::DuplicatedOrder);
+        if !captured {
+            println!(
+                "[Screen {}] El Gateway rechazó la captura del Pedido {}: {:?}",
+                self.id, reply.order_id, reply.response
+            );
+            self.orders.remove(&reply.order_id);
+            self.save_checkpoint();
+            return Ok(());
+        }
+
+        println!(
+            "[Screen {}] El Gateway confirmó la captura del Pedido {}",
+            self.id, reply.order_id
+        );
+        in_flight.state = OrderState::WaitingRobotResponse;
+        self.socket
+            .send_to(&in_flight.order.as_bytes(), self.leader)?;
+        info!(
+            "[Screen {}] Pedido {} enviado a Leader",
+            self.id, reply.order_id
+        );
+        debug!(
+            "[Screen {}] El pedido contiene {:?}",
+            self.id, in_flight.order
+        );
+        println!(
+            "[Screen {}] Se encargó el pedido {}, esperando resultado...",
+            self.id, reply.order_id
+        );
+        self.save_checkpoint();
+        Ok(())
+    }
+
+    // Vuelve a pedir la captura de los pedidos a los que el gateway no respondió, por si se
+    // perdió el pedido o la respuesta. Mientras tanto ocupan el lugar de un cliente.
+    fn retry_captures(&mut self) -> Result<(), ScreenError> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for (order_id, in_flight) in self.orders.iter_mut() {
+            if in_flight.state == OrderState::WaitingGatewayResponse
+                && in_flight.capture_retry_at <= now
+            {
+                in_flight.capture_retry_at = now + CAPTURE_RETRY_INTERVAL;
+                in_flight.capture_retried = true;
+                due.push(*order_id);
+            }
+        }
+        for order_id in due {
+            if let Some(in_flight) = self.orders.get(&order_id) {
+                info!(
+                    "[Screen {}] El gateway no respondio la captura del pedido {}, la vuelvo a pedir",
+                    self.id, order_id
+                );
+                self.send_capture(&in_flight.order)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Si algún cliente se cansó de esperar le pide a la líder que cancele su pedido. El
+    // resultado llega como cualquier otro: Abort si se canceló o Ready si ya se preparó.
+    fn cancel_due_orders(&mut self) -> Result<(), ScreenError> {
+        let now = Instant::now();
+        for (order_id, in_flight) in self.orders.iter_mut() {
+            match in_flight.cancel_at {
+                Some(at) if at <= now && in_flight.state == OrderState::WaitingRobotResponse => {
+                    println!(
+                        "[Screen {}] El cliente cancela el pedido {}",
+                        self.id, order_id
+                    );
+                    let cancel = CancelOrder::new(self.id, *order_id);
+                    self.socket.send_to(&cancel.as_bytes(), self.leader)?;
+                    in_flight.cancel_at = None;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Espera hasta la próxima cancelación, el próximo reintento de captura o hasta que haya
+    // que chequear a la líder
+    fn next_timeout(&self) -> Duration {
+        let now = Instant::now();
+        let mut wake_at = now + LEADER_TIMEOUT;
+        match &self.raft {
+            Some(raft) if self.status == BeingLeader => {
+                wake_at = wake_at.min(raft.next_heartbeat);
+            }
+            Some(raft) => wake_at = wake_at.min(raft.election_deadline),
+            None if self.status == BeingLeader => {
+                if let Some(stream) = &self.standby_stream {
+                    wake_at = wake_at.min(stream.next_heartbeat());
+                }
+            }
+            None => {
+                wake_at = wake_at.min(self.leader_deadline);
+                if let Some(mirror) = &self.standby_mirror {
+                    wake_at = wake_at.min(mirror.deadline());
+                }
+            }
+        }
+        for in_flight in self.orders.values() {
+            match (in_flight.cancel_at, in_flight.state) {
+                (Some(at), OrderState::WaitingRobotResponse) => wake_at = wake_at.min(at),
+                (_, OrderState::WaitingGatewayResponse) => {
+                    wake_at = wake_at.min(in_flight.capture_retry_at);
+                }
+                _ => {}
+            }
+        }
+        wake_at
+            .saturating_duration_since(now)
+            .max(Duration::from_millis(1))
+    }
+
+    fn handle_timeout(&mut self) -> Result<(), ScreenError> {
+        self.cancel_due_orders()?;
+        self.retry_captures()?;
+        self.watch_leader()
+    }
+
+    // Se fija si la líder dejó de dar señales de vida. Se llama después de cada mensaje y
+    // no solo cuando vence el timeout del socket, para que una pantalla que recibe mensajes
+    // seguido igual note a tiempo que la líder se cayó.
+    fn watch_leader(&mut self) -> Result<(), ScreenError> {
+        if let Some(raft) = &self.raft {
+            if self.status != BeingLeader && Instant::now() >= raft.election_deadline {
+                info!(
+                    "[Screen {}] No tengo noticias de la lider del mandato {}, me postulo",
+                    self.id, self.term
+                );
+                self.leader_election()?;
+            }
+            return Ok(());
+        }
+        if self.status != BeingLeader
+            && self
+                .standby_mirror
+                .as_ref()
+                .is_some_and(StandbyMirror::expired)
+        {
+            info!(
+                "[Screen {}] La lider no me manda su cola hace rato, busco reemplazarla",
+                self.id
+            );
+            return self.leader_election();
+        }
+        // la líder no se vigila a sí misma
+        if self.status == BeingLeader || Instant::now() < self.leader_deadline {
+            return Ok(());
+        }
+        if self.waiting_keepalive {
+            info!(
+                "[Screen {}] El lider No me respondio el KEEPALIVE, arranco busqueda de nuevo lider",
+                self.id
+            );
+            self.leader_election()?;
+        } else {
+            info!(
+                "[Screen {}] Tarda mucho el lider (phi {:.1})! Le mando un KEEPALIVE",
+                self.id,
+                self.leader_detector.phi()
+            );
+            self.socket
+                .send_to(&KeepAlive::new(self.id).as_bytes(), self.leader)?;
+            self.waiting_keepalive = true;
+            self.leader_deadline = Instant::now() + self.leader_detector.timeout();
+        }
+        Ok(())
     }
 
     pub fn handle_orderresult(&mut self, buf: &[u8]) -> Result<(), ScreenError> {
         let order_result = OrderResult::from_bytes(buf)?;
+        self.leader_is_alive();
+        let in_flight = match self.orders.get(&order_result.order_id) {
+            Some(in_flight) if in_flight.state == OrderState::WaitingRobotResponse => {
+                self.orders.remove(&order_result.order_id)
+            }
+            _ => None,
+        };
+        let in_flight = match in_flight {
+            Some(in_flight) => in_flight,
+            None => {
+                // la líder nueva puede reenviar un resultado que ya se cobró
+                debug!(
+                    "[Screen {}] Resultado repetido del pedido {}",
+                    self.id, order_result.order_id
+                );
+                return Ok(());
+            }
+        };
         println!(
             "[Screen {}] Recibí el resultado del pedido: {:?}",
             self.id, order_result
         );
+        println!(
+            "[Screen {}] El cliente del pedido {} esperó {:.1}s",
+            self.id,
+            order_result.order_id,
+            in_flight.since.elapsed().as_secs_f64()
+        );
 
         // ======== PARTE DONDE SE COMUNICA CON EL GATEWAY ========
         let gateway_msg_bytes: Vec<u8> = if order_result.status == OrderStatus::Ready {
@@ -225,247 +1774,215 @@ impl Screen {
 
         info!("[Screen {}] Le envié al gateway el resultado", self.id);
         // ========================================================
+        self.save_checkpoint();
         Ok(())
     }
 
     pub fn start(&mut self) -> Result<(), ScreenError> {
         info!("[Screen {}] Arranque a funcionar", self.id);
-        let mut order_id: u8 = 1;
         if self.status == BeingLeader {
-            // Ejecuto la lógica del líder, esto es cuando es la primer pantalla lider
-            self.run_leader_logic()?;
+            // Levanto la lógica del líder, esto es cuando es la primer pantalla lider
+            self.adopt_term(self.term + 1);
+            self.save_raft();
+            self.become_leader()?;
         } else {
-            // espero a que me llegue el coordinator
-            let mut buf = [0; 100];
-            let (_, from) = self.socket.recv_from(&mut buf)?;
-            let msg = Messages::try_from(buf[0])?;
+            // la líder del checkpoint es solo una pista: puede haberse caído o haber sido
+            // destituida. Se le pide sumarse, y si sigue siendo la líder contesta con su
+            // MEMBERSHIP, pero igual no se toman pedidos hasta que una líder se anuncie.
+            if self.resumed {
+                self.join_leader()?;
+            }
+            self.discover_leader()?;
+            if self.status == BeingLeader && self.leader_system.is_none() {
+                self.become_leader()?;
+            }
             info!(
-                "Me llego el {:?} de {:?}, arranco con los pedidos",
-                msg, from
+                "[Screen {}] La lider es {}, arranco con los pedidos",
+                self.id,
+                addr_to_id_leader(self.leader)
             );
-            thread::sleep(Duration::from_secs(1));
-            while let Some(line) = self.file_lines.next() {
-                // Leer cada linea del archivo 📂 y procesar el pedido
-                let line = line.expect("Error al leer una linea del archivo");
-
-                // Parsear la linea como un objeto JSON
-                let order: OrderJson =
-                    serde_json::from_str(&line).expect("Error al deserializar la orden");
-
-                self.current_order = Some(Order::new(self.id, order_id, order.items));
-                let order_msg = match &self.current_order {
-                    Some(order) => order,
-                    None => continue,
-                };
-                println!("[Screen {}] Cree la orden {:?}", self.id, order_msg);
-                let order_msg_clone = order_msg.clone();
-                let order_bytes = order_msg.as_bytes();
-
-                // ======== PARTE DONDE SE COMUNICA CON EL GATEWAY ========
-
-                let prepare = GatewayAction::Capture {
-                    order_id,
-                    card_number: CARDNUMBER,
-                    amount: PRICE,
-                    owner_id: self.id,
-                };
-                let prepare_bytes: Vec<u8> = prepare.clone().into();
-
-                self.socket
-                    .send_to(&prepare_bytes, (Ipv4Addr::new(127, 0, 0, 1), 6000))?;
-                self.status = WaitingGatewayResponse;
-
-                info!(
-                    "[Screen {}] Envíe Capture al Gateway {:?}",
-                    self.id, prepare
-                );
-                self.listen_socket()?;
-
-                // ========================================================
-
-                // ======== PARTE DONDE SE LE ENVIA EL PEDIDO AL LIDER ========
-
-                if self.status == WaitingRobotResponse {
-                    println!(
-                        "[Screen {}] El Gateway confirmó la captura del Pedido {}",
-                        self.id, order_msg_clone.order_id
-                    );
-                    self.socket
-                        .send_to(&order_bytes, self.leader)
-                        .expect("Error al enviar el mensaje");
-                    info!(
-                        "[Screen {}] Pedido {} enviado a Leader",
-                        self.id, order_msg_clone.order_id
-                    );
-                    debug!(
-                        "[Screen {}] El pedido contiene {:?}",
-                        self.id, order_msg_clone
-                    );
-                    self.socket
-                        .set_read_timeout(Some(Duration::from_secs(10)))?;
+            if !self.resumed {
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+        if self.resumed {
+            // el sistema ya estaba andando, la líder es la del checkpoint o esta pantalla
+            self.recover_orders()?;
+        }
 
-                    // espero resultado:
-                    println!(
-                        "[Screen {}] Se encargó el pedido {}, esperando resultado...",
-                        self.id, order_msg_clone.order_id
-                    );
-                    self.listen_socket()?;
-                } else {
-                    println!(
-                        "[Screen {}] El Gateway rechazó la captura del Pedido {}",
-                        self.id, order_msg_clone.order_id
-                    );
+        let orders_rx: Receiver<SourcedOrder> = match self.source.take() {
+            Some(source) => order_source::spawn(
+                source,
+                RejectionReport::for_screen(self.id),
+                self.source_line,
+            ),
+            None => return Ok(()),
+        };
+        let mut source_open = true;
+        loop {
+            // Tomar pedidos de la fuente 📂 mientras haya lugar para otro cliente
+            while source_open && self.orders.len() < MAX_ORDERS_IN_FLIGHT {
+                match orders_rx.try_recv() {
+                    Ok(order) => self.take_order(order)?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => source_open = false,
                 }
+            }
+            // la líder sigue coordinando a las demás aunque no le queden clientes propios
+            if !source_open && self.orders.is_empty() && self.leader_system.is_none() {
+                break;
+            }
 
-                if self.status == BeingLeader {
-                    self.run_leader_logic()?;
-                }
-                // ============================================================
-                order_id += 1;
+            let mut timeout = self.next_timeout();
+            if source_open && self.orders.len() < MAX_ORDERS_IN_FLIGHT {
+                timeout = timeout.min(ORDER_POLL_INTERVAL);
+            }
+            self.socket.set_read_timeout(Some(timeout))?;
+            self.listen_socket()?;
+
+            if self.status == BeingLeader && self.leader_system.is_none() {
+                self.become_leader()?;
+            }
+            if self.status == BeingLeader {
+                self.replicate()?;
+                self.stream_to_standby()?;
+                self.check_members()?;
+            } else {
+                self.watch_leader()?;
+            }
+            // un robot o una pantalla que conoce un mandato más nuevo destituyó a la líder
+            if self
+                .leader_system
+                .as_ref()
+                .is_some_and(|leader_system| leader_system.is_finished())
+            {
+                self.step_down(self.term);
+                self.leader_election()?;
             }
         }
+        // ya no atiende clientes, deja de participar de las elecciones
+        let leader = addr_to_id_leader(self.leader);
+        self.socket.send_to(
+            &ScreenLeave::new(self.id).as_bytes(),
+            id_to_addr_screen(leader as u16),
+        )?;
         Ok(())
     }
 
+    // Espera un mensaje, o que venza el timeout del socket, y lo maneja
     pub fn listen_socket(&mut self) -> Result<(), ScreenError> {
-        let mut waiting_keepalive = false;
-        loop {
-            let mut buf = [0; 1024];
-            let (size, from) = match self.socket.recv_from(&mut buf) {
-                Ok(result) => result,
-                Err(ref error) if error.kind() == ErrorKind::WouldBlock => {
-                    // HUBO TIMEOUT
-                    match &self.status {
-                        WaitingRobotResponse => {
-                            if waiting_keepalive {
-                                info!("[Screen {}] El lider No me respondio el KEEPALIVE, arranco busqueda de nuevo lider", self.id);
-                                self.leader_election()?;
-                                if self.status == BeingLeader {
-                                    break;
-                                }
-                                self.status = WaitingRobotResponse;
-                                self.socket
-                                    .set_read_timeout(Some(Duration::from_secs(10)))?;
-                                waiting_keepalive = false;
-                            } else {
-                                info!(
-                                    "[Screen {}] Tarda mucho el lider! Le mando un KEEPALIVE",
-                                    self.id
-                                );
-                                waiting_keepalive = true;
-                                self.socket
-                                    .send_to(&KeepAlive::new(self.id).as_bytes(), self.leader)?;
-                            };
-                            continue;
-                        }
-                        ElectingNewLeader => {
-                            // si soy el de ID mayor mando el mensaje a todos anunciando mi nuevo mandato
-                            info!(
-                                "[Screen {}] Soy el lider! Envio mensaje COORDINATOR al resto",
-                                self.id
-                            );
-                            self.status = BeingLeader;
-                            break;
-                        }
-                        _ => {}
-                    }
-                    continue;
+        let mut buf = [0; SCREEN_BUFFER_SIZE];
+        let (size, from) = match self.socket.recv_from(&mut buf) {
+            Ok(result) => result,
+            Err(ref error) if error.kind() == ErrorKind::WouldBlock => {
+                // HUBO TIMEOUT
+                if self.status == ElectingNewLeader && self.raft.is_some() {
+                    // nadie ganó la elección a tiempo, me vuelvo a postular
+                    return self.start_candidacy();
                 }
-                Err(error) => return Err(error.into()),
-            };
+                if self.status == ElectingNewLeader && self.coordinator_deadline.is_some() {
+                    // leader_election se fija si ya venció la espera del COORDINATOR
+                    return Ok(());
+                }
+                if self.status == ElectingNewLeader {
+                    // si soy el de ID mayor mando el mensaje a todos anunciando mi nuevo mandato
+                    info!(
+                        "[Screen {}] Soy el lider! Envio mensaje COORDINATOR al resto",
+                        self.id
+                    );
+                    self.adopt_term(self.term + 1);
+                    self.status = BeingLeader;
+                    return Ok(());
+                }
+                return self.handle_timeout();
+            }
+            Err(error) => return Err(error.into()),
+        };
 
-            // NO HUBO TIMEOUT
-            if let Ok(msg) = Messages::try_from(buf[0]) {
-                debug!(
-                    "[Screen {}] Me llego un {:?} con el estado {:?}",
-                    self.id, msg, self.status
-                );
-                match (msg, &self.status) {
-                    // llega un mensaje de elección bully
-                    (Messages::BullyElection, WaitingGatewayResponse) => {
-                        self.handle_election(&buf[1..], from)?;
-                    }
-                    (Messages::BullyElection, WaitingRobotResponse) => {
-                        self.handle_election(&buf[1..], from)?;
-                        self.socket
-                            .set_read_timeout(Some(Duration::from_secs(10)))?;
-                        waiting_keepalive = false;
-                        if self.status == BeingLeader {
-                            break;
-                        }
-                    }
-                    (Messages::BullyElection, ElectingNewLeader) => {
-                        // me mandaron otro election, le mando ok y sigo con mi busqueda
-                        let msg = BullyOk { owner: self.id };
-                        self.socket.send_to(&msg.as_bytes(), from)?;
-                    }
-                    (Messages::BullyOk, ElectingNewLeader) => {
-                        // hay uno mayor que yo, que continue con la busqueda, me quedo esperando al nuevo lider
-                        self.socket.set_read_timeout(None)?;
-                    }
-                    // llega el mensaje de coordinador
-                    (Messages::Coordinator, WaitingRobotResponse) => {
-                        waiting_keepalive = false;
-                        self.handle_coordinator(&buf[1..size]);
-                        if let Some(ref order) = self.current_order {
-                            debug!(
-                                "[Screen {}] Le vuelvo a mandar el pedido a la lider",
-                                self.id
-                            );
-                            self.socket.send_to(&order.as_bytes(), self.leader)?;
-                        };
-                    }
-                    (Messages::Coordinator, ElectingNewLeader) => {
-                        // me llego el mensaje de nuevo lider
-                        self.handle_coordinator(&buf[1..size]);
-                        break;
-                    }
-                    (Messages::Coordinator, _) => self.handle_coordinator(&buf[1..size]),
-                    (Messages::GatewayResponse, WaitingGatewayResponse) => {
-                        let result = GatewayResponse::try_from(buf[1])?;
-                        match result {
-                            GatewayResponse::Acknowledge => {
-                                self.status = WaitingRobotResponse;
-                            }
-                            GatewayResponse::DuplicatedOrder => {
-                                self.status = ReadingOrder;
-                            }
-                            GatewayResponse::NoSuchPendingPayment => {
-                                self.status = ReadingOrder;
-                            }
-                            GatewayResponse::RejectedCard => {
-                                self.status = ReadingOrder;
-                            }
-                        }
-                        break;
-                    }
-                    (Messages::OrderResult, WaitingRobotResponse) => {
-                        self.handle_orderresult(&buf[1..size])?;
-                        break;
-                    }
-                    (Messages::OrderResult, ElectingNewLeader) => {
-                        let result = OrderResult::from_bytes(&buf[1..size])?;
-                        if result.status == OrderStatus::Ready {
-                            self.status = WaitingRobotResponse;
-                        } else {
-                            self.status = ReadingOrder;
-                        };
-                        break;
-                    }
-                    (Messages::KeepAlive, _) => {
-                        self.handle_keepalive(&buf[1..size], from)?;
+        // NO HUBO TIMEOUT
+        if let Ok(msg) = Messages::try_from(buf[0]) {
+            debug!(
+                "[Screen {}] Me llego un {:?} con el estado {:?}",
+                self.id, msg, self.status
+            );
+            match (msg, &self.status) {
+                // llega un mensaje de elección bully
+                (Messages::BullyElection, ElectingNewLeader) => {
+                    // me mandaron otro election, le mando ok y sigo con mi busqueda
+                    let msg = BullyOk { owner: self.id };
+                    self.socket.send_to(&msg.as_bytes(), from)?;
+                }
+                (Messages::BullyElection, BeingLeader) => {
+                    // ya soy la lider, le recuerdo quien coordina
+                    self.socket
+                        .send_to(&BullyOk { owner: self.id }.as_bytes(), from)?;
+                    self.socket
+                        .send_to(&Coordinator::new(self.id, self.term).as_bytes(), from)?;
+                }
+                (Messages::BullyElection, _) => {
+                    self.handle_election(&buf[1..size], from)?;
+                }
+                (Messages::BullyOk, ElectingNewLeader) => {
+                    // hay uno mayor que yo, que continue con la busqueda, me quedo esperando al
+                    // nuevo lider. Ese todavía espera los OK de los suyos antes de anunciarse.
+                    let wait = self.election_timeout() * 2;
+                    self.coordinator_deadline
+                        .get_or_insert_with(|| Instant::now() + wait);
+                }
+                // llega el mensaje de coordinador
+                (Messages::Coordinator, _) => self.handle_coordinator(&buf[1..size], from)?,
+                (Messages::RequestVote, _) => self.handle_request_vote(&buf[1..size], from)?,
+                (Messages::Vote, _) => self.handle_vote(&buf[1..size])?,
+                (Messages::AppendEntries, _) => {
+                    self.handle_append_entries(&buf[1..size], from)?;
+                }
+                (Messages::AppendReply, _) => self.handle_append_reply(&buf[1..size])?,
+                (Messages::InstallSnapshot, _) => {
+                    self.handle_install_snapshot(&buf[1..size], from)?;
+                }
+                (Messages::LeaderHeartbeat, _) => {
+                    self.handle_leader_heartbeat(&buf[1..size])?;
+                }
+                (Messages::QueueUpdate, _) => self.handle_queue_update(&buf[1..size], from)?,
+                (Messages::QueueResync, BeingLeader) => {
+                    self.handle_queue_resync(&buf[1..size])?;
+                }
+                (Messages::StaleTerm, BeingLeader) => {
+                    // otra pantalla ya reconoce a una líder más nueva
+                    let stale = StaleTerm::from_bytes(&buf[1..size])?;
+                    if stale.term > self.term {
+                        self.step_down(stale.term);
+                        self.leader_election()?;
                     }
-                    (Messages::Alive, WaitingRobotResponse) => {
-                        // le llega alive del lider, vuelve a esperar
-                        waiting_keepalive = false;
+                }
+                (Messages::GatewayResponse, _) => self.handle_gateway_response(&buf[1..size])?,
+                (Messages::OrderResult, _) => self.handle_orderresult(&buf[1..size])?,
+                (Messages::KeepAlive, _) => {
+                    self.handle_keepalive(&buf[1..size], from)?;
+                }
+                (Messages::Alive, BeingLeader) => {
+                    // una pantalla respondió el latido
+                    let alive = Alive::from_bytes(&buf[1..size]);
+                    if let Some(watch) = self.member_watch.as_mut() {
+                        watch.alive(alive.owner);
                     }
-                    (Messages::OrderRequest, _) => {
-                        if let Some(order) = &self.current_order {
-                            self.socket.send_to(&order.as_bytes(), from)?;
-                        }
+                }
+                (Messages::ScreenJoin, BeingLeader) => self.handle_screen_join(&buf[1..size])?,
+                (Messages::ScreenLeave, BeingLeader) => {
+                    self.handle_screen_leave(&buf[1..size])?;
+                }
+                (Messages::Membership, _) => self.handle_membership(&buf[1..size])?,
+                (Messages::LeaderQuery, _) => self.handle_leader_query(&buf[1..size])?,
+                (Messages::Alive, _) => {
+                    // le llega alive del lider, vuelve a esperar
+                    self.leader_is_alive();
+                }
+                (Messages::OrderRequest, _) => {
+                    if let SocketAddr::V4(addr) = from {
+                        self.resend_orders((*addr.ip(), addr.port()))?;
                     }
-                    _ => {}
                 }
+                _ => {}
             }
         }
         Ok(())
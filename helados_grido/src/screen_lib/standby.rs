@@ -0,0 +1,194 @@
+use log::{info, warn};
+use std::time::{Duration, Instant};
+
+use crate::{
+    screen_lib::{
+        order_log::{OrderCommand, QueueMirror},
+        screen::MAX_COMMANDS_PER_MESSAGE,
+    },
+    utils::{
+        failure_detector::FailureDetector,
+        messages::{QueueUpdate, Term},
+    },
+};
+
+/// Cada cuánto la líder le manda un QueueUpdate a su respaldo aunque la cola no cambie.
+pub const STANDBY_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Si el respaldo pasa este tiempo sin recibir la cola, da a la líder por caída y busca
+/// reemplazarla sin esperar al resto de las pantallas. La cola le llega cada
+/// `STANDBY_HEARTBEAT_INTERVAL`, así que son tres envíos perdidos seguidos; como espera menos
+/// que el resto, el respaldo suele ser el primero en arrancar la elección.
+pub const STANDBY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// La pantalla que le sigue a la líder en Bully: la de id mayor entre las de id menor. Es
+/// la que ganaría la elección si se cae la líder.
+pub fn standby_for(leader: u8, peers: &[u8]) -> Option<u8> {
+    peers.iter().filter(|id| **id < leader).max().copied()
+}
+
+/// Lado de la líder: la copia de su cola y lo que ya le mandó al respaldo. La copia se
+/// mantiene aunque no haya respaldo, por si se suma una pantalla que pueda serlo.
+#[derive(Debug)]
+pub struct StandbyStream {
+    pub standby: Option<u8>,
+    seq: u32,
+    mirror: QueueMirror,
+    next_heartbeat: Instant,
+}
+
+impl StandbyStream {
+    pub fn new(standby: Option<u8>, mirror: QueueMirror) -> Self {
+        StandbyStream {
+            standby,
+            seq: 0,
+            mirror,
+            next_heartbeat: Instant::now(),
+        }
+    }
+
+    pub fn next_heartbeat(&self) -> Instant {
+        self.next_heartbeat
+    }
+
+    /// Cambia el respaldo porque entró o salió una pantalla. Devuelve si cambió: el nuevo
+    /// necesita la cola entera.
+    pub fn retarget(&mut self, standby: Option<u8>) -> bool {
+        if self.standby == standby {
+            return false;
+        }
+        self.standby = standby;
+        self.next_heartbeat = Instant::now();
+        true
+    }
+
+    // Parte los comandos en envíos numerados. El primero lleva `reset` si se pide.
+    fn chunk(
+        &mut self,
+        owner: u8,
+        term: Term,
+        commands: Vec<OrderCommand>,
+        reset: bool,
+    ) -> Vec<QueueUpdate> {
+        let mut updates = Vec::new();
+        let mut chunks = commands.chunks(MAX_COMMANDS_PER_MESSAGE).peekable();
+        // sin comandos igual sale un envío: el latido o la cola vacía
+        if chunks.peek().is_none() {
+            self.seq += 1;
+            updates.push(QueueUpdate {
+                owner,
+                term,
+                seq: self.seq,
+                reset,
+                commands: Vec::new(),
+            });
+        }
+        for chunk in chunks {
+            self.seq += 1;
+            updates.push(QueueUpdate {
+                owner,
+                term,
+                seq: self.seq,
+                reset: reset && updates.is_empty(),
+                commands: chunk.to_vec(),
+            });
+        }
+        self.next_heartbeat = Instant::now() + STANDBY_HEARTBEAT_INTERVAL;
+        updates
+    }
+
+    /// Aplica los cambios de la cola a la copia y arma los envíos para el respaldo. Si no
+    /// hubo cambios, devuelve un latido cuando corresponde.
+    pub fn updates(
+        &mut self,
+        owner: u8,
+        term: Term,
+        commands: Vec<OrderCommand>,
+    ) -> Vec<QueueUpdate> {
+        if commands.is_empty() && Instant::now() < self.next_heartbeat {
+            return Vec::new();
+        }
+        for command in commands.iter() {
+            self.mirror.apply(command);
+        }
+        if self.standby.is_none() {
+            self.next_heartbeat = Instant::now() + STANDBY_HEARTBEAT_INTERVAL;
+            return Vec::new();
+        }
+        self.chunk(owner, term, commands, false)
+    }
+
+    /// La cola entera, para un respaldo que perdió algún envío o que recién empieza.
+    pub fn resync(&mut self, owner: u8, term: Term) -> Vec<QueueUpdate> {
+        if self.standby.is_none() {
+            return Vec::new();
+        }
+        let commands = self.mirror.commands();
+        self.chunk(owner, term, commands, true)
+    }
+}
+
+/// Lado del respaldo: la copia de la cola de la líder, armada con sus QueueUpdate.
+#[derive(Debug)]
+pub struct StandbyMirror {
+    pub leader: u8,
+    // hasta que llega una cola entera los cambios sueltos no sirven
+    synced: bool,
+    expected_seq: u32,
+    mirror: QueueMirror,
+    detector: FailureDetector,
+}
+
+impl StandbyMirror {
+    pub fn new(id: u8, leader: u8) -> Self {
+        info!(
+            "[Screen {}] Soy el respaldo de la lider {}, copio su cola",
+            id, leader
+        );
+        StandbyMirror {
+            leader,
+            synced: false,
+            expected_seq: 0,
+            mirror: QueueMirror::default(),
+            detector: FailureDetector::new(STANDBY_TIMEOUT),
+        }
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.detector.deadline()
+    }
+
+    pub fn expired(&self) -> bool {
+        self.detector.is_suspected()
+    }
+
+    /// Aplica el envío si es el que sigue. Devuelve `false` si se perdió alguno y hay que
+    /// pedirle la cola entera a la líder.
+    pub fn receive(&mut self, id: u8, update: QueueUpdate) -> bool {
+        self.detector.heartbeat();
+        if update.reset {
+            self.mirror = QueueMirror::default();
+            self.synced = true;
+        } else if !self.synced || update.seq > self.expected_seq {
+            warn!(
+                "[Screen {}] Me perdi cambios de la cola de la lider (llego el {}, esperaba el {})",
+                id, update.seq, self.expected_seq
+            );
+            return false;
+        } else if update.seq < self.expected_seq {
+            // repetido
+            return true;
+        }
+        for command in update.commands.iter() {
+            self.mirror.apply(command);
+        }
+        self.expected_seq = update.seq + 1;
+        true
+    }
+
+    /// La copia de la cola, para que el respaldo la retome si pasa a ser la líder. Solo
+    /// sirve si alguna vez llegó la cola entera.
+    pub fn into_mirror(self) -> Option<QueueMirror> {
+        self.synced.then_some(self.mirror)
+    }
+}
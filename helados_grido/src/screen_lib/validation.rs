@@ -0,0 +1,312 @@
+use crate::{
+    robot_lib::icecream::{IceCream, BUCKET_GRAMS},
+    utils::messages::OrderJson,
+};
+use serde_json::{json, Value};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+/// Cantidad máxima de gustos (bochas) que puede llevar un pedido.
+pub const MAX_SCOOPS: usize = 4;
+
+/// Máximo de segundos que se puede pedir en `sla_secs` o `cancel_after_secs`.
+pub const MAX_WAIT_SECS: u64 = 3600;
+
+/// Directorio donde cada pantalla deja el reporte de los pedidos que rechazó.
+pub const REJECTED_ORDERS_DIR: &str = "rechazados";
+
+/// Motivo por el que se rechaza un pedido.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderRejection {
+    InvalidJson(String),
+    MissingItems,
+    EmptyItems,
+    UnknownFlavor(String),
+    InvalidAmount(String, String),
+    AmountAboveBucket(String, u64),
+    TooManyScoops(usize),
+    InvalidSla(String),
+    InvalidCancelAfter(String),
+}
+
+impl fmt::Display for OrderRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderRejection::InvalidJson(error) => write!(f, "no es un pedido JSON valido: {error}"),
+            OrderRejection::MissingItems => write!(f, "no tiene el campo items"),
+            OrderRejection::EmptyItems => write!(f, "no tiene ningun gusto"),
+            OrderRejection::UnknownFlavor(flavor) => write!(f, "el gusto {flavor} no existe"),
+            OrderRejection::InvalidAmount(flavor, amount) => write!(
+                f,
+                "la cantidad de {flavor} no es un entero positivo de gramos: {amount}"
+            ),
+            OrderRejection::AmountAboveBucket(flavor, amount) => write!(
+                f,
+                "la cantidad de {flavor} ({amount}g) supera un contenedor entero ({BUCKET_GRAMS}g)"
+            ),
+            OrderRejection::TooManyScoops(scoops) => {
+                write!(f, "tiene {scoops} gustos y el maximo es {MAX_SCOOPS}")
+            }
+            OrderRejection::InvalidSla(secs) => write!(
+                f,
+                "sla_secs tiene que ser un entero entre 1 y {MAX_WAIT_SECS}: {secs}"
+            ),
+            OrderRejection::InvalidCancelAfter(secs) => write!(
+                f,
+                "cancel_after_secs tiene que ser un entero entre 1 y {MAX_WAIT_SECS}: {secs}"
+            ),
+        }
+    }
+}
+
+/// Un pedido rechazado, con la línea de la fuente de la que salió.
+#[derive(Debug, Clone)]
+pub struct RejectedOrder {
+    pub line: usize,
+    pub content: String,
+    pub reason: OrderRejection,
+}
+
+impl RejectedOrder {
+    pub fn new(line: usize, content: &[u8], reason: OrderRejection) -> Self {
+        RejectedOrder {
+            line,
+            content: String::from_utf8_lossy(content).trim().to_string(),
+            reason,
+        }
+    }
+}
+
+// Un tiempo en segundos, si está, tiene que estar entre 1 y `MAX_WAIT_SECS`
+fn valid_wait(value: &Value, field: &str) -> Result<(), String> {
+    match value.get(field) {
+        None | Some(Value::Null) => Ok(()),
+        Some(secs) => match secs.as_u64() {
+            Some(1..=MAX_WAIT_SECS) => Ok(()),
+            _ => Err(secs.to_string()),
+        },
+    }
+}
+
+/// Valida un pedido antes de cobrarlo: tiene que tener entre 1 y `MAX_SCOOPS` gustos
+/// conocidos, cada uno con una cantidad positiva de gramos que entre en un contenedor, y
+/// tiempos de espera de hasta `MAX_WAIT_SECS`.
+pub fn validate_order(bytes: &[u8]) -> Result<OrderJson, OrderRejection> {
+    let value: Value = serde_json::from_slice(bytes)
+        .map_err(|error| OrderRejection::InvalidJson(error.to_string()))?;
+
+    let items = match value.get("items") {
+        Some(Value::Object(items)) => items,
+        Some(_) => {
+            return Err(OrderRejection::InvalidJson(
+                "items tiene que ser un objeto".to_string(),
+            ))
+        }
+        None => return Err(OrderRejection::MissingItems),
+    };
+    if items.is_empty() {
+        return Err(OrderRejection::EmptyItems);
+    }
+    for (flavor, amount) in items {
+        if serde_json::from_value::<IceCream>(Value::String(flavor.clone())).is_err() {
+            return Err(OrderRejection::UnknownFlavor(flavor.clone()));
+        }
+        match amount.as_u64() {
+            Some(0) | None => {
+                return Err(OrderRejection::InvalidAmount(
+                    flavor.clone(),
+                    amount.to_string(),
+                ))
+            }
+            Some(grams) if grams > BUCKET_GRAMS as u64 => {
+                return Err(OrderRejection::AmountAboveBucket(flavor.clone(), grams))
+            }
+            Some(_) => {}
+        }
+    }
+    if items.len() > MAX_SCOOPS {
+        return Err(OrderRejection::TooManyScoops(items.len()));
+    }
+    valid_wait(&value, "sla_secs").map_err(OrderRejection::InvalidSla)?;
+    valid_wait(&value, "cancel_after_secs").map_err(OrderRejection::InvalidCancelAfter)?;
+
+    // el resto de los campos (envase, prioridad, tiempos) los valida serde
+    serde_json::from_value(value).map_err(|error| OrderRejection::InvalidJson(error.to_string()))
+}
+
+/// Resultado de validar un archivo de pedidos entero.
+#[derive(Debug, Default)]
+pub struct ValidationSummary {
+    pub accepted: usize,
+    pub rejected: Vec<RejectedOrder>,
+}
+
+/// Valida todas las líneas de un archivo de pedidos, sin cortar en la primera inválida.
+/// Las líneas en blanco se ignoran, pero cuentan para la numeración.
+pub fn validate_file(path: &str) -> io::Result<ValidationSummary> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut summary = ValidationSummary::default();
+    for (i, line) in reader.split(b'\n').enumerate() {
+        let line = line?;
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        match validate_order(&line) {
+            Ok(_) => summary.accepted += 1,
+            Err(reason) => summary
+                .rejected
+                .push(RejectedOrder::new(i + 1, &line, reason)),
+        }
+    }
+    Ok(summary)
+}
+
+/// Archivo JSONL con los pedidos rechazados: una línea por pedido con la fuente, el número
+/// de línea, el motivo y el pedido tal cual llegó. Se crea recién con el primer rechazo.
+pub struct RejectionReport {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl RejectionReport {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        RejectionReport {
+            path: path.as_ref().to_path_buf(),
+            file: None,
+        }
+    }
+
+    pub fn for_screen(id: u8) -> Self {
+        Self::new(PathBuf::from(REJECTED_ORDERS_DIR).join(format!("screen_{}.jsonl", id)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&mut self, source: &str, rejected: &RejectedOrder) -> io::Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                if let Some(dir) = self.path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                self.file.insert(file)
+            }
+        };
+        let entry = json!({
+            "source": source,
+            "line": rejected.line,
+            "reason": rejected.reason.to_string(),
+            "order": rejected.content,
+        });
+        writeln!(file, "{}", entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(order: &str) -> Result<OrderJson, OrderRejection> {
+        validate_order(order.as_bytes())
+    }
+
+    #[test]
+    fn accepts_order_at_every_limit() {
+        let order = format!(
+            r#"{{"items": {{"Chocolate": 1, "Vanilla": {BUCKET_GRAMS}, "Lemon": 250, "DulceDeLeche": 250}}, "sla_secs": 1, "cancel_after_secs": {MAX_WAIT_SECS}}}"#
+        );
+        let order = validate(&order).unwrap();
+        assert_eq!(order.items.len(), MAX_SCOOPS);
+        assert_eq!(order.items[&IceCream::Vanilla], BUCKET_GRAMS);
+    }
+
+    #[test]
+    fn rejects_unknown_flavor() {
+        assert_eq!(
+            validate(r#"{"items": {"Pistacho": 250}}"#).unwrap_err(),
+            OrderRejection::UnknownFlavor("Pistacho".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_zero_grams() {
+        assert_eq!(
+            validate(r#"{"items": {"Chocolate": 0}}"#).unwrap_err(),
+            OrderRejection::InvalidAmount("Chocolate".to_string(), "0".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_more_than_a_bucket() {
+        let order = format!(r#"{{"items": {{"Chocolate": {}}}}}"#, BUCKET_GRAMS + 1);
+        assert_eq!(
+            validate(&order).unwrap_err(),
+            OrderRejection::AmountAboveBucket("Chocolate".to_string(), BUCKET_GRAMS as u64 + 1)
+        );
+    }
+
+    #[test]
+    fn rejects_too_many_scoops() {
+        let order = r#"{"items": {"Chocolate": 100, "Vanilla": 100, "Strawberry": 100, "Lemon": 100, "DulceDeLeche": 100}}"#;
+        assert_eq!(
+            validate(order).unwrap_err(),
+            OrderRejection::TooManyScoops(MAX_SCOOPS + 1)
+        );
+    }
+
+    #[test]
+    fn rejects_sla_out_of_range() {
+        for secs in [0, MAX_WAIT_SECS + 1] {
+            let order = format!(r#"{{"items": {{"Lemon": 250}}, "sla_secs": {secs}}}"#);
+            assert_eq!(
+                validate(&order).unwrap_err(),
+                OrderRejection::InvalidSla(secs.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_cancel_after_out_of_range() {
+        for secs in [0, MAX_WAIT_SECS + 1] {
+            let order = format!(r#"{{"items": {{"Lemon": 250}}, "cancel_after_secs": {secs}}}"#);
+            assert_eq!(
+                validate(&order).unwrap_err(),
+                OrderRejection::InvalidCancelAfter(secs.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn validate_file_reports_every_invalid_line() {
+        let path = std::env::temp_dir().join(format!("validation_{}.jsonl", std::process::id()));
+        fs::write(
+            &path,
+            "{\"items\": {\"Chocolate\": 250}}\n\n{\"items\": {\"Pistacho\": 250}}\n{\"items\": {\"Lemon\": 0}}\n",
+        )
+        .unwrap();
+        let summary = validate_file(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(summary.accepted, 1);
+        let lines: Vec<usize> = summary
+            .rejected
+            .iter()
+            .map(|rejected| rejected.line)
+            .collect();
+        assert_eq!(lines, vec![3, 4]);
+        assert_eq!(
+            summary.rejected[0].reason,
+            OrderRejection::UnknownFlavor("Pistacho".to_string())
+        );
+    }
+}
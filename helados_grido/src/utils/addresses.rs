@@ -4,6 +4,10 @@ pub fn id_to_addr_robot(id: u16) -> (Ipv4Addr, u16) {
     (Ipv4Addr::new(127, 0, 0, 1), 2000 + id)
 }
 
+pub fn addr_to_id_robot(addr: (Ipv4Addr, u16)) -> u8 {
+    (addr.1 - 2000) as u8
+}
+
 pub fn id_to_addr_screen(id: u16) -> (Ipv4Addr, u16) {
     (Ipv4Addr::new(127, 0, 0, 1), 3000 + id)
 }
@@ -15,3 +19,11 @@ pub fn id_to_addr_resolver(id: u16) -> (Ipv4Addr, u16) {
 pub fn id_to_addr_leader(id: u16) -> (Ipv4Addr, u16) {
     (Ipv4Addr::new(127, 0, 0, 1), 5000 + id)
 }
+
+pub fn addr_to_id_leader(addr: (Ipv4Addr, u16)) -> u8 {
+    (addr.1 - 5000) as u8
+}
+
+pub fn id_to_addr_order_input(id: u16) -> (Ipv4Addr, u16) {
+    (Ipv4Addr::new(127, 0, 0, 1), 7000 + id)
+}
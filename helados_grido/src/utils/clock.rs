@@ -0,0 +1,10 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Milisegundos desde el epoch, para comparar marcas de tiempo de distintos procesos, como
+/// los snapshots de los robots o el vencimiento de los pedidos.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
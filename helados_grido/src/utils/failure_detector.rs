@@ -0,0 +1,246 @@
+use std::{
+    collections::VecDeque,
+    env,
+    time::{Duration, Instant},
+};
+
+/// Variable de entorno con el umbral de sospecha (phi). Más alto tarda más en dar a un par
+/// por caído y se equivoca menos.
+pub const PHI_THRESHOLD_VAR: &str = "FAILURE_PHI_THRESHOLD";
+/// Variable de entorno con la pausa aceptable en milisegundos, que se suma al intervalo
+/// medio entre mensajes.
+pub const ACCEPTABLE_PAUSE_VAR: &str = "FAILURE_ACCEPTABLE_PAUSE_MS";
+/// Variables de entorno con el plazo mínimo y máximo en milisegundos.
+pub const MIN_TIMEOUT_VAR: &str = "FAILURE_MIN_TIMEOUT_MS";
+pub const MAX_TIMEOUT_VAR: &str = "FAILURE_MAX_TIMEOUT_MS";
+
+const DEFAULT_PHI_THRESHOLD: f64 = 8.0;
+const DEFAULT_ACCEPTABLE_PAUSE: Duration = Duration::from_secs(1);
+const DEFAULT_MIN_TIMEOUT: Duration = Duration::from_secs(3);
+const DEFAULT_MAX_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Intervalos que se recuerdan para estimar la media y el desvío
+const WINDOW: usize = 64;
+// Con menos intervalos observados se usa el plazo inicial
+const MIN_SAMPLES: usize = 3;
+// Desvío mínimo, para que pares muy regulares no den un plazo demasiado justo
+const MIN_STD_DEVIATION: Duration = Duration::from_millis(200);
+
+/// Umbrales del detector de fallas, configurables por variables de entorno.
+#[derive(Debug, Clone, Copy)]
+pub struct FailureDetectorConfig {
+    pub phi_threshold: f64,
+    pub acceptable_pause: Duration,
+    pub min_timeout: Duration,
+    pub max_timeout: Duration,
+}
+
+impl Default for FailureDetectorConfig {
+    fn default() -> Self {
+        FailureDetectorConfig {
+            phi_threshold: DEFAULT_PHI_THRESHOLD,
+            acceptable_pause: DEFAULT_ACCEPTABLE_PAUSE,
+            min_timeout: DEFAULT_MIN_TIMEOUT,
+            max_timeout: DEFAULT_MAX_TIMEOUT,
+        }
+    }
+}
+
+fn millis_from_env(var: &str) -> Option<Duration> {
+    env::var(var)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .map(Duration::from_millis)
+}
+
+impl FailureDetectorConfig {
+    /// Lee los umbrales de las variables de entorno. Las que no están definidas o no se
+    /// entienden quedan con su valor por defecto.
+    pub fn from_env() -> Self {
+        let default = FailureDetectorConfig::default();
+        let config = FailureDetectorConfig {
+            phi_threshold: env::var(PHI_THRESHOLD_VAR)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|phi: &f64| *phi > 0.0)
+                .unwrap_or(default.phi_threshold),
+            acceptable_pause: millis_from_env(ACCEPTABLE_PAUSE_VAR)
+                .unwrap_or(default.acceptable_pause),
+            min_timeout: millis_from_env(MIN_TIMEOUT_VAR).unwrap_or(default.min_timeout),
+            max_timeout: millis_from_env(MAX_TIMEOUT_VAR).unwrap_or(default.max_timeout),
+        };
+        FailureDetectorConfig {
+            max_timeout: config.max_timeout.max(config.min_timeout),
+            ..config
+        }
+    }
+}
+
+/// Detector de fallas phi-accrual: recuerda cada cuánto llegan los mensajes de un par y, con
+/// la media y el desvío de esos intervalos, estima qué tan probable es que el par se haya
+/// caído dado el tiempo que lleva sin mandar nada. Hasta tener suficientes intervalos usa el
+/// plazo inicial, que era el timeout fijo de cada caso.
+#[derive(Debug, Clone)]
+pub struct FailureDetector {
+    config: FailureDetectorConfig,
+    initial_timeout: Duration,
+    intervals: VecDeque<Duration>,
+    last_heartbeat: Instant,
+}
+
+impl FailureDetector {
+    pub fn new(initial_timeout: Duration) -> Self {
+        FailureDetector::with_config(initial_timeout, FailureDetectorConfig::from_env())
+    }
+
+    pub fn with_config(initial_timeout: Duration, config: FailureDetectorConfig) -> Self {
+        FailureDetector {
+            config,
+            initial_timeout,
+            intervals: VecDeque::with_capacity(WINDOW),
+            last_heartbeat: Instant::now(),
+        }
+    }
+
+    /// Llegó un mensaje del par: anota cuánto tardó desde el anterior.
+    pub fn heartbeat(&mut self) {
+        let now = Instant::now();
+        if self.intervals.len() == WINDOW {
+            self.intervals.pop_front();
+        }
+        self.intervals.push_back(now - self.last_heartbeat);
+        self.last_heartbeat = now;
+    }
+
+    /// Empieza a esperar al par sin contar el tiempo que pasó desde su último mensaje, por
+    /// ejemplo porque no se le había pedido nada.
+    pub fn restart(&mut self) {
+        self.last_heartbeat = Instant::now();
+    }
+
+    fn mean_and_std_deviation(&self) -> Option<(f64, f64)> {
+        if self.intervals.len() < MIN_SAMPLES {
+            return None;
+        }
+        let samples: Vec<f64> = self.intervals.iter().map(Duration::as_secs_f64).collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance = samples
+            .iter()
+            .map(|sample| (sample - mean).powi(2))
+            .sum::<f64>()
+            / samples.len() as f64;
+        Some((
+            mean + self.config.acceptable_pause.as_secs_f64(),
+            variance.sqrt().max(MIN_STD_DEVIATION.as_secs_f64()),
+        ))
+    }
+
+    // Sospecha de que el par se cayó tras `elapsed` sin mensajes, con la aproximación
+    // logística de la normal
+    fn phi_at(elapsed: f64, mean: f64, std_deviation: f64) -> f64 {
+        let y = (elapsed - mean) / std_deviation;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        if elapsed > mean {
+            -(e / (1.0 + e)).log10()
+        } else {
+            -(1.0 - 1.0 / (1.0 + e)).log10()
+        }
+    }
+
+    /// La sospecha actual de que el par se cayó.
+    pub fn phi(&self) -> f64 {
+        match self.mean_and_std_deviation() {
+            Some((mean, std_deviation)) => Self::phi_at(
+                self.last_heartbeat.elapsed().as_secs_f64(),
+                mean,
+                std_deviation,
+            ),
+            None if self.last_heartbeat.elapsed() >= self.initial_timeout => f64::INFINITY,
+            None => 0.0,
+        }
+    }
+
+    /// Tiempo sin mensajes a partir del cual el par se da por caído. Se adapta a lo que se
+    /// observó, dentro del mínimo y el máximo configurados.
+    pub fn timeout(&self) -> Duration {
+        let (mean, std_deviation) = match self.mean_and_std_deviation() {
+            Some(stats) => stats,
+            None => return self.initial_timeout,
+        };
+        // phi crece con el tiempo, se busca dónde pasa el umbral
+        let (mut low, mut high) = (0.0, self.config.max_timeout.as_secs_f64());
+        for _ in 0..32 {
+            let middle = (low + high) / 2.0;
+            if Self::phi_at(middle, mean, std_deviation) < self.config.phi_threshold {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        Duration::from_secs_f64(high).clamp(self.config.min_timeout, self.config.max_timeout)
+    }
+
+    /// Cuándo se da por caído al par si no manda nada.
+    pub fn deadline(&self) -> Instant {
+        self.last_heartbeat + self.timeout()
+    }
+
+    pub fn is_suspected(&self) -> bool {
+        Instant::now() >= self.deadline()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> FailureDetectorConfig {
+        FailureDetectorConfig {
+            phi_threshold: DEFAULT_PHI_THRESHOLD,
+            acceptable_pause: Duration::ZERO,
+            min_timeout: Duration::from_millis(100),
+            max_timeout: Duration::from_secs(30),
+        }
+    }
+
+    // Detector que ya vio llegar mensajes con estos intervalos, en milisegundos
+    fn with_intervals(config: FailureDetectorConfig, intervals: &[u64]) -> FailureDetector {
+        let mut detector = FailureDetector::with_config(Duration::from_secs(5), config);
+        detector
+            .intervals
+            .extend(intervals.iter().map(|ms| Duration::from_millis(*ms)));
+        detector
+    }
+
+    #[test]
+    fn uses_initial_timeout_without_enough_samples() {
+        let mut detector = FailureDetector::with_config(Duration::from_secs(5), config());
+        assert_eq!(detector.timeout(), Duration::from_secs(5));
+        for _ in 0..MIN_SAMPLES - 1 {
+            detector.heartbeat();
+        }
+        assert_eq!(detector.timeout(), Duration::from_secs(5));
+        detector.heartbeat();
+        assert_ne!(detector.timeout(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn timeout_stays_within_bounds() {
+        let config = FailureDetectorConfig {
+            min_timeout: Duration::from_secs(2),
+            max_timeout: Duration::from_secs(4),
+            ..config()
+        };
+        let fast = with_intervals(config, &[10, 10, 10, 10]);
+        assert_eq!(fast.timeout(), config.min_timeout);
+        let slow = with_intervals(config, &[9_000, 10_000, 11_000]);
+        assert_eq!(slow.timeout(), config.max_timeout);
+    }
+
+    #[test]
+    fn timeout_grows_with_irregular_intervals() {
+        let regular = with_intervals(config(), &[500, 500, 500, 500, 500, 500]);
+        let irregular = with_intervals(config(), &[100, 900, 200, 800, 50, 950]);
+        assert!(irregular.timeout() > regular.timeout());
+    }
+}
@@ -1,8 +1,25 @@
-use crate::robot_lib::icecream::{Bucket, IceCream};
+use crate::robot_lib::{
+    capabilities::Capabilities,
+    icecream::{Bucket, Container, Grams, IceCream},
+    snapshot::FlavorSnapshot,
+};
+use crate::screen_lib::{order_log::OrderCommand, raft::LogEntry};
 use serde::{Deserialize, Serialize};
 use std::{cmp::Ordering, collections::HashMap};
 
-use super::errors::ParseError;
+use super::{clock::now_millis, errors::ParseError};
+
+/// Mandato de una pantalla líder. Cada elección lo aumenta, y los mensajes de un mandato
+/// anterior se descartan.
+pub type Term = u32;
+
+// Lee un u32 (un mandato, un índice del log o gramos) que empieza en `offset`
+fn u32_at(buffer: &[u8], offset: usize) -> Result<u32, ParseError> {
+    let bytes = buffer
+        .get(offset..offset + 4)
+        .ok_or(ParseError::ConversionError)?;
+    Ok(u32::from_be_bytes(bytes.try_into()?))
+}
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Grade {
@@ -71,6 +88,31 @@ pub enum Messages {
     RobotWithOrder = 17,
     GatewayResponse = 18,
     Init = 19,
+    RobotQueueStatus = 20,
+    StatusRequest = 21,
+    StatusReply = 22,
+    Drain = 23,
+    RobotDraining = 24,
+    Leave = 25,
+    SnapshotCollect = 26,
+    CachedOrderResult = 27,
+    OrderProgress = 28,
+    CancelOrder = 29,
+    StaleTerm = 30,
+    RequestVote = 31,
+    Vote = 32,
+    AppendEntries = 33,
+    AppendReply = 34,
+    QueueUpdate = 35,
+    QueueResync = 36,
+    LeaderHeartbeat = 37,
+    ScreenJoin = 38,
+    Membership = 39,
+    ScreenLeave = 40,
+    LeaderQuery = 41,
+    InstallSnapshot = 42,
+    DrainReply = 43,
+    LeaveAck = 44,
 }
 
 impl TryFrom<u8> for Messages {
@@ -98,6 +140,31 @@ impl TryFrom<u8> for Messages {
             17 => Ok(Messages::RobotWithOrder),
             18 => Ok(Messages::GatewayResponse),
             19 => Ok(Messages::Init),
+            20 => Ok(Messages::RobotQueueStatus),
+            21 => Ok(Messages::StatusRequest),
+            22 => Ok(Messages::StatusReply),
+            23 => Ok(Messages::Drain),
+            24 => Ok(Messages::RobotDraining),
+            25 => Ok(Messages::Leave),
+            26 => Ok(Messages::SnapshotCollect),
+            27 => Ok(Messages::CachedOrderResult),
+            28 => Ok(Messages::OrderProgress),
+            29 => Ok(Messages::CancelOrder),
+            30 => Ok(Messages::StaleTerm),
+            31 => Ok(Messages::RequestVote),
+            32 => Ok(Messages::Vote),
+            33 => Ok(Messages::AppendEntries),
+            34 => Ok(Messages::AppendReply),
+            35 => Ok(Messages::QueueUpdate),
+            36 => Ok(Messages::QueueResync),
+            37 => Ok(Messages::LeaderHeartbeat),
+            38 => Ok(Messages::ScreenJoin),
+            39 => Ok(Messages::Membership),
+            40 => Ok(Messages::ScreenLeave),
+            41 => Ok(Messages::LeaderQuery),
+            42 => Ok(Messages::InstallSnapshot),
+            43 => Ok(Messages::DrainReply),
+            44 => Ok(Messages::LeaveAck),
             _ => Err(ParseError::ConversionError),
         }
     }
@@ -138,23 +205,26 @@ impl Handshake {
 }
 
 pub struct Coordinator {
-    owner: u8,
+    pub owner: u8,
+    pub term: Term,
 }
 
 impl Coordinator {
-    pub fn new(owner: u8) -> Self {
-        Coordinator { owner }
+    pub fn new(owner: u8, term: Term) -> Self {
+        Coordinator { owner, term }
     }
 
     pub fn as_bytes(self) -> Vec<u8> {
-        let buf_msg = vec![Messages::Coordinator as u8, self.owner];
+        let mut buf_msg = vec![Messages::Coordinator as u8, self.owner];
+        buf_msg.extend_from_slice(&self.term.to_be_bytes());
         buf_msg
     }
 
-    pub fn from_bytes(buffer: &[u8]) -> Coordinator {
-        let owner = buffer[0];
+    pub fn from_bytes(buffer: &[u8]) -> Result<Coordinator, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        let term = u32_at(buffer, 1)?;
 
-        Coordinator { owner }
+        Ok(Coordinator { owner, term })
     }
 }
 
@@ -219,27 +289,84 @@ impl Token {
     }
 }
 
+/// Prioridad de un pedido. Los pedidos express y VIP se atienden antes y cuestan más.
+#[derive(
+    Eq, Hash, PartialEq, PartialOrd, Ord, Clone, Debug, Copy, Serialize, Deserialize, Default,
+)]
+pub enum Priority {
+    #[default]
+    Normal = 0,
+    Express = 1,
+    Vip = 2,
+}
+
+impl TryFrom<u8> for Priority {
+    type Error = ParseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Priority::Normal),
+            1 => Ok(Priority::Express),
+            2 => Ok(Priority::Vip),
+            _ => Err(ParseError::ConversionError),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OrderJson {
-    pub items: HashMap<IceCream, f32>,
+    pub items: HashMap<IceCream, Grams>,
+    #[serde(default)]
+    pub container: Container,
+    #[serde(default)]
+    pub priority: Priority,
+    // segundos que el cliente está dispuesto a esperar, si no se usa el de la pantalla
+    #[serde(default)]
+    pub sla_secs: Option<u64>,
+    // segundos de espera tras los que el cliente cancela el pedido, si todavía no está listo
+    #[serde(default)]
+    pub cancel_after_secs: Option<u64>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
     pub screen_id: u8,
     pub order_id: u8,
-    pub items: HashMap<IceCream, f32>,
+    pub items: HashMap<IceCream, Grams>,
+    pub container: Container,
+    pub priority: Priority,
+    // milisegundos desde el epoch a partir de los que el pedido ya no se prepara
+    pub deadline: u64,
+    // mandato de la líder a la que va dirigido el pedido
+    #[serde(default)]
+    pub term: Term,
 }
 
 impl Order {
-    pub fn new(screen_id: u8, order_id: u8, items: HashMap<IceCream, f32>) -> Self {
+    pub fn new(
+        screen_id: u8,
+        order_id: u8,
+        items: HashMap<IceCream, Grams>,
+        container: Container,
+        priority: Priority,
+        deadline: u64,
+        term: Term,
+    ) -> Self {
         Order {
             screen_id,
             order_id,
             items,
+            container,
+            priority,
+            deadline,
+            term,
         }
     }
 
+    pub fn is_expired(&self) -> bool {
+        now_millis() >= self.deadline
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut buf_msg = vec![
             Messages::Order as u8,
@@ -251,37 +378,60 @@ impl Order {
             buf_msg.push(*ice_cream as u8);
             buf_msg.extend_from_slice(&amount.to_be_bytes());
         }
+        buf_msg.push(self.container as u8);
+        buf_msg.push(self.priority as u8);
+        buf_msg.extend_from_slice(&self.deadline.to_be_bytes());
+        buf_msg.extend_from_slice(&self.term.to_be_bytes());
         buf_msg
     }
 
+    // Largo en bytes de la orden serializada, sin contar el tipo de mensaje
+    pub fn encoded_len(&self) -> usize {
+        17 + 5 * self.items.len()
+    }
+
     pub fn from_bytes(buffer: &[u8]) -> Order {
-        let screen_id = buffer[0];
-        let order_id = buffer[1];
-        let len_items = buffer[2] as usize;
+        Self::try_from_bytes(buffer).expect("Debe ser una orden bien formada")
+    }
+
+    // Como from_bytes, pero devuelve un error si el buffer está truncado o tiene valores
+    // inválidos en lugar de entrar en pánico
+    pub fn try_from_bytes(buffer: &[u8]) -> Result<Order, ParseError> {
+        let byte = |i: usize| buffer.get(i).copied().ok_or(ParseError::ConversionError);
+        let screen_id = byte(0)?;
+        let order_id = byte(1)?;
+        let len_items = byte(2)? as usize;
         let mut items = HashMap::new();
 
         for i in 0..len_items {
             let offset = 3 + i * 5;
-            let icecream = IceCream::try_from(buffer[offset])
-                .expect("Debe ser un número válido para el gusto de helado");
-            let amount = f32::from_be_bytes(
-                buffer[offset + 1..offset + 5]
-                    .try_into()
-                    .expect("Debía obtener los bytes que representan el f32"),
-            );
+            let icecream = IceCream::try_from(byte(offset)?)?;
+            let amount = u32_at(buffer, offset + 1)?;
             items.insert(icecream, amount);
         }
-
-        Order {
+        let container = Container::try_from(byte(3 + len_items * 5)?)?;
+        let priority = Priority::try_from(byte(4 + len_items * 5)?)?;
+        let offset = 5 + len_items * 5;
+        let deadline = buffer
+            .get(offset..offset + 8)
+            .ok_or(ParseError::ConversionError)?;
+        let deadline = u64::from_be_bytes(deadline.try_into()?);
+        let term = u32_at(buffer, offset + 8)?;
+
+        Ok(Order {
             screen_id,
             order_id,
             items,
-        }
+            container,
+            priority,
+            deadline,
+            term,
+        })
     }
 }
 
 // =================================== OrderResult =========================================================== //
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 pub enum OrderStatus {
     Ready = 0,
     Abort = 1,
@@ -299,11 +449,39 @@ impl TryFrom<u8> for OrderStatus {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Motivo por el que se abortó un pedido. Los pedidos listos van con `Unspecified`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum AbortReason {
+    Unspecified = 0,
+    OutOfStock = 1,
+    Unsupported = 2,
+    Timeout = 3,
+    Cancelled = 4,
+    RobotFailure = 5,
+}
+
+impl TryFrom<u8> for AbortReason {
+    type Error = ParseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(AbortReason::Unspecified),
+            1 => Ok(AbortReason::OutOfStock),
+            2 => Ok(AbortReason::Unsupported),
+            3 => Ok(AbortReason::Timeout),
+            4 => Ok(AbortReason::Cancelled),
+            5 => Ok(AbortReason::RobotFailure),
+            _ => Err(ParseError::ConversionError),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct OrderResult {
     pub status: OrderStatus,
     pub order_id: u8,
     pub owner_id: u8,
+    pub reason: AbortReason,
 }
 
 impl OrderResult {
@@ -312,6 +490,16 @@ impl OrderResult {
             status,
             order_id,
             owner_id,
+            reason: AbortReason::Unspecified,
+        }
+    }
+
+    pub fn abort(order_id: u8, owner_id: u8, reason: AbortReason) -> Self {
+        OrderResult {
+            status: OrderStatus::Abort,
+            order_id,
+            owner_id,
+            reason,
         }
     }
 
@@ -321,18 +509,35 @@ impl OrderResult {
             self.status as u8,
             self.order_id,
             self.owner_id,
+            self.reason as u8,
+        ];
+        buf_msg
+    }
+
+    /// Resultado de un pedido que el robot ya había preparado y vuelve a recibir. Se
+    /// responde sin esperar ACK para no confundirlo con la entrega del pedido en curso.
+    pub fn as_cached_bytes(self) -> Vec<u8> {
+        let buf_msg = vec![
+            Messages::CachedOrderResult as u8,
+            self.status as u8,
+            self.order_id,
+            self.owner_id,
+            self.reason as u8,
         ];
         buf_msg
     }
 
     pub fn from_bytes(buffer: &[u8]) -> Result<OrderResult, ParseError> {
-        let status = OrderStatus::try_from(buffer[0])?;
-        let order_id = buffer[1];
-        let owner_id = buffer[2];
+        let byte = |i: usize| buffer.get(i).copied().ok_or(ParseError::ConversionError);
+        let status = OrderStatus::try_from(byte(0)?)?;
+        let order_id = byte(1)?;
+        let owner_id = byte(2)?;
+        let reason = AbortReason::try_from(byte(3)?)?;
         Ok(OrderResult {
             status,
             order_id,
             owner_id,
+            reason,
         })
     }
 }
@@ -344,13 +549,8 @@ pub struct RobotOrder {
 }
 
 impl RobotOrder {
-    pub fn new(
-        resolver_owner: u8,
-        screen_owner: u8,
-        order_id: u8,
-        items: HashMap<IceCream, f32>,
-    ) -> Self {
-        let order = Order::new(screen_owner, order_id, items);
+    /// El mandato del pedido es el de la líder cuyo resolver se lo manda al robot.
+    pub fn new(resolver_owner: u8, order: Order) -> Self {
         RobotOrder {
             resolver_owner,
             order,
@@ -496,35 +696,133 @@ impl OrderRequest {
     }
 }
 
+/// Respuesta del robot libre al `NewLeader`, con lo que es capaz de preparar.
+#[derive(Debug)]
+pub struct RobotAvailable {
+    pub capabilities: Capabilities,
+}
+
+impl RobotAvailable {
+    pub fn new(capabilities: Capabilities) -> Self {
+        RobotAvailable { capabilities }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf_msg = vec![Messages::RobotAvailable as u8];
+        buf_msg.extend(self.capabilities.as_bytes());
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<RobotAvailable, ParseError> {
+        let capabilities = Capabilities::from_bytes(buffer)?;
+        Ok(RobotAvailable { capabilities })
+    }
+}
+
+/// Respuesta del robot al `NewLeader`: lo que es capaz de preparar, los pedidos que tiene
+/// encolados y los resultados de los últimos pedidos que ya preparó.
 #[derive(Debug)]
 pub struct RobotWithOrder {
-    pub screen_id: u8,
-    pub order_id: u8,
+    pub capabilities: Capabilities,
+    pub orders: Vec<(u8, u8)>,
+    pub executed: Vec<OrderResult>,
 }
 
 impl RobotWithOrder {
-    pub fn new(screen_id: u8, order_id: u8) -> Self {
+    pub fn new(
+        capabilities: Capabilities,
+        orders: Vec<(u8, u8)>,
+        executed: Vec<OrderResult>,
+    ) -> Self {
         RobotWithOrder {
+            capabilities,
+            orders,
+            executed,
+        }
+    }
+
+    pub fn as_bytes(self) -> Vec<u8> {
+        let mut buf_msg = vec![Messages::RobotWithOrder as u8];
+        buf_msg.extend(self.capabilities.as_bytes());
+        buf_msg.push(self.orders.len() as u8);
+        for (screen_id, order_id) in self.orders {
+            buf_msg.push(screen_id);
+            buf_msg.push(order_id);
+        }
+        buf_msg.push(self.executed.len() as u8);
+        for result in self.executed {
+            // el resultado sin el tipo de mensaje
+            buf_msg.extend_from_slice(&result.as_bytes()[1..]);
+        }
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<RobotWithOrder, ParseError> {
+        let capabilities = Capabilities::from_bytes(buffer)?;
+        let mut pos = capabilities.encoded_len();
+        let len_orders = buffer[pos] as usize;
+        let mut orders = Vec::new();
+        for i in 0..len_orders {
+            let screen_id = buffer[pos + 1 + 2 * i];
+            let order_id = buffer[pos + 2 + 2 * i];
+            orders.push((screen_id, order_id));
+        }
+        pos += 1 + 2 * len_orders;
+        let len_executed = buffer[pos] as usize;
+        let mut executed = Vec::new();
+        for i in 0..len_executed {
+            let start = pos + 1 + 4 * i;
+            executed.push(OrderResult::from_bytes(&buffer[start..start + 4])?);
+        }
+        Ok(RobotWithOrder {
+            capabilities,
+            orders,
+            executed,
+        })
+    }
+}
+
+/// Respuesta del robot a un `RobotOrder`: indica si el pedido entró en su cola y
+/// cuántos pedidos tiene encolados en ese momento.
+#[derive(Debug)]
+pub struct RobotQueueStatus {
+    pub owner: u8,
+    pub screen_id: u8,
+    pub order_id: u8,
+    pub accepted: bool,
+    pub depth: u8,
+}
+
+impl RobotQueueStatus {
+    pub fn new(owner: u8, screen_id: u8, order_id: u8, accepted: bool, depth: u8) -> Self {
+        RobotQueueStatus {
+            owner,
             screen_id,
             order_id,
+            accepted,
+            depth,
         }
     }
 
     pub fn as_bytes(self) -> Vec<u8> {
         let buf_msg = vec![
-            Messages::RobotWithOrder as u8,
+            Messages::RobotQueueStatus as u8,
+            self.owner,
             self.screen_id,
             self.order_id,
+            self.accepted as u8,
+            self.depth,
         ];
         buf_msg
     }
 
-    pub fn from_bytes(buffer: &[u8]) -> RobotWithOrder {
-        let screen_id = buffer[0];
-        let order_id = buffer[1];
-        RobotWithOrder {
-            screen_id,
-            order_id,
+    pub fn from_bytes(buffer: &[u8]) -> RobotQueueStatus {
+        RobotQueueStatus {
+            owner: buffer[0],
+            screen_id: buffer[1],
+            order_id: buffer[2],
+            accepted: buffer[3] != 0,
+            depth: buffer[4],
         }
     }
 }
@@ -532,27 +830,771 @@ impl RobotWithOrder {
 #[derive(Debug)]
 pub struct NewLeader {
     pub owner: u8,
+    pub leader: u8,
+    pub term: Term,
 }
 
 impl NewLeader {
+    pub fn new(owner: u8, leader: u8, term: Term) -> Self {
+        NewLeader {
+            owner,
+            leader,
+            term,
+        }
+    }
+
+    pub fn as_bytes(self) -> Vec<u8> {
+        let mut buf_msg = vec![Messages::NewLeader as u8, self.owner, self.leader];
+        buf_msg.extend_from_slice(&self.term.to_be_bytes());
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<NewLeader, ParseError> {
+        let owner = buffer[0];
+        let leader = buffer[1];
+        let term = u32_at(buffer, 2)?;
+        Ok(NewLeader {
+            owner,
+            leader,
+            term,
+        })
+    }
+}
+
+/// Respuesta a un mensaje de un mandato viejo: le avisa a la líder que lo mandó cuál es el
+/// mandato actual, para que deje de coordinar.
+#[derive(Debug, Clone, Copy)]
+pub struct StaleTerm {
+    pub owner: u8,
+    pub term: Term,
+}
+
+impl StaleTerm {
+    pub fn new(owner: u8, term: Term) -> Self {
+        StaleTerm { owner, term }
+    }
+
+    pub fn as_bytes(self) -> Vec<u8> {
+        let mut buf_msg = vec![Messages::StaleTerm as u8, self.owner];
+        buf_msg.extend_from_slice(&self.term.to_be_bytes());
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<StaleTerm, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        let term = u32_at(buffer, 1)?;
+        Ok(StaleTerm { owner, term })
+    }
+}
+
+/// Latido que el LeaderReceiver de la líder les manda a las demás pantallas, haya o no
+/// pedidos en curso.
+#[derive(Debug, Clone, Copy)]
+pub struct LeaderHeartbeat {
+    pub owner: u8,
+    pub term: Term,
+}
+
+impl LeaderHeartbeat {
+    pub fn new(owner: u8, term: Term) -> Self {
+        LeaderHeartbeat { owner, term }
+    }
+
+    pub fn as_bytes(self) -> Vec<u8> {
+        let mut buf_msg = vec![Messages::LeaderHeartbeat as u8, self.owner];
+        buf_msg.extend_from_slice(&self.term.to_be_bytes());
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<LeaderHeartbeat, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        let term = u32_at(buffer, 1)?;
+        Ok(LeaderHeartbeat { owner, term })
+    }
+}
+
+// ================================== Miembros ================================== //
+
+/// Una pantalla se anuncia a la líder para sumarse a las elecciones.
+#[derive(Debug, Clone, Copy)]
+pub struct ScreenJoin {
+    pub owner: u8,
+}
+
+impl ScreenJoin {
+    pub fn new(owner: u8) -> Self {
+        ScreenJoin { owner }
+    }
+
+    pub fn as_bytes(self) -> Vec<u8> {
+        vec![Messages::ScreenJoin as u8, self.owner]
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<ScreenJoin, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        Ok(ScreenJoin { owner })
+    }
+}
+
+/// Las pantallas que conoce la líder del mandato, incluida ella. Se lo manda a todas cada
+/// vez que alguna entra o sale.
+#[derive(Debug, Clone)]
+pub struct Membership {
+    pub owner: u8,
+    pub term: Term,
+    pub members: Vec<u8>,
+}
+
+impl Membership {
+    pub fn new(owner: u8, term: Term, members: Vec<u8>) -> Self {
+        Membership {
+            owner,
+            term,
+            members,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf_msg = vec![Messages::Membership as u8, self.owner];
+        buf_msg.extend_from_slice(&self.term.to_be_bytes());
+        buf_msg.push(self.members.len() as u8);
+        buf_msg.extend_from_slice(&self.members);
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<Membership, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        let term = u32_at(buffer, 1)?;
+        let len = *buffer.get(5).ok_or(ParseError::ConversionError)? as usize;
+        let members = buffer
+            .get(6..6 + len)
+            .ok_or(ParseError::ConversionError)?
+            .to_vec();
+        Ok(Membership {
+            owner,
+            term,
+            members,
+        })
+    }
+}
+
+/// Una pantalla que se va avisa a la líder para que no la espere en las elecciones.
+#[derive(Debug, Clone, Copy)]
+pub struct ScreenLeave {
+    pub owner: u8,
+}
+
+impl ScreenLeave {
+    pub fn new(owner: u8) -> Self {
+        ScreenLeave { owner }
+    }
+
+    pub fn as_bytes(self) -> Vec<u8> {
+        vec![Messages::ScreenLeave as u8, self.owner]
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<ScreenLeave, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        Ok(ScreenLeave { owner })
+    }
+}
+
+/// Una pantalla que arranca pregunta quién es la líder. Solo la líder contesta, con su
+/// COORDINATOR; las demás le pasan la pregunta a su líder una sola vez.
+#[derive(Debug, Clone, Copy)]
+pub struct LeaderQuery {
+    pub owner: u8,
+    pub forwarded: bool,
+}
+
+impl LeaderQuery {
+    pub fn new(owner: u8) -> Self {
+        LeaderQuery {
+            owner,
+            forwarded: false,
+        }
+    }
+
+    pub fn as_bytes(self) -> Vec<u8> {
+        vec![
+            Messages::LeaderQuery as u8,
+            self.owner,
+            self.forwarded as u8,
+        ]
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<LeaderQuery, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        let forwarded = buffer.get(1).is_some_and(|forwarded| *forwarded != 0);
+        Ok(LeaderQuery { owner, forwarded })
+    }
+}
+
+// ================================== Elección Raft ================================== //
+
+/// Una pantalla candidata pide el voto para el mandato, con el último índice y mandato de
+/// su log para que solo la voten si está al día.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestVote {
+    pub owner: u8,
+    pub term: Term,
+    pub last_log_index: u32,
+    pub last_log_term: Term,
+}
+
+impl RequestVote {
+    pub fn as_bytes(self) -> Vec<u8> {
+        let mut buf_msg = vec![Messages::RequestVote as u8, self.owner];
+        buf_msg.extend_from_slice(&self.term.to_be_bytes());
+        buf_msg.extend_from_slice(&self.last_log_index.to_be_bytes());
+        buf_msg.extend_from_slice(&self.last_log_term.to_be_bytes());
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<RequestVote, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        Ok(RequestVote {
+            owner,
+            term: u32_at(buffer, 1)?,
+            last_log_index: u32_at(buffer, 5)?,
+            last_log_term: u32_at(buffer, 9)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Vote {
+    pub owner: u8,
+    pub term: Term,
+    pub granted: bool,
+}
+
+impl Vote {
+    pub fn as_bytes(self) -> Vec<u8> {
+        let mut buf_msg = vec![Messages::Vote as u8, self.owner];
+        buf_msg.extend_from_slice(&self.term.to_be_bytes());
+        buf_msg.push(self.granted as u8);
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<Vote, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        let term = u32_at(buffer, 1)?;
+        let granted = *buffer.get(5).ok_or(ParseError::ConversionError)? == 1;
+        Ok(Vote {
+            owner,
+            term,
+            granted,
+        })
+    }
+}
+
+/// La líder le manda a cada pantalla las entradas del log que le faltan, a partir de la
+/// que sigue a `prev_log_index`. Sin entradas sirve de latido.
+#[derive(Debug, Clone)]
+pub struct AppendEntries {
+    pub owner: u8,
+    pub term: Term,
+    pub prev_log_index: u32,
+    pub prev_log_term: Term,
+    pub leader_commit: u32,
+    pub entries: Vec<LogEntry>,
+}
+
+impl AppendEntries {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf_msg = vec![Messages::AppendEntries as u8, self.owner];
+        buf_msg.extend_from_slice(&self.term.to_be_bytes());
+        buf_msg.extend_from_slice(&self.prev_log_index.to_be_bytes());
+        buf_msg.extend_from_slice(&self.prev_log_term.to_be_bytes());
+        buf_msg.extend_from_slice(&self.leader_commit.to_be_bytes());
+        buf_msg.push(self.entries.len() as u8);
+        for entry in self.entries.iter() {
+            buf_msg.extend_from_slice(&entry.as_bytes());
+        }
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<AppendEntries, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        let term = u32_at(buffer, 1)?;
+        let prev_log_index = u32_at(buffer, 5)?;
+        let prev_log_term = u32_at(buffer, 9)?;
+        let leader_commit = u32_at(buffer, 13)?;
+        let len_entries = *buffer.get(17).ok_or(ParseError::ConversionError)? as usize;
+        let mut offset = 18;
+        let mut entries = Vec::new();
+        for _ in 0..len_entries {
+            let entry = LogEntry::from_bytes(buffer.get(offset..).unwrap_or_default())?;
+            offset += entry.encoded_len();
+            entries.push(entry);
+        }
+        Ok(AppendEntries {
+            owner,
+            term,
+            prev_log_index,
+            prev_log_term,
+            leader_commit,
+            entries,
+        })
+    }
+}
+
+/// Respuesta a un AppendEntries. Si se aceptó, `match_index` es hasta dónde coincide el
+/// log de la pantalla con el de la líder; si no, `conflict_index` es desde qué entrada
+/// tiene que volver a mandarle la líder.
+#[derive(Debug, Clone, Copy)]
+pub struct AppendReply {
+    pub owner: u8,
+    pub term: Term,
+    pub success: bool,
+    pub match_index: u32,
+    pub conflict_index: u32,
+}
+
+impl AppendReply {
+    pub fn as_bytes(self) -> Vec<u8> {
+        let mut buf_msg = vec![Messages::AppendReply as u8, self.owner];
+        buf_msg.extend_from_slice(&self.term.to_be_bytes());
+        buf_msg.push(self.success as u8);
+        buf_msg.extend_from_slice(&self.match_index.to_be_bytes());
+        buf_msg.extend_from_slice(&self.conflict_index.to_be_bytes());
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<AppendReply, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        let term = u32_at(buffer, 1)?;
+        let success = *buffer.get(5).ok_or(ParseError::ConversionError)? == 1;
+        let match_index = u32_at(buffer, 6)?;
+        let conflict_index = u32_at(buffer, 10)?;
+        Ok(AppendReply {
+            owner,
+            term,
+            success,
+            match_index,
+            conflict_index,
+        })
+    }
+}
+
+/// La líder le manda su snapshot a una pantalla a la que le faltan entradas que ya compactó.
+/// Va en pedazos numerados por `seq`; `done` marca el último. Se responde con un
+/// AppendReply, con `match_index` en `last_index` una vez que llegó entero.
+#[derive(Debug, Clone)]
+pub struct InstallSnapshot {
+    pub owner: u8,
+    pub term: Term,
+    pub last_index: u32,
+    pub last_term: Term,
+    pub seq: u32,
+    pub done: bool,
+    pub commands: Vec<OrderCommand>,
+}
+
+impl InstallSnapshot {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf_msg = vec![Messages::InstallSnapshot as u8, self.owner];
+        buf_msg.extend_from_slice(&self.term.to_be_bytes());
+        buf_msg.extend_from_slice(&self.last_index.to_be_bytes());
+        buf_msg.extend_from_slice(&self.last_term.to_be_bytes());
+        buf_msg.extend_from_slice(&self.seq.to_be_bytes());
+        buf_msg.push(self.done as u8);
+        buf_msg.push(self.commands.len() as u8);
+        for command in self.commands.iter() {
+            buf_msg.extend_from_slice(&command.as_bytes());
+        }
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<InstallSnapshot, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        let term = u32_at(buffer, 1)?;
+        let last_index = u32_at(buffer, 5)?;
+        let last_term = u32_at(buffer, 9)?;
+        let seq = u32_at(buffer, 13)?;
+        let done = *buffer.get(17).ok_or(ParseError::ConversionError)? == 1;
+        let len_commands = *buffer.get(18).ok_or(ParseError::ConversionError)? as usize;
+        let mut offset = 19;
+        let mut commands = Vec::new();
+        for _ in 0..len_commands {
+            let command = OrderCommand::from_bytes(buffer.get(offset..).unwrap_or_default())?;
+            offset += command.encoded_len();
+            commands.push(command);
+        }
+        Ok(InstallSnapshot {
+            owner,
+            term,
+            last_index,
+            last_term,
+            seq,
+            done,
+            commands,
+        })
+    }
+}
+
+/// Cambios en la cola del coordinador que la líder le manda a su pantalla de respaldo. Cada
+/// envío lleva el siguiente número de secuencia; si el respaldo ve un salto pide la cola
+/// entera. `reset` marca el primer envío de la cola entera, que reemplaza a la copia
+/// anterior. Sin cambios, un envío vacío sirve de latido.
+#[derive(Debug, Clone)]
+pub struct QueueUpdate {
+    pub owner: u8,
+    pub term: Term,
+    pub seq: u32,
+    pub reset: bool,
+    pub commands: Vec<OrderCommand>,
+}
+
+impl QueueUpdate {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf_msg = vec![Messages::QueueUpdate as u8, self.owner];
+        buf_msg.extend_from_slice(&self.term.to_be_bytes());
+        buf_msg.extend_from_slice(&self.seq.to_be_bytes());
+        buf_msg.push(self.reset as u8);
+        buf_msg.push(self.commands.len() as u8);
+        for command in self.commands.iter() {
+            buf_msg.extend_from_slice(&command.as_bytes());
+        }
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<QueueUpdate, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        let term = u32_at(buffer, 1)?;
+        let seq = u32_at(buffer, 5)?;
+        let reset = *buffer.get(9).ok_or(ParseError::ConversionError)? == 1;
+        let len_commands = *buffer.get(10).ok_or(ParseError::ConversionError)? as usize;
+        let mut offset = 11;
+        let mut commands = Vec::new();
+        for _ in 0..len_commands {
+            let command = OrderCommand::from_bytes(buffer.get(offset..).unwrap_or_default())?;
+            offset += command.encoded_len();
+            commands.push(command);
+        }
+        Ok(QueueUpdate {
+            owner,
+            term,
+            seq,
+            reset,
+            commands,
+        })
+    }
+}
+
+/// Pedido del respaldo a la líder para que le vuelva a mandar la cola entera.
+#[derive(Debug, Clone, Copy)]
+pub struct QueueResync {
+    pub owner: u8,
+}
+
+impl QueueResync {
+    pub fn new(owner: u8) -> Self {
+        QueueResync { owner }
+    }
+
+    pub fn as_bytes(self) -> Vec<u8> {
+        vec![Messages::QueueResync as u8, self.owner]
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<QueueResync, ParseError> {
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        Ok(QueueResync { owner })
+    }
+}
+
+/// Aviso de un robot a la pantalla líder de que se está drenando y no debe recibir más
+/// pedidos.
+pub struct RobotDraining {
+    pub owner: u8,
+}
+
+impl RobotDraining {
     pub fn new(owner: u8) -> Self {
-        NewLeader { owner }
+        RobotDraining { owner }
+    }
+
+    pub fn as_bytes(self) -> Vec<u8> {
+        let buf_msg = vec![Messages::RobotDraining as u8, self.owner];
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> RobotDraining {
+        let owner = buffer[0];
+        RobotDraining { owner }
+    }
+}
+
+/// Pedido de un cliente de cancelar su pedido antes de que se empiece a preparar. Lo envía la
+/// pantalla a la líder, y la líder al robot que lo tiene encolado.
+#[derive(Debug, Clone, Copy)]
+pub struct CancelOrder {
+    pub screen_id: u8,
+    pub order_id: u8,
+}
+
+impl CancelOrder {
+    pub fn new(screen_id: u8, order_id: u8) -> Self {
+        CancelOrder {
+            screen_id,
+            order_id,
+        }
+    }
+
+    pub fn as_bytes(self) -> Vec<u8> {
+        let buf_msg = vec![Messages::CancelOrder as u8, self.screen_id, self.order_id];
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> CancelOrder {
+        CancelOrder {
+            screen_id: buffer[0],
+            order_id: buffer[1],
+        }
+    }
+}
+
+/// Aviso de un robot que sale del anillo a su anterior, indicándole quién pasa a ser su
+/// siguiente.
+pub struct Leave {
+    pub owner: u8,
+    pub next: u8,
+}
+
+impl Leave {
+    pub fn new(owner: u8, next: u8) -> Self {
+        Leave { owner, next }
+    }
+
+    pub fn as_bytes(self) -> Vec<u8> {
+        let buf_msg = vec![Messages::Leave as u8, self.owner, self.next];
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Leave {
+        let owner = buffer[0];
+        let next = buffer[1];
+        Leave { owner, next }
+    }
+}
+
+/// Respuesta del robot a un DRAIN: si lo acepta, o si lo rechaza porque no conoce a la
+/// pantalla líder a la que tiene que avisarle.
+#[derive(Debug)]
+pub struct DrainReply {
+    pub owner: u8,
+    pub accepted: bool,
+}
+
+impl DrainReply {
+    pub fn new(owner: u8, accepted: bool) -> Self {
+        DrainReply { owner, accepted }
     }
 
     pub fn as_bytes(self) -> Vec<u8> {
-        let buf_msg = vec![Messages::NewLeader as u8, self.owner];
+        vec![Messages::DrainReply as u8, self.owner, self.accepted as u8]
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<DrainReply, ParseError> {
+        match buffer {
+            [owner, accepted, ..] => Ok(DrainReply {
+                owner: *owner,
+                accepted: *accepted != 0,
+            }),
+            _ => Err(ParseError::ConversionError),
+        }
+    }
+}
+
+/// Recorre el anillo en un arranque en frío juntando el snapshot más fresco de cada gusto,
+/// hasta volver al robot que lo inició.
+#[derive(Debug)]
+pub struct SnapshotCollect {
+    pub owner: u8,
+    pub stock: Vec<(IceCream, FlavorSnapshot)>,
+}
+
+impl SnapshotCollect {
+    pub fn new(owner: u8, stock: Vec<(IceCream, FlavorSnapshot)>) -> Self {
+        SnapshotCollect { owner, stock }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf_msg = vec![
+            Messages::SnapshotCollect as u8,
+            self.owner,
+            self.stock.len() as u8,
+        ];
+        for (ice_cream, flavor) in self.stock.iter() {
+            buf_msg.push(*ice_cream as u8);
+            buf_msg.extend_from_slice(&flavor.amount.to_be_bytes());
+        }
         buf_msg
     }
 
-    pub fn from_bytes(buffer: &[u8]) -> NewLeader {
+    pub fn from_bytes(buffer: &[u8]) -> Result<SnapshotCollect, ParseError> {
         let owner = buffer[0];
-        NewLeader { owner }
+        let len = buffer[1] as usize;
+        let mut stock = Vec::with_capacity(len);
+        let mut pos = 2;
+        for _ in 0..len {
+            let ice_cream = IceCream::try_from(buffer[pos])?;
+            let amount = Grams::from_be_bytes(buffer[pos + 1..pos + 5].try_into()?);
+            stock.push((ice_cream, FlavorSnapshot { amount }));
+            pos += 5;
+        }
+        Ok(SnapshotCollect { owner, stock })
+    }
+}
+
+/// Avance de un pedido: todos los gustos que el robot ya descontó del stock hasta el
+/// momento. Es acumulativo, así que perder uno no hace falta reenviarlo.
+#[derive(Debug)]
+pub struct OrderProgress {
+    pub owner: u8,
+    pub screen_id: u8,
+    pub order_id: u8,
+    pub served: Vec<(IceCream, Grams)>,
+}
+
+impl OrderProgress {
+    pub fn new(owner: u8, screen_id: u8, order_id: u8, served: Vec<(IceCream, Grams)>) -> Self {
+        OrderProgress {
+            owner,
+            screen_id,
+            order_id,
+            served,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf_msg = vec![
+            Messages::OrderProgress as u8,
+            self.owner,
+            self.screen_id,
+            self.order_id,
+            self.served.len() as u8,
+        ];
+        for (ice_cream, amount) in self.served.iter() {
+            buf_msg.push(*ice_cream as u8);
+            buf_msg.extend_from_slice(&amount.to_be_bytes());
+        }
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<OrderProgress, ParseError> {
+        let owner = buffer[0];
+        let screen_id = buffer[1];
+        let order_id = buffer[2];
+        let len = buffer[3] as usize;
+        let mut served = Vec::with_capacity(len);
+        for i in 0..len {
+            let start = 4 + 5 * i;
+            let ice_cream = IceCream::try_from(buffer[start])?;
+            let amount = Grams::from_be_bytes(buffer[start + 1..start + 5].try_into()?);
+            served.push((ice_cream, amount));
+        }
+        Ok(OrderProgress {
+            owner,
+            screen_id,
+            order_id,
+            served,
+        })
+    }
+}
+
+/// Estado de un robot para los operadores: pedido actual y cola, stock conocido de cada
+/// gusto, posición en el anillo, tiempo en funcionamiento y pedidos completados.
+#[derive(Debug)]
+pub struct StatusReply {
+    pub owner: u8,
+    pub uptime_secs: u64,
+    pub orders_completed: u32,
+    pub prev: u8,
+    pub next: u8,
+    pub ring_path: Vec<u8>,
+    pub stock: Vec<(IceCream, Grams, Grade)>,
+    pub orders: Vec<Order>,
+}
+
+impl StatusReply {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buf_msg = vec![Messages::StatusReply as u8, self.owner];
+        buf_msg.extend_from_slice(&self.uptime_secs.to_be_bytes());
+        buf_msg.extend_from_slice(&self.orders_completed.to_be_bytes());
+        buf_msg.push(self.prev);
+        buf_msg.push(self.next);
+        buf_msg.push(self.ring_path.len() as u8);
+        buf_msg.extend_from_slice(&self.ring_path);
+        buf_msg.push(self.stock.len() as u8);
+        for (ice_cream, amount, grade) in self.stock.iter() {
+            buf_msg.push(*ice_cream as u8);
+            buf_msg.extend_from_slice(&amount.to_be_bytes());
+            buf_msg.push(grade.clone() as u8);
+        }
+        buf_msg.push(self.orders.len() as u8);
+        for order in self.orders.iter() {
+            buf_msg.extend_from_slice(&order.as_bytes()[1..]);
+        }
+        buf_msg
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<StatusReply, ParseError> {
+        let byte = |i: usize| buffer.get(i).copied().ok_or(ParseError::ConversionError);
+        let owner = *buffer.first().ok_or(ParseError::EmptyPacket)?;
+        let uptime_secs = buffer.get(1..9).ok_or(ParseError::ConversionError)?;
+        let uptime_secs = u64::from_be_bytes(uptime_secs.try_into()?);
+        let orders_completed = u32_at(buffer, 9)?;
+        let prev = byte(13)?;
+        let next = byte(14)?;
+        let len_path = byte(15)? as usize;
+        let ring_path = buffer
+            .get(16..16 + len_path)
+            .ok_or(ParseError::ConversionError)?
+            .to_vec();
+        let mut offset = 16 + len_path;
+
+        let len_stock = byte(offset)? as usize;
+        offset += 1;
+        let mut stock = Vec::new();
+        for _ in 0..len_stock {
+            let ice_cream = IceCream::try_from(byte(offset)?)?;
+            let amount = buffer
+                .get(offset + 1..offset + 5)
+                .ok_or(ParseError::ConversionError)?;
+            let amount = Grams::from_be_bytes(amount.try_into()?);
+            let grade = Grade::try_from(byte(offset + 5)?)?;
+            stock.push((ice_cream, amount, grade));
+            offset += 6;
+        }
+
+        let len_orders = byte(offset)? as usize;
+        offset += 1;
+        let mut orders = Vec::new();
+        for _ in 0..len_orders {
+            let order =
+                Order::try_from_bytes(buffer.get(offset..).ok_or(ParseError::ConversionError)?)?;
+            offset += order.encoded_len();
+            orders.push(order);
+        }
+
+        Ok(StatusReply {
+            owner,
+            uptime_secs,
+            orders_completed,
+            prev,
+            next,
+            ring_path,
+            stock,
+            orders,
+        })
     }
 }
 
 // ====================================== Gateway ============================================== //
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GatewayResponse {
     Acknowledge = 0,
     RejectedCard = 1,
@@ -574,37 +1616,112 @@ impl TryFrom<u8> for GatewayResponse {
     }
 }
 
-impl From<GatewayResponse> for Vec<u8> {
-    fn from(value: GatewayResponse) -> Self {
-        match value {
-            GatewayResponse::Acknowledge => {
-                let bytes = vec![
-                    Messages::GatewayResponse as u8,
-                    GatewayResponse::Acknowledge as u8,
-                ];
-                bytes
-            }
-            GatewayResponse::RejectedCard => {
-                let bytes = vec![
-                    Messages::GatewayResponse as u8,
-                    GatewayResponse::RejectedCard as u8,
-                ];
-                bytes
-            }
-            GatewayResponse::NoSuchPendingPayment => {
-                let bytes = vec![
-                    Messages::GatewayResponse as u8,
-                    GatewayResponse::NoSuchPendingPayment as u8,
-                ];
-                bytes
-            }
-            GatewayResponse::DuplicatedOrder => {
-                let bytes = vec![
-                    Messages::GatewayResponse as u8,
-                    GatewayResponse::DuplicatedOrder as u8,
-                ];
-                bytes
-            }
+/// Respuesta del gateway a la pantalla. Lleva el id del pedido porque una pantalla puede
+/// tener varios pedidos cobrándose a la vez.
+#[derive(Debug, Clone, Copy)]
+pub struct GatewayReply {
+    pub order_id: u8,
+    pub response: GatewayResponse,
+}
+
+impl GatewayReply {
+    pub fn new(order_id: u8, response: GatewayResponse) -> Self {
+        GatewayReply { order_id, response }
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<GatewayReply, ParseError> {
+        if buffer.len() < 2 {
+            return Err(ParseError::ConversionError);
+        }
+        Ok(GatewayReply {
+            response: GatewayResponse::try_from(buffer[0])?,
+            order_id: buffer[1],
+        })
+    }
+}
+
+impl From<GatewayReply> for Vec<u8> {
+    fn from(value: GatewayReply) -> Self {
+        let bytes = vec![
+            Messages::GatewayResponse as u8,
+            value.response as u8,
+            value.order_id,
+        ];
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order() -> Order {
+        Order::new(
+            1,
+            7,
+            HashMap::from([(IceCream::Vanilla, 500), (IceCream::Lemon, 250)]),
+            Container::Cone,
+            Priority::Express,
+            1_700_000_000_000,
+            3,
+        )
+    }
+
+    fn status_reply() -> StatusReply {
+        StatusReply {
+            owner: 2,
+            uptime_secs: 90,
+            orders_completed: 4,
+            prev: 1,
+            next: 0,
+            ring_path: vec![0, 1, 2],
+            stock: vec![(IceCream::Vanilla, 9_500, Grade::B)],
+            orders: vec![order(), order()],
+        }
+    }
+
+    #[test]
+    fn order_roundtrips() {
+        let bytes = order().as_bytes();
+        let parsed = Order::try_from_bytes(&bytes[1..]).expect("la orden es valida");
+        assert_eq!(parsed.items, order().items);
+        assert_eq!(parsed.term, 3);
+    }
+
+    #[test]
+    fn truncated_order_is_an_error() {
+        let bytes = order().as_bytes();
+        for len in 1..bytes.len() {
+            assert!(
+                Order::try_from_bytes(&bytes[1..len]).is_err(),
+                "largo {len}"
+            );
+        }
+    }
+
+    #[test]
+    fn order_with_unknown_flavor_is_an_error() {
+        let mut bytes = order().as_bytes();
+        bytes[4] = 99;
+        assert!(Order::try_from_bytes(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn status_reply_roundtrips() {
+        let bytes = status_reply().as_bytes();
+        let parsed = StatusReply::from_bytes(&bytes[1..]).expect("la respuesta es valida");
+        assert_eq!(parsed.ring_path, vec![0, 1, 2]);
+        assert_eq!(parsed.orders.len(), 2);
+    }
+
+    #[test]
+    fn truncated_status_reply_is_an_error() {
+        let bytes = status_reply().as_bytes();
+        for len in 1..bytes.len() {
+            assert!(
+                StatusReply::from_bytes(&bytes[1..len]).is_err(),
+                "largo {len}"
+            );
         }
     }
 }
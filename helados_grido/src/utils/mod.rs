@@ -1,3 +1,6 @@
 pub mod addresses;
+pub mod clock;
 pub mod errors;
+pub mod failure_detector;
 pub mod messages;
+pub mod persistence;
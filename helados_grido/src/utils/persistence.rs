@@ -0,0 +1,32 @@
+use log::warn;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fs, io, path::Path};
+
+/// Lee un archivo JSON guardado con `save_json`. Si no existe devuelve `None`, y si está
+/// corrupto lo avisa con el prefijo de log de quien lo lee y también devuelve `None`, para
+/// que arranque de cero.
+pub fn load_json<T: DeserializeOwned>(path: &Path, owner: &str) -> Option<T> {
+    let content = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(value) => Some(value),
+        Err(error) => {
+            warn!(
+                "{} El archivo {:?} esta corrupto, lo ignoro: {}",
+                owner, path, error
+            );
+            None
+        }
+    }
+}
+
+/// Escribe el valor en un archivo temporal y lo renombra, para que un corte a mitad de la
+/// escritura no deje el archivo a medias.
+pub fn save_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("json.tmp");
+    let content = serde_json::to_string_pretty(value).map_err(io::Error::from)?;
+    fs::write(&tmp_path, content)?;
+    fs::rename(tmp_path, path)
+}